@@ -1,21 +1,30 @@
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 
-use borsh::BorshDeserialize;
-use program::Escrow;
-use solana_client::rpc_client::RpcClient;
+use borsh::{BorshDeserialize, BorshSerialize};
+use program::{
+    Bundle, Config, Custody, DutchAuction, Escrow, Market, NoncePage, Rfq, SealedAuction,
+    SealedBid, Side, SignedOrder, UnrevealedPolicy,
+};
+use solana_client::{rpc_client::RpcClient, rpc_request::TokenAccountsFilter};
 use solana_sdk::{
+    account::from_account,
     borsh::get_packed_len,
+    clock::Clock,
     commitment_config::{CommitmentConfig, CommitmentLevel},
     instruction::{AccountMeta, Instruction},
     native_token::LAMPORTS_PER_SOL,
-    program_pack::Pack,
+    program_option::COption,
     pubkey::Pubkey,
-    signature::Keypair,
+    signature::{Keypair, Signature},
     signer::keypair::read_keypair_file,
     signer::Signer,
     transaction::Transaction,
 };
-use spl_associated_token_account::get_associated_token_address;
+use spl_token_2022::{
+    extension::StateWithExtensions,
+    state::{Account as TokenAccount, Mint},
+};
 use structopt::StructOpt;
 
 fn main() -> Result<(), Error> {
@@ -23,20 +32,67 @@ fn main() -> Result<(), Error> {
         RpcClient::new_with_commitment("http://localhost:8899", CommitmentConfig::confirmed());
     match Command::from_args() {
         Command::Create(create) => do_create_fee_account(&client, &create),
+        Command::Config(ConfigCommand::Init(init)) => do_init_config(&client, &init),
+        Command::Config(ConfigCommand::Update(update)) => do_update_config(&client, &update),
+        Command::Config(ConfigCommand::Show) => do_show_config(&client),
+        Command::Fees(FeesCommand::Withdraw(withdraw)) => do_withdraw_fees(&client, &withdraw),
+        Command::Fees(FeesCommand::Balance(balance)) => do_fees_balance(&client, &balance),
         Command::Post(post) => do_post(&client, &post),
+        Command::Show(show) => do_show(&client, &show),
         Command::Take(take) => do_take(&client, &take),
         Command::Cancel(cancel) => do_cancel(&client, &cancel),
+        Command::Amend(amend) => do_amend(&client, &amend),
+        Command::Crank(crank) => do_crank(&client, &crank),
+        Command::Match(matcher) => do_match(&client, &matcher),
+        Command::Market(MarketCommand::Create(create)) => do_create_market(&client, &create),
+        Command::Market(MarketCommand::Show(show)) => do_show_market(&client, &show),
+        Command::Market(MarketCommand::Place(place)) => do_place_order(&client, &place),
+        Command::Market(MarketCommand::Cancel(cancel)) => do_cancel_order(&client, &cancel),
+        Command::Market(MarketCommand::Match(matcher)) => do_match_orders(&client, &matcher),
+        Command::Bid(bid) => do_bid(&client, &bid),
+        Command::Settle(settle) => do_settle(&client, &settle),
+        Command::Reveal(reveal) => do_reveal(&client, &reveal),
+        Command::Claim(claim) => do_claim(&client, &claim),
+        Command::Option(OptionCommand::Write(write)) => do_write_option(&client, &write),
+        Command::Option(OptionCommand::Buy(buy)) => do_buy_option(&client, &buy),
+        Command::Option(OptionCommand::Exercise(exercise)) => do_exercise(&client, &exercise),
+        Command::Order(OrderCommand::Approve(approve)) => do_approve_orders(&client, &approve),
+        Command::Order(OrderCommand::Sign(sign)) => do_sign_order(&client, &sign),
+        Command::Order(OrderCommand::Show(show)) => do_show_order(&client, &show),
+        Command::Order(OrderCommand::Fill(fill)) => do_fill_order(&client, &fill),
+        Command::Order(OrderCommand::Cancel(cancel)) => do_cancel_signed_order(&client, &cancel),
+        Command::Rfq(RfqCommand::Create(create)) => do_create_rfq(&client, &create),
+        Command::Rfq(RfqCommand::Show(show)) => do_show_rfq(&client, &show),
+        Command::Rfq(RfqCommand::Quote(quote)) => do_post_quote(&client, &quote),
+        Command::Rfq(RfqCommand::Accept(accept)) => do_accept_quote(&client, &accept),
+        Command::Rfq(RfqCommand::Cancel(cancel)) => do_cancel_rfq(&client, &cancel),
     }
 }
 
 type Error = Box<dyn std::error::Error>;
 
+// parsed once at startup, so there is nothing to gain from boxing the larger commands
+#[allow(clippy::large_enum_variant)]
 #[derive(StructOpt)]
 enum Command {
     Create(Create),
+    Config(ConfigCommand),
+    Fees(FeesCommand),
     Post(Post),
+    Show(Show),
     Take(Take),
     Cancel(Cancel),
+    Amend(Amend),
+    Crank(Crank),
+    Match(Match),
+    Market(MarketCommand),
+    Bid(Bid),
+    Settle(Settle),
+    Reveal(Reveal),
+    Claim(Claim),
+    Option(OptionCommand),
+    Order(OrderCommand),
+    Rfq(RfqCommand),
 }
 
 #[derive(StructOpt)]
@@ -46,20 +102,408 @@ struct Create {
 }
 
 #[derive(StructOpt)]
-struct Post {
+enum ConfigCommand {
+    Init(ConfigInit),
+    Update(ConfigUpdate),
+    Show,
+}
+
+#[derive(StructOpt)]
+struct ConfigInit {
+    /// The program's upgrade authority, which becomes the config's administrator
     #[structopt(parse(try_from_str = read_keypair_file))]
-    poster: Keypair,
+    administrator: Keypair,
+    fee_recipient: Pubkey,
+    /// Fee in lamports charged to post a trade
+    fee: u64,
+    /// Fee in basis points taken from the tokens a poster receives when their trade is taken
+    #[structopt(long, default_value = "0")]
+    fee_bps: u16,
+}
+
+#[derive(StructOpt)]
+struct ConfigUpdate {
+    #[structopt(parse(try_from_str = read_keypair_file))]
+    administrator: Keypair,
+    #[structopt(long)]
+    new_administrator: Option<Pubkey>,
+    #[structopt(long)]
+    fee_recipient: Option<Pubkey>,
+    /// Fee in lamports charged to post a trade
+    #[structopt(long)]
+    fee: Option<u64>,
+    /// Fee in basis points taken from the tokens a poster receives when their trade is taken
+    #[structopt(long)]
+    fee_bps: Option<u16>,
+}
+
+#[allow(clippy::large_enum_variant)]
+#[derive(StructOpt)]
+enum FeesCommand {
+    Withdraw(FeesWithdraw),
+    Balance(FeesBalance),
+}
+
+#[derive(StructOpt)]
+struct FeesWithdraw {
+    #[structopt(parse(try_from_str = read_keypair_file))]
+    administrator: Keypair,
+    destination: Pubkey,
+    /// Amount to withdraw, defaults to everything collected
+    #[structopt(long)]
+    amount: Option<u64>,
+    /// Withdraw the basis-point fees collected in this token, rather than lamports.
+    /// They are sent to the destination's associated token account
+    #[structopt(long, parse(try_from_str = parse_mint))]
+    mint: Option<Pubkey>,
+}
+
+#[derive(StructOpt)]
+struct FeesBalance {
+    /// Report the basis-point fees collected in this token, rather than lamports
+    #[structopt(long, parse(try_from_str = parse_mint))]
+    mint: Option<Pubkey>,
+}
+
+#[derive(StructOpt)]
+enum MarketCommand {
+    Create(MarketCreate),
+    Show(MarketShow),
+    Place(MarketPlace),
+    Cancel(MarketCancel),
+    Match(MarketMatch),
+}
+
+#[derive(StructOpt)]
+struct MarketCreate {
+    #[structopt(parse(try_from_str = read_keypair_file))]
+    payer: Keypair,
+    /// Mint of the token bought and sold, or `SOL`
+    #[structopt(parse(try_from_str = parse_mint))]
+    base: Pubkey,
+    /// Mint of the token prices are in, or `SOL`
+    #[structopt(parse(try_from_str = parse_mint))]
+    quote: Pubkey,
+}
+
+#[derive(StructOpt)]
+struct MarketShow {
+    #[structopt(parse(try_from_str = parse_mint))]
+    base: Pubkey,
+    #[structopt(parse(try_from_str = parse_mint))]
+    quote: Pubkey,
+}
+
+#[derive(StructOpt)]
+struct MarketPlace {
+    #[structopt(parse(try_from_str = read_keypair_file))]
+    owner: Keypair,
+    #[structopt(parse(try_from_str = parse_mint))]
+    base: Pubkey,
+    #[structopt(parse(try_from_str = parse_mint))]
+    quote: Pubkey,
+    /// `bid` to buy the base token, `ask` to sell it
+    #[structopt(parse(try_from_str = parse_side))]
+    side: Side,
+    /// Amount of the base token to buy or sell
+    base_amount: u64,
+    /// Amount of the quote token to pay for it, or to ask for it
+    quote_amount: u64,
+}
+
+#[derive(StructOpt)]
+struct MarketCancel {
+    #[structopt(parse(try_from_str = read_keypair_file))]
+    owner: Keypair,
+    #[structopt(parse(try_from_str = parse_mint))]
+    base: Pubkey,
+    #[structopt(parse(try_from_str = parse_mint))]
+    quote: Pubkey,
+    order_id: u64,
+}
+
+#[derive(StructOpt)]
+struct MarketMatch {
+    #[structopt(parse(try_from_str = read_keypair_file))]
+    matcher: Keypair,
+    #[structopt(parse(try_from_str = parse_mint))]
+    base: Pubkey,
+    #[structopt(parse(try_from_str = parse_mint))]
+    quote: Pubkey,
+    /// Most fills to make in one transaction
+    #[structopt(long, default_value = "4")]
+    limit: u8,
+}
+
+#[derive(StructOpt)]
+enum OptionCommand {
+    Write(OptionWrite),
+    Buy(OptionBuy),
+    Exercise(OptionExercise),
+}
+
+#[derive(StructOpt)]
+struct OptionWrite {
+    #[structopt(parse(try_from_str = read_keypair_file))]
+    writer: Keypair,
+    /// Mint of the token the option is on, or `SOL`
+    #[structopt(parse(try_from_str = parse_mint))]
+    sell_token: Pubkey,
+    sell_amount: u64,
+    /// Mint of the token the strike and premium are paid in
+    #[structopt(parse(try_from_str = parse_mint))]
+    buy_token: Pubkey,
+    /// Amount of the buy token the holder pays to exercise the option
+    strike: u64,
+    /// Amount of the buy token the option sells for
+    premium: u64,
+    /// How long the option can be exercised for, e.g. `90`, `30m`, `12h` or `7d` (seconds if no unit)
+    #[structopt(parse(try_from_str = parse_duration))]
+    expires_in: i64,
+    /// Id of the offer, defaults to the lowest id the writer has no open offer or option token for
+    #[structopt(long)]
+    id: Option<u64>,
+}
+
+#[derive(StructOpt)]
+struct OptionBuy {
+    #[structopt(parse(try_from_str = read_keypair_file))]
+    buyer: Keypair,
+    /// The option, either as `<writer>/<id>` or as its escrow account
+    #[structopt(parse(try_from_str = parse_escrow_address))]
+    escrow_account: Pubkey,
+}
+
+#[derive(StructOpt)]
+struct OptionExercise {
+    /// Holder of the option token, from their associated token account
+    #[structopt(parse(try_from_str = read_keypair_file))]
+    holder: Keypair,
+    /// The option, either as `<writer>/<id>` or as its escrow account
+    #[structopt(parse(try_from_str = parse_escrow_address))]
+    escrow_account: Pubkey,
+}
+
+#[derive(StructOpt)]
+enum OrderCommand {
+    Approve(OrderApprove),
+    Sign(OrderSign),
+    Show(OrderShow),
+    Fill(OrderFillArgs),
+    Cancel(OrderCancel),
+}
+
+#[derive(StructOpt)]
+struct OrderApprove {
+    /// Maker approving their associated token account, replacing any earlier approval of it
+    #[structopt(parse(try_from_str = read_keypair_file))]
+    maker: Keypair,
+    /// Mint of the token signed orders sell, or `SOL` for wrapped SOL
+    #[structopt(parse(try_from_str = parse_mint))]
+    token: Pubkey,
+    /// Most that signed orders can sell altogether
+    amount: u64,
+}
+
+#[derive(StructOpt)]
+struct OrderSign {
+    #[structopt(parse(try_from_str = read_keypair_file))]
+    maker: Keypair,
+    /// Mint of the token to sell, or `SOL` for wrapped SOL
+    #[structopt(parse(try_from_str = parse_mint))]
     sell_token: Pubkey,
     sell_amount: u64,
+    /// Mint of the token to buy, or `SOL` for wrapped SOL
+    #[structopt(parse(try_from_str = parse_mint))]
     buy_token: Pubkey,
     buy_amount: u64,
+    /// How long the order can be filled for, e.g. `90`, `30m`, `12h` or `7d` (seconds if no unit)
+    #[structopt(parse(try_from_str = parse_duration))]
+    expires_in: i64,
+    /// File to write the signed order to, for a taker to fill
+    file: PathBuf,
+    /// Nonce of the order, defaults to a random one. Filling or cancelling any order with a nonce
+    /// stops every other order of the maker's with it being filled
+    #[structopt(long)]
+    nonce: Option<u64>,
+    /// Reserve the order for this account, no one else will be able to fill it
+    #[structopt(long)]
+    taker: Option<Pubkey>,
+}
+
+#[derive(StructOpt)]
+struct OrderShow {
+    /// File holding the signed order
+    file: PathBuf,
+}
+
+#[derive(StructOpt)]
+struct OrderFillArgs {
+    #[structopt(parse(try_from_str = read_keypair_file))]
+    taker: Keypair,
+    /// File holding the signed order
+    file: PathBuf,
+    /// Fill the order without asking for confirmation
+    #[structopt(short, long)]
+    force: bool,
+}
+
+#[derive(StructOpt)]
+struct OrderCancel {
+    #[structopt(parse(try_from_str = read_keypair_file))]
+    maker: Keypair,
+    /// Nonce of the orders to cancel
+    nonce: u64,
+}
+
+#[derive(StructOpt)]
+enum RfqCommand {
+    Create(RfqCreate),
+    Show(RfqShow),
+    Quote(RfqQuote),
+    Accept(RfqAccept),
+    Cancel(RfqCancel),
+}
+
+#[derive(StructOpt)]
+struct RfqCreate {
+    #[structopt(parse(try_from_str = read_keypair_file))]
+    requester: Keypair,
+    /// Mint of the token to buy, or `SOL`
+    #[structopt(parse(try_from_str = parse_mint))]
+    token: Pubkey,
+    amount: u64,
+    /// Mint of the token to pay with, or `SOL`
+    #[structopt(parse(try_from_str = parse_mint))]
+    pay_token: Pubkey,
+    /// How long quotes can be posted and accepted for, e.g. `90`, `30m`, `12h` or `7d` (seconds if no unit)
+    #[structopt(parse(try_from_str = parse_duration))]
+    deadline_in: i64,
+    /// Id of the request, defaults to the lowest id the requester has no open request for
+    #[structopt(long)]
+    id: Option<u64>,
+}
+
+#[derive(StructOpt)]
+struct RfqShow {
+    /// The request, either as `<requester>/<id>` or as its account
+    #[structopt(parse(try_from_str = parse_rfq_address))]
+    rfq_account: Pubkey,
+}
+
+#[derive(StructOpt)]
+struct RfqQuote {
+    #[structopt(parse(try_from_str = read_keypair_file))]
+    maker: Keypair,
+    /// The request, either as `<requester>/<id>` or as its account
+    #[structopt(parse(try_from_str = parse_rfq_address))]
+    rfq_account: Pubkey,
+    /// Amount of the token the requester pays with to ask for the requested amount
+    price: u64,
+    /// Id of the quote's offer, defaults to the lowest id the maker has no open offer for
+    #[structopt(long)]
+    id: Option<u64>,
+}
+
+#[derive(StructOpt)]
+struct RfqAccept {
+    #[structopt(parse(try_from_str = read_keypair_file))]
+    requester: Keypair,
+    /// The request, either as `<requester>/<id>` or as its account
+    #[structopt(parse(try_from_str = parse_rfq_address))]
+    rfq_account: Pubkey,
+    /// The quote to accept, either as `<maker>/<id>` or as its escrow account
+    #[structopt(parse(try_from_str = parse_escrow_address))]
+    quote: Pubkey,
+    /// Accept the quote without asking for confirmation
+    #[structopt(short, long)]
+    force: bool,
+}
+
+#[derive(StructOpt)]
+struct RfqCancel {
+    #[structopt(parse(try_from_str = read_keypair_file))]
+    requester: Keypair,
+    /// The request, either as `<requester>/<id>` or as its account
+    #[structopt(parse(try_from_str = parse_rfq_address))]
+    rfq_account: Pubkey,
+}
+
+#[derive(StructOpt)]
+struct Post {
+    #[structopt(parse(try_from_str = read_keypair_file))]
+    poster: Keypair,
+    /// Mint of the token to sell, or `SOL`
+    #[structopt(parse(try_from_str = parse_mint))]
+    sell_token: Option<Pubkey>,
+    sell_amount: Option<u64>,
+    /// Mint of the token to buy, or `SOL`
+    #[structopt(parse(try_from_str = parse_mint))]
+    buy_token: Option<Pubkey>,
+    buy_amount: Option<u64>,
+    /// A token to sell as part of a bundle, as `MINT:AMOUNT`. Repeat it to sell several tokens at once,
+    /// in place of the token and amount arguments
+    #[structopt(long = "sell", parse(try_from_str = parse_token_amount))]
+    sell_legs: Vec<TokenAmount>,
+    /// A token to buy as part of a bundle, as `MINT:AMOUNT`. Repeat it to ask for several tokens at once
+    #[structopt(long = "buy", parse(try_from_str = parse_token_amount))]
+    buy_legs: Vec<TokenAmount>,
+    /// How long the offer can be taken for, e.g. `90`, `30m`, `12h` or `7d` (seconds if no unit)
+    #[structopt(long, parse(try_from_str = parse_duration))]
+    expires_in: Option<i64>,
+    /// Id of the offer, defaults to the lowest id the poster has no open offer for
+    #[structopt(long)]
+    id: Option<u64>,
+    /// Reserve the offer for this account, no one else will be able to take it
+    #[structopt(long)]
+    taker: Option<Pubkey>,
+    /// Keep the tokens being sold in the poster's account, approving the program to transfer them,
+    /// instead of moving them into a vault
+    #[structopt(long)]
+    delegate: bool,
+    /// Run the offer as a Dutch auction, asking this much of the token to buy at first,
+    /// falling to the buy amount over `--decay`
+    #[structopt(long, requires = "decay")]
+    start_price: Option<u64>,
+    /// How long a Dutch auction's price takes to fall to the buy amount, e.g. `30m`, `12h` or `7d`
+    #[structopt(long, requires = "start-price", parse(try_from_str = parse_duration))]
+    decay: Option<i64>,
+    /// Run the offer as an English auction taking bids for this long, with the buy amount as
+    /// its reserve price, e.g. `30m`, `12h` or `7d`
+    #[structopt(long, parse(try_from_str = parse_duration))]
+    auction_ends_in: Option<i64>,
+    /// How much each bid in an English auction has to beat the highest bid by
+    #[structopt(long, default_value = "0")]
+    min_increment: u64,
+    /// Run the offer as a sealed-bid auction taking hidden bids for this long, with the buy amount as
+    /// its reserve price, e.g. `30m`, `12h` or `7d`
+    #[structopt(long, requires = "reveal-period", parse(try_from_str = parse_duration))]
+    sealed_auction_ends_in: Option<i64>,
+    /// How long bids on a sealed-bid auction can be revealed for once bidding closes
+    #[structopt(long, requires = "sealed-auction-ends-in", parse(try_from_str = parse_duration))]
+    reveal_period: Option<i64>,
+    /// Pay the deposits of sealed bids that aren't revealed to the poster, instead of refunding them
+    #[structopt(long, requires = "sealed-auction-ends-in")]
+    forfeit_unrevealed: bool,
+}
+
+#[derive(StructOpt)]
+struct Show {
+    /// The offer, either as `<poster>/<id>` or as its escrow account
+    #[structopt(parse(try_from_str = parse_escrow_address))]
+    escrow_account: Pubkey,
 }
 
 #[derive(StructOpt)]
 struct Take {
     #[structopt(parse(try_from_str = read_keypair_file))]
     taker: Keypair,
+    /// The offer, either as `<poster>/<id>` or as its escrow account
+    #[structopt(parse(try_from_str = parse_escrow_address))]
     escrow_account: Pubkey,
+    /// Amount of the posted token to take, defaults to everything left in the offer
+    #[structopt(short, long)]
+    amount: Option<u64>,
     #[structopt(short, long)]
     force: bool,
 }
@@ -68,9 +512,80 @@ struct Take {
 struct Cancel {
     #[structopt(parse(try_from_str = read_keypair_file))]
     poster: Keypair,
+    /// The offer, either as `<poster>/<id>` or as its escrow account
+    #[structopt(parse(try_from_str = parse_escrow_address))]
+    escrow_account: Pubkey,
+}
+
+#[derive(StructOpt)]
+struct Amend {
+    #[structopt(parse(try_from_str = read_keypair_file))]
+    poster: Keypair,
+    /// The offer, either as `<poster>/<id>` or as its escrow account
+    #[structopt(parse(try_from_str = parse_escrow_address))]
+    escrow_account: Pubkey,
+    /// New amount of the wanted token for what is left of the offer
+    buy_amount: u64,
+    /// New amount of the posted token left on offer, topping up or withdrawing from the escrow
+    #[structopt(long)]
+    sell_amount: Option<u64>,
+}
+
+#[derive(StructOpt)]
+struct Crank {
+    #[structopt(parse(try_from_str = read_keypair_file))]
+    caller: Keypair,
+}
+
+#[derive(StructOpt)]
+struct Match {
+    /// Receives any surplus of the matched offers, into its associated token accounts
+    #[structopt(parse(try_from_str = read_keypair_file))]
+    matcher: Keypair,
+}
+
+#[derive(StructOpt)]
+struct Bid {
+    #[structopt(parse(try_from_str = read_keypair_file))]
+    bidder: Keypair,
+    /// The auction, either as `<poster>/<id>` or as its escrow account
+    #[structopt(parse(try_from_str = parse_escrow_address))]
+    escrow_account: Pubkey,
+    /// Amount of the token asked for to bid
+    amount: u64,
+    /// How much to deposit with a bid on a sealed-bid auction, hiding the bid. Defaults to the bid
+    #[structopt(long)]
+    deposit: Option<u64>,
+}
+
+#[derive(StructOpt)]
+struct Reveal {
+    #[structopt(parse(try_from_str = read_keypair_file))]
+    bidder: Keypair,
+    /// The sealed-bid auction, either as `<poster>/<id>` or as its escrow account
+    #[structopt(parse(try_from_str = parse_escrow_address))]
+    escrow_account: Pubkey,
+}
+
+#[derive(StructOpt)]
+struct Settle {
+    /// Pays for the transaction, and for the fee token account and the winner's claim vault if they don't
+    /// exist yet
+    #[structopt(parse(try_from_str = read_keypair_file))]
+    caller: Keypair,
+    /// The auction, either as `<poster>/<id>` or as its escrow account
+    #[structopt(parse(try_from_str = parse_escrow_address))]
     escrow_account: Pubkey,
 }
 
+#[derive(StructOpt)]
+struct Claim {
+    /// Withdraws everything auctions and markets owe this account: refunds of its bids, the tokens it won,
+    /// the winning bids of its auctions and what its orders were paid
+    #[structopt(parse(try_from_str = read_keypair_file))]
+    owner: Keypair,
+}
+
 fn do_create_fee_account(client: &RpcClient, create: &Create) -> Result<(), Error> {
     let fee_account = Keypair::new();
     println!("Making new fee account {}", fee_account.pubkey());
@@ -89,289 +604,3156 @@ fn do_create_fee_account(client: &RpcClient, create: &Create) -> Result<(), Erro
     )
 }
 
-///
-/// Post trade
-///
-
-fn do_post(client: &RpcClient, post: &Post) -> Result<(), Error> {
-    let sell_account = get_associated_token_address(&post.poster.pubkey(), &post.sell_token);
-    let buy_account = get_associated_token_address(&post.poster.pubkey(), &post.buy_token);
-    let escrow_account = Keypair::new();
-    let token_account = Keypair::new();
-    println!("Creating escrow account {}", escrow_account.pubkey());
-    println!("Creating token account {}", token_account.pubkey());
-    println!("Using sell Associated Token Account {}", sell_account);
-    println!("Using buy Associated Token Account {}", buy_account);
+//
+// Program config
+//
 
-    let mut instructions = Vec::new();
-    add_associated_token_account(
-        client,
-        &buy_account,
-        &post.poster.pubkey(),
-        &post.poster.pubkey(),
-        &post.buy_token,
-        &mut instructions,
-    )?;
-    instructions.extend_from_slice(&[
-        create_token_account_instruction(client, &post.poster.pubkey(), &token_account.pubkey())?,
-        spl_token::instruction::initialize_account(
-            &spl_token::ID,
-            &token_account.pubkey(),
-            &post.sell_token,
-            &post.poster.pubkey(),
-        )?,
-        spl_token::instruction::transfer(
-            &spl_token::ID,
-            &sell_account,
-            &token_account.pubkey(),
-            &post.poster.pubkey(),
-            &[],
-            post.sell_amount * LAMPORTS_PER_SOL,
-        )?,
-        create_escrow_instruction(
-            client,
-            &post.poster.pubkey(),
-            &escrow_account.pubkey(),
-            &program_id(),
-        )?,
-        post_trade_instruction(
-            post,
-            buy_account,
-            escrow_account.pubkey(),
-            token_account.pubkey(),
-        ),
-    ]);
+fn do_init_config(client: &RpcClient, init: &ConfigInit) -> Result<(), Error> {
+    let (config_account, _) = program::find_config_address(&program_id());
+    println!("Creating config account {}", config_account);
+    let (program_data, _) = Pubkey::find_program_address(
+        &[program_id().as_ref()],
+        &solana_sdk::bpf_loader_upgradeable::id(),
+    );
+    let instruction = Instruction::new_with_borsh(
+        program_id(),
+        &program::Instruction::InitializeConfig {
+            fee: init.fee,
+            fee_recipient: init.fee_recipient,
+            fee_bps: init.fee_bps,
+        },
+        vec![
+            AccountMeta::new(init.administrator.pubkey(), true),
+            AccountMeta::new(config_account, false),
+            AccountMeta::new_readonly(solana_sdk::system_program::ID, false),
+            AccountMeta::new_readonly(program_data, false),
+        ],
+    );
     execute(
         client,
-        &post.poster,
-        &instructions,
-        vec![&post.poster, &token_account, &escrow_account],
+        &init.administrator,
+        &[instruction],
+        vec![&init.administrator],
     )
 }
 
-fn get_token_mint(client: &RpcClient, token: &Pubkey) -> Result<Pubkey, Error> {
-    let account = client.get_account(token)?;
-    let account_info = spl_token::state::Account::unpack(&account.data)?;
-    Ok(account_info.mint)
-}
-
-fn create_token_account_instruction(
-    client: &RpcClient,
-    poster: &Pubkey,
-    token_account: &Pubkey,
-) -> Result<Instruction, Error> {
-    let space = spl_token::state::Account::LEN;
-    let rent = client.get_minimum_balance_for_rent_exemption(space)?;
-    Ok(solana_sdk::system_instruction::create_account(
-        poster,
-        token_account,
-        rent,
-        space as u64,
-        &spl_token::ID,
-    ))
-}
-
-fn create_escrow_instruction(
-    client: &RpcClient,
-    poster: &Pubkey,
-    escrow_account: &Pubkey,
-    program_id: &Pubkey,
-) -> Result<Instruction, Error> {
-    let space = get_packed_len::<program::Escrow>();
-    let rent = client.get_minimum_balance_for_rent_exemption(space)?;
-    Ok(solana_sdk::system_instruction::create_account(
-        poster,
-        escrow_account,
-        rent,
-        space as u64,
-        program_id,
-    ))
-}
-
-fn post_trade_instruction(
-    post: &Post,
-    buy_account: Pubkey,
-    escrow_account: Pubkey,
-    token_account: Pubkey,
-) -> Instruction {
-    Instruction::new_with_borsh(
+fn do_update_config(client: &RpcClient, update: &ConfigUpdate) -> Result<(), Error> {
+    let (config_account, config) = get_config(client)?;
+    let instruction = Instruction::new_with_borsh(
         program_id(),
-        &program::Instruction::Post {
-            buy_amount: post.buy_amount * LAMPORTS_PER_SOL,
+        &program::Instruction::UpdateConfig {
+            admin: update.new_administrator.unwrap_or(config.admin),
+            fee: update.fee.unwrap_or(config.fee),
+            fee_recipient: update.fee_recipient.unwrap_or(config.fee_recipient),
+            fee_bps: update.fee_bps.unwrap_or(config.fee_bps),
         },
         vec![
-            AccountMeta::new_readonly(post.poster.pubkey(), true),
-            AccountMeta::new(token_account, false),
-            AccountMeta::new_readonly(buy_account, false),
-            AccountMeta::new(escrow_account, false),
-            AccountMeta::new_readonly(spl_token::ID, false),
-            AccountMeta::new_readonly(solana_sdk::system_program::ID, false),
-            AccountMeta::new(program::fee_account_pubkey(), false),
+            AccountMeta::new_readonly(update.administrator.pubkey(), true),
+            AccountMeta::new(config_account, false),
         ],
+    );
+    execute(
+        client,
+        &update.administrator,
+        &[instruction],
+        vec![&update.administrator],
     )
 }
 
-///
-/// Take trade
-///
-
-fn do_take(client: &RpcClient, take: &Take) -> Result<(), Error> {
-    let escrow =
-        Escrow::deserialize(&mut client.get_account(&take.escrow_account)?.data.as_slice())?;
-    let sell_token = get_token_mint(client, &escrow.poster_buy_account)?;
-    let buy_token = get_token_mint(client, &escrow.token_account)?;
-    let buy_amount = get_token_amount(client, &escrow.token_account)?;
-    if !take.force && !confirm_with_user(&escrow, buy_amount, &sell_token, &buy_token)? {
-        return Err("Trade aborted".into());
-    }
+fn do_show_config(client: &RpcClient) -> Result<(), Error> {
+    let (config_account, config) = get_config(client)?;
+    println!("Config account {}", config_account);
+    println!("Administrator {}", config.admin);
+    println!("Fee recipient {}", config.fee_recipient);
+    println!("Fee {} lamports", config.fee);
+    println!("Token fee {} basis points", config.fee_bps);
+    Ok(())
+}
 
-    let taker_sell_account = get_associated_token_address(&take.taker.pubkey(), &sell_token);
-    let taker_buy_account = get_associated_token_address(&take.taker.pubkey(), &buy_token);
+//
+// Collected fees
+//
 
+fn do_withdraw_fees(client: &RpcClient, withdraw: &FeesWithdraw) -> Result<(), Error> {
+    let (config_account, config) = get_config(client)?;
     let mut instructions = Vec::new();
-    add_associated_token_account(
+    match withdraw.mint {
+        None => instructions.push(Instruction::new_with_borsh(
+            program_id(),
+            &program::Instruction::WithdrawFees {
+                amount: withdraw.amount,
+            },
+            vec![
+                AccountMeta::new_readonly(withdraw.administrator.pubkey(), true),
+                AccountMeta::new_readonly(config_account, false),
+                AccountMeta::new(config.fee_recipient, false),
+                AccountMeta::new(withdraw.destination, false),
+            ],
+        )),
+        Some(mint) => {
+            let (fee_authority, _) = program::find_fee_authority(&program_id());
+            let token_program = get_token_program(client, &mint)?;
+            let destination =
+                get_associated_token_address(&withdraw.destination, &mint, &token_program);
+            add_associated_token_account(
+                client,
+                &destination,
+                &withdraw.administrator.pubkey(),
+                &withdraw.destination,
+                &mint,
+                &token_program,
+                &mut instructions,
+            )?;
+            instructions.push(Instruction::new_with_borsh(
+                program_id(),
+                &program::Instruction::WithdrawTokenFees {
+                    amount: withdraw.amount,
+                },
+                vec![
+                    AccountMeta::new_readonly(withdraw.administrator.pubkey(), true),
+                    AccountMeta::new_readonly(config_account, false),
+                    AccountMeta::new(
+                        get_associated_token_address(&fee_authority, &mint, &token_program),
+                        false,
+                    ),
+                    AccountMeta::new(destination, false),
+                    AccountMeta::new_readonly(token_program, false),
+                    AccountMeta::new_readonly(fee_authority, false),
+                    AccountMeta::new_readonly(mint, false),
+                ],
+            ));
+        }
+    }
+    execute(
         client,
-        &taker_buy_account,
-        &take.taker.pubkey(),
-        &take.taker.pubkey(),
-        &buy_token,
-        &mut instructions,
-    )?;
-    let (pda, _) = Pubkey::find_program_address(&[program::ESCROW_SEED], &program_id());
-    instructions.push(take_trade_instruction(
-        take,
-        &escrow,
-        taker_sell_account,
-        taker_buy_account,
-        buy_amount,
-        pda,
-    ));
-
-    execute(client, &take.taker, &instructions, vec![&take.taker])
+        &withdraw.administrator,
+        &instructions,
+        vec![&withdraw.administrator],
+    )
 }
 
-fn get_token_amount(client: &RpcClient, token: &Pubkey) -> Result<u64, Error> {
-    let account = client.get_account(token)?;
-    let account_info = spl_token::state::Account::unpack(&account.data)?;
-    Ok(account_info.amount)
+fn do_fees_balance(client: &RpcClient, balance: &FeesBalance) -> Result<(), Error> {
+    if let Some(mint) = balance.mint {
+        let (fee_authority, _) = program::find_fee_authority(&program_id());
+        let token_program = get_token_program(client, &mint)?;
+        let fee_token_account = get_associated_token_address(&fee_authority, &mint, &token_program);
+        println!("Fee token account {}", fee_token_account);
+        println!(
+            "Collected {} of {}",
+            get_token_amount(client, &fee_token_account)?,
+            mint
+        );
+        return Ok(());
+    }
+    let (_, config) = get_config(client)?;
+    let fee_account = client.get_account(&config.fee_recipient)?;
+    let rent = client.get_minimum_balance_for_rent_exemption(fee_account.data.len())?;
+    println!("Fee account {}", config.fee_recipient);
+    println!(
+        "Collected {} lamports",
+        fee_account.lamports.saturating_sub(rent)
+    );
+    Ok(())
 }
 
-fn confirm_with_user(
-    escrow: &Escrow,
-    buy_amount: u64,
-    sell_token: &Pubkey,
-    buy_token: &Pubkey,
-) -> Result<bool, Error> {
-    println!("Preparing to do trade:");
-    println!("  sell {} of {}", escrow.buy_amount, sell_token);
-    println!("  buy {} of {}", buy_amount, buy_token);
-    println!("  from user {}", escrow.poster);
-    let answer = question::Question::new("Are you sure you want to continue?")
-        .yes_no()
-        .until_acceptable()
+///
+/// Post trade
+///
+
+fn do_post(client: &RpcClient, post: &Post) -> Result<(), Error> {
+    if !post.sell_legs.is_empty() || !post.buy_legs.is_empty() {
+        return do_post_bundle(client, post);
+    }
+    let (sell, buy) = match (
+        post.sell_token,
+        post.sell_amount,
+        post.buy_token,
+        post.buy_amount,
+    ) {
+        (Some(sell_token), Some(sell_amount), Some(buy_token), Some(buy_amount)) => (
+            TokenAmount {
+                mint: sell_token,
+                amount: sell_amount,
+            },
+            TokenAmount {
+                mint: buy_token,
+                amount: buy_amount,
+            },
+        ),
+        _ => return Err("Give the tokens and amounts to trade, or --sell and --buy".into()),
+    };
+    if post.delegate && sell.mint == spl_token::native_mint::ID {
+        return Err(
+            "SOL can't be delegated, post it without --delegate to wrap it into a vault".into(),
+        );
+    }
+    if post.delegate && post.start_price.is_some() {
+        return Err("Dutch auctions are posted into a vault, leave out --delegate".into());
+    }
+    if post.auction_ends_in.is_some() {
+        if post.delegate || post.start_price.is_some() || post.expires_in.is_some() {
+            return Err(
+                "English auctions can't be delegated, Dutch auctions or expire, \
+                 leave out --delegate, --start-price and --expires-in"
+                    .into(),
+            );
+        }
+        if buy.mint == spl_token::native_mint::ID {
+            return Err("English auctions can't ask for SOL, ask for wrapped SOL instead".into());
+        }
+    }
+    if post.sealed_auction_ends_in.is_some() {
+        if post.delegate
+            || post.start_price.is_some()
+            || post.expires_in.is_some()
+            || post.auction_ends_in.is_some()
+        {
+            return Err(
+                "Sealed-bid auctions can't be delegated, Dutch or English auctions or expire, \
+                 leave out --delegate, --start-price, --auction-ends-in and --expires-in"
+                    .into(),
+            );
+        }
+        if buy.mint == spl_token::native_mint::ID {
+            return Err(
+                "Sealed-bid auctions can't ask for SOL, ask for wrapped SOL instead".into(),
+            );
+        }
+    }
+    let sell_token_program = get_token_program(client, &sell.mint)?;
+    let buy_token_program = get_token_program(client, &buy.mint)?;
+    let sell_account = owner_account(&post.poster.pubkey(), &sell.mint, &sell_token_program);
+    let buy_account = owner_account(&post.poster.pubkey(), &buy.mint, &buy_token_program);
+    let offer_id = match post.id {
+        Some(id) => id,
+        None => next_offer_id(client, &post.poster.pubkey())?,
+    };
+    let (escrow_account, _) =
+        program::find_escrow_address(&post.poster.pubkey(), offer_id, &program_id());
+    println!("Creating escrow account {}", escrow_account);
+    println!("Offer id {}/{}", post.poster.pubkey(), offer_id);
+    if post.delegate {
+        println!("Delegating from token account {}", sell_account);
+    } else {
+        let (vault_account, _) = program::find_escrow_vault(&escrow_account, &program_id());
+        println!("Creating token account {}", vault_account);
+    }
+    println!("Using sell account {}", sell_account);
+    println!("Using buy account {}", buy_account);
+
+    let now = cluster_time(client)?;
+    let expires_at = post.expires_in.map(|duration| now + duration);
+    let sell_amount = sell.amount * LAMPORTS_PER_SOL;
+    let buy_amount = buy.amount * LAMPORTS_PER_SOL;
+    let data = match (
+        post.start_price,
+        post.decay,
+        post.auction_ends_in,
+        post.sealed_auction_ends_in.zip(post.reveal_period),
+    ) {
+        (Some(start_price), Some(decay), _, _) => program::Instruction::PostDutchAuction {
+            offer_id,
+            sell_amount,
+            auction: DutchAuction {
+                start_price: start_price * LAMPORTS_PER_SOL,
+                end_price: buy_amount,
+                start_time: now,
+                end_time: now + decay,
+            },
+            expires_at,
+            allowed_taker: post.taker,
+        },
+        (_, _, Some(ends_in), _) => program::Instruction::PostEnglishAuction {
+            offer_id,
+            sell_amount,
+            reserve_price: buy_amount,
+            min_increment: post.min_increment * LAMPORTS_PER_SOL,
+            end_time: now + ends_in,
+            allowed_taker: post.taker,
+        },
+        (_, _, _, Some((ends_in, reveal_period))) => {
+            let commit_end = now + ends_in;
+            program::Instruction::PostSealedAuction {
+                offer_id,
+                sell_amount,
+                reserve_price: buy_amount,
+                commit_end,
+                reveal_end: commit_end + reveal_period,
+                unrevealed: if post.forfeit_unrevealed {
+                    UnrevealedPolicy::Forfeit
+                } else {
+                    UnrevealedPolicy::Refund
+                },
+                allowed_taker: post.taker,
+            }
+        }
+        _ if post.delegate => program::Instruction::PostDelegated {
+            offer_id,
+            sell_amount,
+            buy_amount,
+            expires_at,
+            allowed_taker: post.taker,
+        },
+        _ => program::Instruction::Post {
+            offer_id,
+            sell_amount,
+            buy_amount,
+            expires_at,
+            allowed_taker: post.taker,
+        },
+    };
+
+    let (_, config) = get_config(client)?;
+
+    let mut instructions = Vec::new();
+    if buy_account != post.poster.pubkey() {
+        add_associated_token_account(
+            client,
+            &buy_account,
+            &post.poster.pubkey(),
+            &post.poster.pubkey(),
+            &buy.mint,
+            &buy_token_program,
+            &mut instructions,
+        )?;
+    }
+    instructions.push(post_trade_instruction(
+        post,
+        offer_id,
+        &data,
+        &sell.mint,
+        sell_account,
+        buy_account,
+        config.fee_recipient,
+        sell_token_program,
+    ));
+    execute(client, &post.poster, &instructions, vec![&post.poster])
+}
+
+/// Posts the `--sell` and `--buy` tokens as a bundle, taken all together
+fn do_post_bundle(client: &RpcClient, post: &Post) -> Result<(), Error> {
+    if post.sell_token.is_some() {
+        return Err("Give the tokens to trade either as arguments or as --sell and --buy".into());
+    }
+    if post.delegate {
+        return Err("Bundles can't be delegated, post them without --delegate".into());
+    }
+    for legs in [&post.sell_legs, &post.buy_legs] {
+        if legs.is_empty() || legs.len() > program::MAX_BUNDLE_LEGS {
+            return Err(format!(
+                "A bundle needs between 1 and {} --sell and --buy tokens",
+                program::MAX_BUNDLE_LEGS
+            )
+            .into());
+        }
+    }
+    let poster = post.poster.pubkey();
+    let offer_id = match post.id {
+        Some(id) => id,
+        None => next_offer_id(client, &poster)?,
+    };
+    let (bundle_account, _) = program::find_escrow_address(&poster, offer_id, &program_id());
+    println!("Creating bundle account {}", bundle_account);
+    println!("Offer id {}/{}", poster, offer_id);
+
+    let expires_at = match post.expires_in {
+        Some(duration) => Some(cluster_time(client)? + duration),
+        None => None,
+    };
+
+    let (config_account, config) = get_config(client)?;
+
+    // Bundles trade SOL wrapped, so it is held in the poster's associated token accounts like any other token
+    let mut instructions = Vec::new();
+    let mut accounts = vec![
+        AccountMeta::new(poster, true),
+        AccountMeta::new(bundle_account, false),
+        AccountMeta::new_readonly(solana_sdk::system_program::ID, false),
+        AccountMeta::new_readonly(config_account, false),
+        AccountMeta::new(config.fee_recipient, false),
+    ];
+    for (index, leg) in (0u8..).zip(&post.sell_legs) {
+        let token_program = get_token_program(client, &leg.mint)?;
+        let sell_account = get_associated_token_address(&poster, &leg.mint, &token_program);
+        let (vault_account, _) = program::find_bundle_vault(&bundle_account, index, &program_id());
+        println!("Creating token account {} for {}", vault_account, leg.mint);
+        accounts.extend([
+            AccountMeta::new(sell_account, false),
+            AccountMeta::new(vault_account, false),
+            AccountMeta::new_readonly(leg.mint, false),
+            AccountMeta::new_readonly(token_program, false),
+        ]);
+    }
+    for leg in &post.buy_legs {
+        let token_program = get_token_program(client, &leg.mint)?;
+        let buy_account = get_associated_token_address(&poster, &leg.mint, &token_program);
+        println!("Using buy account {} for {}", buy_account, leg.mint);
+        add_associated_token_account(
+            client,
+            &buy_account,
+            &poster,
+            &poster,
+            &leg.mint,
+            &token_program,
+            &mut instructions,
+        )?;
+        accounts.push(AccountMeta::new_readonly(buy_account, false));
+    }
+
+    instructions.push(Instruction::new_with_borsh(
+        program_id(),
+        &program::Instruction::PostBundle {
+            offer_id,
+            sell_amounts: post
+                .sell_legs
+                .iter()
+                .map(|leg| leg.amount * LAMPORTS_PER_SOL)
+                .collect(),
+            buy_amounts: post
+                .buy_legs
+                .iter()
+                .map(|leg| leg.amount * LAMPORTS_PER_SOL)
+                .collect(),
+            expires_at,
+            allowed_taker: post.taker,
+        },
+        accounts,
+    ));
+    execute(client, &post.poster, &instructions, vec![&post.poster])
+}
+
+fn next_offer_id(client: &RpcClient, poster: &Pubkey) -> Result<u64, Error> {
+    for offer_id in 0.. {
+        let (escrow_account, _) = program::find_escrow_address(poster, offer_id, &program_id());
+        if client
+            .get_account_with_commitment(&escrow_account, client.commitment())?
+            .value
+            .is_none()
+        {
+            return Ok(offer_id);
+        }
+    }
+    Err("Poster has no free offer ids".into())
+}
+
+#[allow(clippy::too_many_arguments)]
+fn post_trade_instruction(
+    post: &Post,
+    offer_id: u64,
+    data: &program::Instruction,
+    sell_mint: &Pubkey,
+    sell_account: Pubkey,
+    buy_account: Pubkey,
+    fee_account: Pubkey,
+    token_program: Pubkey,
+) -> Instruction {
+    let (escrow_account, _) =
+        program::find_escrow_address(&post.poster.pubkey(), offer_id, &program_id());
+    let (vault_account, _) = program::find_escrow_vault(&escrow_account, &program_id());
+    let (config_account, _) = program::find_config_address(&program_id());
+    if post.delegate {
+        return Instruction::new_with_borsh(
+            program_id(),
+            data,
+            vec![
+                AccountMeta::new(post.poster.pubkey(), true),
+                AccountMeta::new(sell_account, false),
+                AccountMeta::new_readonly(buy_account, false),
+                AccountMeta::new(escrow_account, false),
+                AccountMeta::new_readonly(token_program, false),
+                AccountMeta::new_readonly(solana_sdk::system_program::ID, false),
+                AccountMeta::new_readonly(config_account, false),
+                AccountMeta::new(fee_account, false),
+            ],
+        );
+    }
+    Instruction::new_with_borsh(
+        program_id(),
+        data,
+        vec![
+            AccountMeta::new(post.poster.pubkey(), true),
+            AccountMeta::new(sell_account, false),
+            AccountMeta::new(vault_account, false),
+            AccountMeta::new_readonly(*sell_mint, false),
+            AccountMeta::new_readonly(buy_account, false),
+            AccountMeta::new(escrow_account, false),
+            AccountMeta::new_readonly(token_program, false),
+            AccountMeta::new_readonly(solana_sdk::system_program::ID, false),
+            AccountMeta::new_readonly(config_account, false),
+            AccountMeta::new(fee_account, false),
+        ],
+    )
+}
+
+//
+// Show trade
+//
+
+fn do_show(client: &RpcClient, show: &Show) -> Result<(), Error> {
+    let data = client.get_account(&show.escrow_account)?.data;
+    let now = cluster_time(client)?;
+    if data.len() == Bundle::LEN {
+        let bundle = Bundle::deserialize(&mut data.as_slice())?;
+        println!("Bundle {}/{}", bundle.poster, bundle.offer_id);
+        for leg in &bundle.sell_legs {
+            println!("  sells {} of {}", leg.amount, leg.mint);
+        }
+        for leg in &bundle.buy_legs {
+            println!("  asks {} of {}", leg.amount, leg.mint);
+        }
+        if let Some(expires_at) = bundle.expires_at {
+            println!("  expires at unix time {}", expires_at);
+        }
+        if let Some(allowed_taker) = bundle.allowed_taker {
+            println!("  reserved for {}", allowed_taker);
+        }
+        return Ok(());
+    }
+
+    let escrow = Escrow::deserialize(&mut data.as_slice())?;
+    let remaining_amount = get_remaining_amount(client, &show.escrow_account, &escrow)?;
+    println!("Offer {}/{}", escrow.poster, escrow.offer_id);
+    println!("  sells {} of {}", remaining_amount, escrow.sell_mint);
+    println!(
+        "  asks {} of {}",
+        escrow.current_buy_amount(now),
+        escrow.buy_mint
+    );
+    if let Some(auction) = escrow.dutch_auction {
+        print_auction_price(&escrow, &auction, now);
+    }
+    if let Some(auction) = &escrow.english_auction {
+        println!(
+            "  English auction with a reserve price of {}, ending at unix time {}",
+            auction.reserve_price, auction.end_time
+        );
+        println!(
+            "    bids must beat the highest by {}",
+            auction.min_increment
+        );
+        match &auction.bid {
+            Some(bid) => println!("    highest bid {} by {}", bid.amount, bid.bidder),
+            None => println!("    no bids yet"),
+        }
+    }
+    if let Some(rfq) = &escrow.rfq {
+        println!(
+            "  Quote for request {}, only the requester can accept it",
+            rfq
+        );
+    }
+    if let Some(option) = &escrow.call_option {
+        println!(
+            "  Call option with a premium of {}, exercised by paying what it asks",
+            option.premium
+        );
+        println!("    option token {}", option.option_mint);
+        if option.is_sold {
+            println!("    bought, it can be exercised until it expires");
+        } else {
+            println!("    not bought yet");
+        }
+    }
+    if let Some(auction) = &escrow.sealed_auction {
+        println!(
+            "  Sealed-bid auction with a reserve price of {}, taking bids until unix time {}",
+            auction.reserve_price, auction.commit_end
+        );
+        println!(
+            "    bids can be revealed until unix time {}, after which unrevealed deposits are {}",
+            auction.reveal_end,
+            match auction.unrevealed {
+                UnrevealedPolicy::Refund => "refunded",
+                UnrevealedPolicy::Forfeit => "forfeited to the poster",
+            }
+        );
+        println!("    {} bids not yet revealed", auction.commitments);
+        if let Some(best) = &auction.best {
+            println!(
+                "    highest revealed bid {} by {}",
+                best.amount, best.bidder
+            );
+        }
+    }
+    if let Some(expires_at) = escrow.expires_at {
+        println!("  expires at unix time {}", expires_at);
+    }
+    if let Some(allowed_taker) = escrow.allowed_taker {
+        println!("  reserved for {}", allowed_taker);
+    }
+    println!("  version {}", escrow.version);
+    Ok(())
+}
+
+fn print_auction_price(escrow: &Escrow, auction: &DutchAuction, now: i64) {
+    println!(
+        "  Dutch auction currently asking {} of {}",
+        auction.price(now),
+        escrow.buy_mint
+    );
+    if now < auction.end_time {
+        println!(
+            "    falling to {} at unix time {}",
+            auction.end_price, auction.end_time
+        );
+    }
+}
+
+///
+/// Take trade
+///
+
+fn do_take(client: &RpcClient, take: &Take) -> Result<(), Error> {
+    let data = client.get_account(&take.escrow_account)?.data;
+    if data.len() == Bundle::LEN {
+        return do_take_bundle(client, take, Bundle::deserialize(&mut data.as_slice())?);
+    }
+    let escrow = Escrow::deserialize(&mut data.as_slice())?;
+    if escrow.english_auction.is_some() || escrow.sealed_auction.is_some() {
+        return Err("Offer is an auction, use `bid` instead".into());
+    }
+    if escrow.call_option.is_some() {
+        return Err(
+            "Offer is a call option, use `option buy` and `option exercise` instead".into(),
+        );
+    }
+    if escrow.rfq.is_some() {
+        return Err("Offer is a quote, use `rfq accept` instead".into());
+    }
+    let now = cluster_time(client)?;
+    if escrow.is_expired(now) {
+        return Err("Offer has expired".into());
+    }
+    match escrow.allowed_taker {
+        Some(allowed_taker) if allowed_taker != take.taker.pubkey() => println!(
+            "Warning: this offer is reserved for {}, taking it will fail",
+            allowed_taker
+        ),
+        _ => {}
+    }
+    let sell_token = escrow.buy_mint;
+    let buy_token = escrow.sell_mint;
+    let remaining_amount = get_remaining_amount(client, &take.escrow_account, &escrow)?;
+    let buy_amount = take
+        .amount
+        .map(|amount| amount * LAMPORTS_PER_SOL)
+        .unwrap_or(remaining_amount);
+    // a Dutch auction is paid at most its price now, as it can only fall until the take is processed
+    let price = program::fill_price(buy_amount, remaining_amount, escrow.current_buy_amount(now))
+        .ok_or("Amount to take must be non-zero and at most what is left in the offer")?;
+    let (_, config) = get_config(client)?;
+    let fee = program::token_fee(price, escrow.fee_bps);
+
+    // SOL is paid straight from the taker's account when the poster asked for it there.
+    // Otherwise the taker also pays any Token-2022 transfer fees, so the poster gets their full price
+    let pays_native = escrow.poster_buy_account == escrow.poster;
+    let epoch = client.get_epoch_info()?.epoch;
+    let sell_amount = if pays_native {
+        price
+    } else {
+        let mint_data = client.get_account(&sell_token)?.data;
+        program::amount_with_transfer_fee(&mint_data, epoch, price - fee)?
+            + program::amount_with_transfer_fee(&mint_data, epoch, fee)?
+    };
+    let received_amount = program::amount_after_transfer_fee(
+        &client.get_account(&buy_token)?.data,
+        epoch,
+        buy_amount,
+    )?;
+    if !take.force
+        && !confirm_with_user(
+            &escrow,
+            now,
+            received_amount,
+            sell_amount,
+            fee,
+            &sell_token,
+            &buy_token,
+        )?
+    {
+        return Err("Trade aborted".into());
+    }
+
+    let token_programs = get_token_programs(client, &escrow)?;
+    let taker_sell_account = if pays_native {
+        take.taker.pubkey()
+    } else {
+        get_associated_token_address(&take.taker.pubkey(), &sell_token, &token_programs.buy)
+    };
+    let taker_buy_account = owner_account(&take.taker.pubkey(), &buy_token, &token_programs.sell);
+
+    let mut instructions = Vec::new();
+    if taker_buy_account != take.taker.pubkey() {
+        add_associated_token_account(
+            client,
+            &taker_buy_account,
+            &take.taker.pubkey(),
+            &take.taker.pubkey(),
+            &buy_token,
+            &token_programs.sell,
+            &mut instructions,
+        )?;
+    }
+    let (fee_authority, _) = program::find_fee_authority(&program_id());
+    let fee_token_account = if pays_native {
+        config.fee_recipient
+    } else {
+        get_associated_token_address(&fee_authority, &sell_token, &token_programs.buy)
+    };
+    if fee > 0 && !pays_native {
+        add_associated_token_account(
+            client,
+            &fee_token_account,
+            &take.taker.pubkey(),
+            &fee_authority,
+            &sell_token,
+            &token_programs.buy,
+            &mut instructions,
+        )?;
+    }
+    instructions.push(take_trade_instruction(
+        take,
+        &escrow,
+        &token_programs,
+        taker_sell_account,
+        taker_buy_account,
+        buy_amount,
+        sell_amount,
+        fee_token_account,
+    ));
+
+    execute(client, &take.taker, &instructions, vec![&take.taker])
+}
+
+/// Amount of the poster's tokens still on offer, worked out the same way the program does
+fn get_remaining_amount(
+    client: &RpcClient,
+    escrow_account: &Pubkey,
+    escrow: &Escrow,
+) -> Result<u64, Error> {
+    let account = client.get_account(&escrow.token_account)?;
+    let account_info = StateWithExtensions::<TokenAccount>::unpack(&account.data)?.base;
+    let (pda, _) = program::find_escrow_authority(escrow_account, &escrow.poster, &program_id());
+    Ok(match escrow.custody {
+        Custody::Vault => account_info.amount,
+        Custody::Delegated if account_info.delegate == COption::Some(pda) => {
+            account_info.delegated_amount
+        }
+        Custody::Delegated => 0,
+    })
+}
+
+fn get_token_amount(client: &RpcClient, token: &Pubkey) -> Result<u64, Error> {
+    let account = client.get_account(token)?;
+    let account_info = StateWithExtensions::<TokenAccount>::unpack(&account.data)?;
+    Ok(account_info.base.amount)
+}
+
+fn confirm_with_user(
+    escrow: &Escrow,
+    now: i64,
+    buy_amount: u64,
+    sell_amount: u64,
+    fee: u64,
+    sell_token: &Pubkey,
+    buy_token: &Pubkey,
+) -> Result<bool, Error> {
+    println!("Preparing to do trade:");
+    println!("  sell {} of {}", sell_amount, sell_token);
+    if fee > 0 {
+        println!("    of which {} is taken as a fee", fee);
+    }
+    println!("  buy {} of {}", buy_amount, buy_token);
+    println!("  from user {}", escrow.poster);
+    if let Some(auction) = escrow.dutch_auction {
+        print_auction_price(escrow, &auction, now);
+    }
+    if let Some(expires_at) = escrow.expires_at {
+        println!("  offer expires at unix time {}", expires_at);
+    }
+    ask_to_continue()
+}
+
+fn ask_to_continue() -> Result<bool, Error> {
+    let answer = question::Question::new("Are you sure you want to continue?")
+        .yes_no()
+        .until_acceptable()
         .ask()
         .ok_or("Could not answer confirmation question")?;
     Ok(answer == question::Answer::YES)
 }
 
+/// Takes every token of a bundle, paying for every token it asks for
+fn do_take_bundle(client: &RpcClient, take: &Take, bundle: Bundle) -> Result<(), Error> {
+    if take.amount.is_some() {
+        return Err("Bundles can only be taken in full, leave out --amount".into());
+    }
+    if bundle.is_expired(cluster_time(client)?) {
+        return Err("Offer has expired".into());
+    }
+    match bundle.allowed_taker {
+        Some(allowed_taker) if allowed_taker != take.taker.pubkey() => println!(
+            "Warning: this offer is reserved for {}, taking it will fail",
+            allowed_taker
+        ),
+        _ => {}
+    }
+    let taker = take.taker.pubkey();
+    let (config_account, _) = get_config(client)?;
+    let (fee_authority, _) = program::find_fee_authority(&program_id());
+    let (pda, _) =
+        program::find_escrow_authority(&take.escrow_account, &bundle.poster, &program_id());
+    let epoch = client.get_epoch_info()?.epoch;
+
+    let mut instructions = Vec::new();
+    let mut accounts = vec![
+        AccountMeta::new(taker, true),
+        AccountMeta::new(bundle.poster, false),
+        AccountMeta::new(take.escrow_account, false),
+        AccountMeta::new_readonly(pda, false),
+        AccountMeta::new_readonly(config_account, false),
+    ];
+
+    // The taker pays any Token-2022 transfer fees, so the poster gets their full price
+    println!("Preparing to do trade:");
+    let mut buy_amounts = Vec::with_capacity(bundle.buy_legs.len());
+    for leg in &bundle.buy_legs {
+        let token_program = get_token_program(client, &leg.mint)?;
+        let fee = program::token_fee(leg.amount, bundle.fee_bps);
+        let mint_data = client.get_account(&leg.mint)?.data;
+        let sell_amount = program::amount_with_transfer_fee(&mint_data, epoch, leg.amount - fee)?
+            + program::amount_with_transfer_fee(&mint_data, epoch, fee)?;
+        println!("  sell {} of {}", sell_amount, leg.mint);
+        if fee > 0 {
+            println!("    of which {} is taken as a fee", fee);
+        }
+        buy_amounts.push(sell_amount);
+
+        let fee_token_account =
+            get_associated_token_address(&fee_authority, &leg.mint, &token_program);
+        if fee > 0 {
+            add_associated_token_account(
+                client,
+                &fee_token_account,
+                &taker,
+                &fee_authority,
+                &leg.mint,
+                &token_program,
+                &mut instructions,
+            )?;
+        }
+        accounts.extend([
+            AccountMeta::new(
+                get_associated_token_address(&taker, &leg.mint, &token_program),
+                false,
+            ),
+            AccountMeta::new(leg.token_account, false),
+            AccountMeta::new(fee_token_account, false),
+            AccountMeta::new_readonly(leg.mint, false),
+            AccountMeta::new_readonly(token_program, false),
+        ]);
+    }
+    let mut sell_amounts = Vec::with_capacity(bundle.sell_legs.len());
+    for leg in &bundle.sell_legs {
+        let token_program = get_token_program(client, &leg.mint)?;
+        let vault_amount = get_token_amount(client, &leg.token_account)?;
+        let received_amount = program::amount_after_transfer_fee(
+            &client.get_account(&leg.mint)?.data,
+            epoch,
+            vault_amount,
+        )?;
+        println!("  buy {} of {}", received_amount, leg.mint);
+        sell_amounts.push(vault_amount);
+
+        let taker_buy_account = get_associated_token_address(&taker, &leg.mint, &token_program);
+        add_associated_token_account(
+            client,
+            &taker_buy_account,
+            &taker,
+            &taker,
+            &leg.mint,
+            &token_program,
+            &mut instructions,
+        )?;
+        accounts.extend([
+            AccountMeta::new(leg.token_account, false),
+            AccountMeta::new(taker_buy_account, false),
+            AccountMeta::new(leg.mint, false),
+            AccountMeta::new_readonly(token_program, false),
+        ]);
+    }
+    println!("  from user {}", bundle.poster);
+    if let Some(expires_at) = bundle.expires_at {
+        println!("  offer expires at unix time {}", expires_at);
+    }
+    if !take.force && !ask_to_continue()? {
+        return Err("Trade aborted".into());
+    }
+
+    instructions.push(Instruction::new_with_borsh(
+        program_id(),
+        &program::Instruction::TakeBundle {
+            sell_amounts,
+            buy_amounts,
+        },
+        accounts,
+    ));
+    execute(client, &take.taker, &instructions, vec![&take.taker])
+}
+
+#[allow(clippy::too_many_arguments)]
 fn take_trade_instruction(
     take: &Take,
     escrow: &Escrow,
+    token_programs: &TokenPrograms,
     taker_sell_account: Pubkey,
     taker_buy_account: Pubkey,
     buy_amount: u64,
-    pda: Pubkey,
+    sell_amount: u64,
+    fee_token_account: Pubkey,
 ) -> Instruction {
     Instruction::new_with_borsh(
         program_id(),
         &program::Instruction::Take {
             buy_amount,
-            sell_amount: escrow.buy_amount,
+            sell_amount,
+            version: escrow.version,
         },
-        vec![
-            AccountMeta::new_readonly(take.taker.pubkey(), true),
-            AccountMeta::new(taker_sell_account, false),
-            AccountMeta::new(taker_buy_account, false),
-            AccountMeta::new(escrow.token_account, false),
-            AccountMeta::new(escrow.poster, false),
-            AccountMeta::new(escrow.poster_buy_account, false),
-            AccountMeta::new(take.escrow_account, false),
-            AccountMeta::new_readonly(spl_token::ID, false),
-            AccountMeta::new_readonly(pda, false),
-        ],
+        take_trade_accounts(
+            &take.taker.pubkey(),
+            &take.escrow_account,
+            escrow,
+            token_programs,
+            taker_sell_account,
+            taker_buy_account,
+            fee_token_account,
+        ),
     )
 }
 
+/// Accounts of a `Take`, which `AcceptQuote` also passes on
+fn take_trade_accounts(
+    taker: &Pubkey,
+    escrow_account: &Pubkey,
+    escrow: &Escrow,
+    token_programs: &TokenPrograms,
+    taker_sell_account: Pubkey,
+    taker_buy_account: Pubkey,
+    fee_token_account: Pubkey,
+) -> Vec<AccountMeta> {
+    let (pda, _) = program::find_escrow_authority(escrow_account, &escrow.poster, &program_id());
+    let (config_account, _) = program::find_config_address(&program_id());
+    let (unwrap_account, _) = program::find_unwrap_account(escrow_account, taker, &program_id());
+    vec![
+        AccountMeta::new(*taker, true),
+        AccountMeta::new(taker_sell_account, false),
+        AccountMeta::new(taker_buy_account, false),
+        AccountMeta::new(escrow.token_account, false),
+        AccountMeta::new(escrow.poster, false),
+        AccountMeta::new(escrow.poster_buy_account, false),
+        AccountMeta::new(*escrow_account, false),
+        AccountMeta::new_readonly(token_programs.sell, false),
+        AccountMeta::new_readonly(pda, false),
+        AccountMeta::new_readonly(config_account, false),
+        AccountMeta::new(fee_token_account, false),
+        AccountMeta::new_readonly(solana_sdk::system_program::ID, false),
+        AccountMeta::new(unwrap_account, false),
+        AccountMeta::new_readonly(spl_token::native_mint::ID, false),
+        AccountMeta::new(escrow.sell_mint, false),
+        AccountMeta::new_readonly(escrow.buy_mint, false),
+        AccountMeta::new_readonly(token_programs.buy, false),
+    ]
+}
+
 //
 // Cancel existing trade
 //
 
 fn do_cancel(client: &RpcClient, cancel: &Cancel) -> Result<(), Error> {
-    let escrow_info =
-        Escrow::deserialize(&mut client.get_account(&cancel.escrow_account)?.data.as_ref())?;
-    let sell_token = get_token_mint(client, &escrow_info.token_account)?;
-    let refund_account = get_associated_token_address(&cancel.poster.pubkey(), &sell_token);
+    let data = client.get_account(&cancel.escrow_account)?.data;
+    if data.len() == Bundle::LEN {
+        return do_cancel_bundle(client, cancel, Bundle::deserialize(&mut data.as_slice())?);
+    }
+    let escrow_info = Escrow::deserialize(&mut data.as_slice())?;
+    let token_program = get_token_program(client, &escrow_info.sell_mint)?;
+    let refund_account = match escrow_info.custody {
+        Custody::Vault => owner_account(
+            &cancel.poster.pubkey(),
+            &escrow_info.sell_mint,
+            &token_program,
+        ),
+        Custody::Delegated => escrow_info.token_account,
+    };
     let instructions = [cancel_trade_instruction(
         cancel,
-        escrow_info.token_account,
+        &escrow_info,
         refund_account,
+        token_program,
     )];
     execute(client, &cancel.poster, &instructions, vec![&cancel.poster])
 }
 
+fn do_cancel_bundle(client: &RpcClient, cancel: &Cancel, bundle: Bundle) -> Result<(), Error> {
+    let poster = cancel.poster.pubkey();
+    let (pda, _) = program::find_escrow_authority(&cancel.escrow_account, &poster, &program_id());
+    let mut instructions = Vec::new();
+    let mut accounts = vec![
+        AccountMeta::new(poster, true),
+        AccountMeta::new(cancel.escrow_account, false),
+        AccountMeta::new_readonly(pda, false),
+    ];
+    for leg in &bundle.sell_legs {
+        let token_program = get_token_program(client, &leg.mint)?;
+        let refund_account = get_associated_token_address(&poster, &leg.mint, &token_program);
+        add_associated_token_account(
+            client,
+            &refund_account,
+            &poster,
+            &poster,
+            &leg.mint,
+            &token_program,
+            &mut instructions,
+        )?;
+        accounts.extend([
+            AccountMeta::new(leg.token_account, false),
+            AccountMeta::new(refund_account, false),
+            AccountMeta::new(leg.mint, false),
+            AccountMeta::new_readonly(token_program, false),
+        ]);
+    }
+    instructions.push(Instruction::new_with_borsh(
+        program_id(),
+        &program::Instruction::CancelBundle {},
+        accounts,
+    ));
+    execute(client, &cancel.poster, &instructions, vec![&cancel.poster])
+}
+
 fn cancel_trade_instruction(
     cancel: &Cancel,
-    token_account: Pubkey,
+    escrow: &Escrow,
     refund_account: Pubkey,
+    token_program: Pubkey,
 ) -> Instruction {
-    let (pda, _) = Pubkey::find_program_address(&[program::ESCROW_SEED], &program_id());
+    let (pda, _) = program::find_escrow_authority(
+        &cancel.escrow_account,
+        &cancel.poster.pubkey(),
+        &program_id(),
+    );
     Instruction::new_with_borsh(
         program_id(),
         &program::Instruction::Cancel {},
         vec![
-            AccountMeta::new_readonly(cancel.poster.pubkey(), true),
-            AccountMeta::new(token_account, false),
+            AccountMeta::new(cancel.poster.pubkey(), true),
+            AccountMeta::new(escrow.token_account, false),
             AccountMeta::new(cancel.escrow_account, false),
             AccountMeta::new(refund_account, false),
-            AccountMeta::new_readonly(spl_token::ID, false),
+            AccountMeta::new_readonly(token_program, false),
             AccountMeta::new_readonly(pda, false),
+            AccountMeta::new(escrow.sell_mint, false),
         ],
     )
 }
 
 //
-// Common functions
+// Amend existing trade
 //
 
-fn program_id() -> Pubkey {
-    Pubkey::from_str("77zL4LfjPjZbeCb8baAQ1pDvcWxNKxDFcVoJz5cxSFCv").unwrap()
+fn do_amend(client: &RpcClient, amend: &Amend) -> Result<(), Error> {
+    let data = client.get_account(&amend.escrow_account)?.data;
+    if data.len() == Bundle::LEN {
+        return Err("Bundles can't be amended, cancel and post them again".into());
+    }
+    let escrow_info = Escrow::deserialize(&mut data.as_slice())?;
+    let token_program = get_token_program(client, &escrow_info.sell_mint)?;
+    let sell_account = match escrow_info.custody {
+        Custody::Vault => owner_account(
+            &amend.poster.pubkey(),
+            &escrow_info.sell_mint,
+            &token_program,
+        ),
+        Custody::Delegated => escrow_info.token_account,
+    };
+    let (pda, _) = program::find_escrow_authority(
+        &amend.escrow_account,
+        &amend.poster.pubkey(),
+        &program_id(),
+    );
+    let (unwrap_account, _) =
+        program::find_unwrap_account(&amend.escrow_account, &amend.poster.pubkey(), &program_id());
+    let instructions = [Instruction::new_with_borsh(
+        program_id(),
+        &program::Instruction::Amend {
+            buy_amount: amend.buy_amount * LAMPORTS_PER_SOL,
+            sell_amount: amend.sell_amount.map(|amount| amount * LAMPORTS_PER_SOL),
+        },
+        vec![
+            AccountMeta::new(amend.poster.pubkey(), true),
+            AccountMeta::new(amend.escrow_account, false),
+            AccountMeta::new(escrow_info.token_account, false),
+            AccountMeta::new(sell_account, false),
+            AccountMeta::new_readonly(token_program, false),
+            AccountMeta::new_readonly(pda, false),
+            AccountMeta::new_readonly(solana_sdk::system_program::ID, false),
+            AccountMeta::new(unwrap_account, false),
+            AccountMeta::new_readonly(spl_token::native_mint::ID, false),
+            AccountMeta::new_readonly(escrow_info.sell_mint, false),
+        ],
+    )];
+    execute(client, &amend.poster, &instructions, vec![&amend.poster])
 }
 
-fn add_associated_token_account(
+//
+// Reclaim expired trades
+//
+
+fn do_crank(client: &RpcClient, crank: &Crank) -> Result<(), Error> {
+    let now = cluster_time(client)?;
+    let expired = get_escrows(client)?
+        .into_iter()
+        .filter(|(_, escrow)| escrow.is_initialized && escrow.is_expired(now));
+    for (escrow_account, escrow) in expired {
+        let token_program = get_token_program(client, &escrow.sell_mint)?;
+        let refund_account = match escrow.custody {
+            Custody::Vault => owner_account(&escrow.poster, &escrow.sell_mint, &token_program),
+            Custody::Delegated => escrow.token_account,
+        };
+        if client.get_account(&refund_account).is_err() {
+            println!(
+                "Skipping escrow {}: poster has no token account to refund into",
+                escrow_account
+            );
+            continue;
+        }
+        let instructions = [reclaim_trade_instruction(
+            crank,
+            &escrow,
+            escrow_account,
+            refund_account,
+            token_program,
+        )];
+        match execute(client, &crank.caller, &instructions, vec![&crank.caller]) {
+            Ok(()) => println!(
+                "Reclaimed escrow {} for {} lamports",
+                escrow_account,
+                program::RECLAIM_REWARD
+            ),
+            Err(error) => println!("Failed to reclaim escrow {}: {}", escrow_account, error),
+        }
+    }
+
+    let expired = get_bundles(client)?
+        .into_iter()
+        .filter(|(_, bundle)| bundle.is_initialized && bundle.is_expired(now));
+    for (bundle_account, bundle) in expired {
+        let instruction = match reclaim_bundle_instruction(client, crank, &bundle, bundle_account)?
+        {
+            Some(instruction) => instruction,
+            None => {
+                println!(
+                    "Skipping bundle {}: poster has no token account to refund into",
+                    bundle_account
+                );
+                continue;
+            }
+        };
+        match execute(client, &crank.caller, &[instruction], vec![&crank.caller]) {
+            Ok(()) => println!(
+                "Reclaimed bundle {} for {} lamports",
+                bundle_account,
+                program::RECLAIM_REWARD
+            ),
+            Err(error) => println!("Failed to reclaim bundle {}: {}", bundle_account, error),
+        }
+    }
+    Ok(())
+}
+
+/// `ReclaimBundle` refunding into the poster's associated token accounts, or `None` if any of them is missing
+fn reclaim_bundle_instruction(
     client: &RpcClient,
-    associated_account_address: &Pubkey,
-    funding_address: &Pubkey,
-    wallet_address: &Pubkey,
-    spl_token_mint_address: &Pubkey,
-    instructions: &mut Vec<Instruction>,
-) -> Result<(), Error> {
-    let config = CommitmentConfig {
-        commitment: CommitmentLevel::Processed, // TODO is this the right way to check if account exists?
-    };
-    if client
-        .get_account_with_commitment(associated_account_address, config)?
-        .value
-        .is_some()
-    {
-        return Ok(());
+    crank: &Crank,
+    bundle: &Bundle,
+    bundle_account: Pubkey,
+) -> Result<Option<Instruction>, Error> {
+    let (pda, _) = program::find_escrow_authority(&bundle_account, &bundle.poster, &program_id());
+    let mut accounts = vec![
+        AccountMeta::new(crank.caller.pubkey(), true),
+        AccountMeta::new(bundle_account, false),
+        AccountMeta::new(bundle.poster, false),
+        AccountMeta::new_readonly(pda, false),
+    ];
+    for leg in &bundle.sell_legs {
+        let token_program = get_token_program(client, &leg.mint)?;
+        let refund_account =
+            get_associated_token_address(&bundle.poster, &leg.mint, &token_program);
+        if client.get_account(&refund_account).is_err() {
+            return Ok(None);
+        }
+        accounts.extend([
+            AccountMeta::new(leg.token_account, false),
+            AccountMeta::new(refund_account, false),
+            AccountMeta::new(leg.mint, false),
+            AccountMeta::new_readonly(token_program, false),
+        ]);
     }
-    instructions.push(
-        spl_associated_token_account::instruction::create_associated_token_account(
-            funding_address,
-            wallet_address,
-            spl_token_mint_address,
-        ),
-    );
+    Ok(Some(Instruction::new_with_borsh(
+        program_id(),
+        &program::Instruction::ReclaimBundle {},
+        accounts,
+    )))
+}
+
+fn reclaim_trade_instruction(
+    crank: &Crank,
+    escrow: &Escrow,
+    escrow_account: Pubkey,
+    refund_account: Pubkey,
+    token_program: Pubkey,
+) -> Instruction {
+    let mut accounts = vec![AccountMeta::new(crank.caller.pubkey(), true)];
+    accounts.extend(refund_accounts(
+        escrow,
+        escrow_account,
+        refund_account,
+        token_program,
+    ));
+    Instruction::new_with_borsh(program_id(), &program::Instruction::Reclaim {}, accounts)
+}
+
+/// Accounts of a `Reclaim` after the caller, which `AcceptQuote` also takes for every quote it refunds
+fn refund_accounts(
+    escrow: &Escrow,
+    escrow_account: Pubkey,
+    refund_account: Pubkey,
+    token_program: Pubkey,
+) -> Vec<AccountMeta> {
+    let (pda, _) = program::find_escrow_authority(&escrow_account, &escrow.poster, &program_id());
+    vec![
+        AccountMeta::new(escrow.token_account, false),
+        AccountMeta::new(escrow_account, false),
+        AccountMeta::new(escrow.poster, false),
+        AccountMeta::new(refund_account, false),
+        AccountMeta::new_readonly(token_program, false),
+        AccountMeta::new_readonly(pda, false),
+        AccountMeta::new(escrow.sell_mint, false),
+    ]
+}
+
+//
+// Match crossing trades
+//
+
+fn do_match(client: &RpcClient, matcher: &Match) -> Result<(), Error> {
+    let now = cluster_time(client)?;
+    let (config_account, _) = get_config(client)?;
+    let mut offers = Vec::new();
+    for (escrow_account, escrow) in get_escrows(client)? {
+        if !escrow.is_initialized
+            || escrow.is_expired(now)
+            || escrow.poster_buy_account == escrow.poster
+            || escrow.english_auction.is_some()
+            || escrow.sealed_auction.is_some()
+            || escrow.call_option.is_some()
+            || escrow.rfq.is_some()
+        {
+            continue;
+        }
+        let remaining_amount = get_remaining_amount(client, &escrow_account, &escrow)?;
+        if remaining_amount > 0 {
+            offers.push((escrow_account, escrow, remaining_amount));
+        }
+    }
+
+    let mut matched = vec![false; offers.len()];
+    for i in 0..offers.len() {
+        for j in i + 1..offers.len() {
+            if matched[i] || matched[j] {
+                continue;
+            }
+            let (first_account, first, first_remaining) = &offers[i];
+            let (second_account, second, second_remaining) = &offers[j];
+            if first.sell_mint != second.buy_mint
+                || first.buy_mint != second.sell_mint
+                || matches!(first.allowed_taker, Some(taker) if taker != second.poster)
+                || matches!(second.allowed_taker, Some(taker) if taker != first.poster)
+            {
+                continue;
+            }
+            // Dutch auctions are matched at their price now, as the program does
+            let fill = match program::match_fill(
+                *first_remaining,
+                first.current_buy_amount(now),
+                *second_remaining,
+                second.current_buy_amount(now),
+            ) {
+                Some(fill) => fill,
+                None => continue,
+            };
+
+            // the fully filled offer pays the other's price and the surplus, and each escrow
+            // pays the fee on what the other poster receives, at that offer's rate
+            let (first_paid, first_surplus, second_paid, second_surplus) = if fill.second_filled {
+                (fill.amount, 0, fill.price, fill.surplus)
+            } else {
+                (fill.price, fill.surplus, fill.amount, 0)
+            };
+            let first_fee = program::token_fee(first_paid, second.fee_bps);
+            let second_fee = program::token_fee(second_paid, first.fee_bps);
+            let mut instructions = Vec::new();
+            let mut accounts = vec![
+                AccountMeta::new_readonly(matcher.matcher.pubkey(), true),
+                AccountMeta::new_readonly(config_account, false),
+            ];
+            accounts.extend(match_side_accounts(
+                client,
+                &matcher.matcher.pubkey(),
+                first_account,
+                first,
+                first_fee,
+                first_surplus,
+                &mut instructions,
+            )?);
+            accounts.extend(match_side_accounts(
+                client,
+                &matcher.matcher.pubkey(),
+                second_account,
+                second,
+                second_fee,
+                second_surplus,
+                &mut instructions,
+            )?);
+            instructions.push(Instruction::new_with_borsh(
+                program_id(),
+                &program::Instruction::Match {},
+                accounts,
+            ));
+            match execute(
+                client,
+                &matcher.matcher,
+                &instructions,
+                vec![&matcher.matcher],
+            ) {
+                Ok(()) => {
+                    println!(
+                        "Matched escrows {} and {} for a surplus of {}",
+                        first_account, second_account, fill.surplus
+                    );
+                    matched[i] = true;
+                    matched[j] = true;
+                }
+                Err(error) => println!(
+                    "Failed to match escrows {} and {}: {}",
+                    first_account, second_account, error
+                ),
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Accounts `Match` expects for one of its escrows, creating the fee and matcher token accounts it pays into
+fn match_side_accounts(
+    client: &RpcClient,
+    matcher: &Pubkey,
+    escrow_account: &Pubkey,
+    escrow: &Escrow,
+    fee: u64,
+    surplus: u64,
+    instructions: &mut Vec<Instruction>,
+) -> Result<Vec<AccountMeta>, Error> {
+    let token_program = get_token_program(client, &escrow.sell_mint)?;
+    let (pda, _) = program::find_escrow_authority(escrow_account, &escrow.poster, &program_id());
+    let (fee_authority, _) = program::find_fee_authority(&program_id());
+    let fee_token_account =
+        get_associated_token_address(&fee_authority, &escrow.sell_mint, &token_program);
+    if fee > 0 {
+        add_associated_token_account(
+            client,
+            &fee_token_account,
+            matcher,
+            &fee_authority,
+            &escrow.sell_mint,
+            &token_program,
+            instructions,
+        )?;
+    }
+    let matcher_account = get_associated_token_address(matcher, &escrow.sell_mint, &token_program);
+    if surplus > 0 {
+        add_associated_token_account(
+            client,
+            &matcher_account,
+            matcher,
+            matcher,
+            &escrow.sell_mint,
+            &token_program,
+            instructions,
+        )?;
+    }
+    Ok(vec![
+        AccountMeta::new(*escrow_account, false),
+        AccountMeta::new(escrow.token_account, false),
+        AccountMeta::new(escrow.poster, false),
+        AccountMeta::new(escrow.poster_buy_account, false),
+        AccountMeta::new_readonly(pda, false),
+        AccountMeta::new(escrow.sell_mint, false),
+        AccountMeta::new_readonly(token_program, false),
+        AccountMeta::new(fee_token_account, false),
+        AccountMeta::new(matcher_account, false),
+    ])
+}
+
+//
+// Order book markets
+//
+
+fn do_create_market(client: &RpcClient, create: &MarketCreate) -> Result<(), Error> {
+    let (market_account, _) =
+        program::find_market_address(&create.base, &create.quote, &program_id());
+    let (base_vault, _) = program::find_market_vault(&market_account, &create.base, &program_id());
+    let (quote_vault, _) =
+        program::find_market_vault(&market_account, &create.quote, &program_id());
+    println!("Creating market account {}", market_account);
+    let instruction = Instruction::new_with_borsh(
+        program_id(),
+        &program::Instruction::CreateMarket {},
+        vec![
+            AccountMeta::new(create.payer.pubkey(), true),
+            AccountMeta::new(market_account, false),
+            AccountMeta::new_readonly(create.base, false),
+            AccountMeta::new_readonly(create.quote, false),
+            AccountMeta::new(base_vault, false),
+            AccountMeta::new(quote_vault, false),
+            AccountMeta::new_readonly(get_token_program(client, &create.base)?, false),
+            AccountMeta::new_readonly(get_token_program(client, &create.quote)?, false),
+            AccountMeta::new_readonly(solana_sdk::system_program::ID, false),
+        ],
+    );
+    execute(client, &create.payer, &[instruction], vec![&create.payer])
+}
+
+fn do_show_market(client: &RpcClient, show: &MarketShow) -> Result<(), Error> {
+    let (market_account, market) = get_market(client, &show.base, &show.quote)?;
+    println!("Market account {}", market_account);
+    for (name, side) in [("Asks", Side::Ask), ("Bids", Side::Bid)] {
+        println!("{}:", name);
+        for order in market.orders(side) {
+            println!(
+                "  order {}: {} of {} for {} of {} from {}",
+                order.id,
+                order.base_amount,
+                market.base_mint,
+                order.quote_amount,
+                market.quote_mint,
+                order.owner
+            );
+        }
+    }
+    Ok(())
+}
+
+fn do_place_order(client: &RpcClient, place: &MarketPlace) -> Result<(), Error> {
+    let (market_account, market) = get_market(client, &place.base, &place.quote)?;
+    let owner = place.owner.pubkey();
+    let (vault, mint) = match place.side {
+        Side::Ask => (market.base_vault, market.base_mint),
+        Side::Bid => (market.quote_vault, market.quote_mint),
+    };
+    let token_program = get_token_program(client, &mint)?;
+    let source_account = get_associated_token_address(&owner, &mint, &token_program);
+    // a full side of the book refunds its worst order to make room, if the new one beats it
+    let evicted_owner = match market.orders(place.side) {
+        orders if orders.len() >= program::MAX_ORDERS => orders.last().unwrap().owner,
+        _ => owner,
+    };
+    let (evicted_claim_vault, _) =
+        program::find_claim_vault(&market_account, &evicted_owner, &mint, &program_id());
+    let (config_account, _) = program::find_config_address(&program_id());
+
+    let instruction = Instruction::new_with_borsh(
+        program_id(),
+        &program::Instruction::PlaceOrder {
+            side: place.side,
+            base_amount: place.base_amount * LAMPORTS_PER_SOL,
+            quote_amount: place.quote_amount * LAMPORTS_PER_SOL,
+        },
+        vec![
+            AccountMeta::new(owner, true),
+            AccountMeta::new(market_account, false),
+            AccountMeta::new(source_account, false),
+            AccountMeta::new(vault, false),
+            AccountMeta::new_readonly(mint, false),
+            AccountMeta::new_readonly(token_program, false),
+            AccountMeta::new(evicted_claim_vault, false),
+            AccountMeta::new_readonly(solana_sdk::system_program::ID, false),
+            AccountMeta::new_readonly(config_account, false),
+        ],
+    );
+    execute(client, &place.owner, &[instruction], vec![&place.owner])?;
+    println!("Placed order {}", market.next_order_id);
+    Ok(())
+}
+
+fn do_cancel_order(client: &RpcClient, cancel: &MarketCancel) -> Result<(), Error> {
+    let (market_account, market) = get_market(client, &cancel.base, &cancel.quote)?;
+    let (side, order) = [Side::Bid, Side::Ask]
+        .into_iter()
+        .find_map(|side| {
+            let order = market
+                .orders(side)
+                .iter()
+                .find(|order| order.id == cancel.order_id)?;
+            Some((side, *order))
+        })
+        .ok_or("Order is not in the book")?;
+    let owner = cancel.owner.pubkey();
+    if order.owner != owner {
+        return Err("Order belongs to another owner".into());
+    }
+    let (vault, mint) = match side {
+        Side::Ask => (market.base_vault, market.base_mint),
+        Side::Bid => (market.quote_vault, market.quote_mint),
+    };
+    let token_program = get_token_program(client, &mint)?;
+    let refund_account = get_associated_token_address(&owner, &mint, &token_program);
+
+    let mut instructions = Vec::new();
+    add_associated_token_account(
+        client,
+        &refund_account,
+        &owner,
+        &owner,
+        &mint,
+        &token_program,
+        &mut instructions,
+    )?;
+    instructions.push(Instruction::new_with_borsh(
+        program_id(),
+        &program::Instruction::CancelOrder {
+            side,
+            order_id: cancel.order_id,
+        },
+        vec![
+            AccountMeta::new_readonly(owner, true),
+            AccountMeta::new(market_account, false),
+            AccountMeta::new(vault, false),
+            AccountMeta::new(refund_account, false),
+            AccountMeta::new_readonly(mint, false),
+            AccountMeta::new_readonly(token_program, false),
+        ],
+    ));
+    execute(client, &cancel.owner, &instructions, vec![&cancel.owner])
+}
+
+fn do_match_orders(client: &RpcClient, matcher: &MarketMatch) -> Result<(), Error> {
+    let (market_account, mut market) = get_market(client, &matcher.base, &matcher.quote)?;
+    let (config_account, _) = get_config(client)?;
+    let base_token_program = get_token_program(client, &market.base_mint)?;
+    let quote_token_program = get_token_program(client, &market.quote_mint)?;
+
+    // work out the fills the same way the program will, to pass the claim vaults they pay into
+    let fills = program::match_orders(&mut market, matcher.limit as usize);
+    if fills.is_empty() {
+        return Err("No orders in the book cross".into());
+    }
+
+    let mut instructions = Vec::new();
+    let (fee_authority, _) = program::find_fee_authority(&program_id());
+    let fee_token_account =
+        get_associated_token_address(&fee_authority, &market.quote_mint, &quote_token_program);
+    if fills.iter().any(|fill| fill.ask_fee_bps > 0) {
+        add_associated_token_account(
+            client,
+            &fee_token_account,
+            &matcher.matcher.pubkey(),
+            &fee_authority,
+            &market.quote_mint,
+            &quote_token_program,
+            &mut instructions,
+        )?;
+    }
+    let mut accounts = vec![
+        AccountMeta::new(matcher.matcher.pubkey(), true),
+        AccountMeta::new(market_account, false),
+        AccountMeta::new(market.base_vault, false),
+        AccountMeta::new(market.quote_vault, false),
+        AccountMeta::new_readonly(market.base_mint, false),
+        AccountMeta::new_readonly(market.quote_mint, false),
+        AccountMeta::new_readonly(base_token_program, false),
+        AccountMeta::new_readonly(quote_token_program, false),
+        AccountMeta::new_readonly(config_account, false),
+        AccountMeta::new(fee_token_account, false),
+        AccountMeta::new_readonly(solana_sdk::system_program::ID, false),
+    ];
+    for fill in &fills {
+        println!(
+            "Filling {} base tokens for {} quote tokens",
+            fill.base_amount, fill.quote_amount
+        );
+        let claim_vault = |owner: &Pubkey, mint: &Pubkey| {
+            program::find_claim_vault(&market_account, owner, mint, &program_id()).0
+        };
+        accounts.extend([
+            AccountMeta::new(claim_vault(&fill.ask_owner, &market.quote_mint), false),
+            AccountMeta::new(claim_vault(&fill.bid_owner, &market.base_mint), false),
+            AccountMeta::new(claim_vault(&fill.bid_owner, &market.quote_mint), false),
+        ]);
+    }
+    instructions.push(Instruction::new_with_borsh(
+        program_id(),
+        &program::Instruction::MatchOrders {
+            limit: matcher.limit,
+        },
+        accounts,
+    ));
+    execute(
+        client,
+        &matcher.matcher,
+        &instructions,
+        vec![&matcher.matcher],
+    )
+}
+
+fn get_market(
+    client: &RpcClient,
+    base: &Pubkey,
+    quote: &Pubkey,
+) -> Result<(Pubkey, Market), Error> {
+    let (market_account, _) = program::find_market_address(base, quote, &program_id());
+    let market = Market::deserialize(&mut client.get_account(&market_account)?.data.as_slice())?;
+    Ok((market_account, market))
+}
+
+//
+// Auctions
+//
+
+fn do_bid(client: &RpcClient, bid: &Bid) -> Result<(), Error> {
+    let escrow = get_auction(client, &bid.escrow_account)?;
+    if escrow.sealed_auction.is_some() {
+        return do_commit_bid(client, bid, &escrow);
+    }
+    if bid.deposit.is_some() {
+        return Err("Only sealed-bid auctions take a --deposit".into());
+    }
+    let auction = escrow.english_auction.as_ref().unwrap();
+    if cluster_time(client)? >= auction.end_time {
+        return Err("Auction has ended".into());
+    }
+    let amount = bid.amount * LAMPORTS_PER_SOL;
+    match auction.min_bid() {
+        Some(min_bid) if amount >= min_bid => {}
+        _ => return Err("Bid has to be at least the reserve price, and beat the highest bid by the minimum increment".into()),
+    }
+
+    let bidder = bid.bidder.pubkey();
+    let token_program = get_token_program(client, &escrow.buy_mint)?;
+    let bid_account = get_associated_token_address(&bidder, &escrow.buy_mint, &token_program);
+    let (claim_vault, _) = program::find_claim_vault(
+        &bid.escrow_account,
+        &bidder,
+        &escrow.buy_mint,
+        &program_id(),
+    );
+    let (bid_vault, _) = program::find_bid_vault(&bid.escrow_account, &program_id());
+    let (pda, _) =
+        program::find_escrow_authority(&bid.escrow_account, &escrow.poster, &program_id());
+    let outbid_claim_vault = match &auction.bid {
+        Some(highest) => {
+            program::find_claim_vault(
+                &bid.escrow_account,
+                &highest.bidder,
+                &escrow.buy_mint,
+                &program_id(),
+            )
+            .0
+        }
+        None => claim_vault,
+    };
+    println!(
+        "Bidding {} of {} from {}",
+        amount, escrow.buy_mint, bid_account
+    );
+
+    let instructions = [Instruction::new_with_borsh(
+        program_id(),
+        &program::Instruction::Bid { amount },
+        vec![
+            AccountMeta::new(bidder, true),
+            AccountMeta::new(bid.escrow_account, false),
+            AccountMeta::new(bid_account, false),
+            AccountMeta::new(claim_vault, false),
+            AccountMeta::new(bid_vault, false),
+            AccountMeta::new(outbid_claim_vault, false),
+            AccountMeta::new_readonly(escrow.buy_mint, false),
+            AccountMeta::new_readonly(token_program, false),
+            AccountMeta::new_readonly(pda, false),
+            AccountMeta::new_readonly(solana_sdk::system_program::ID, false),
+        ],
+    )];
+    execute(client, &bid.bidder, &instructions, vec![&bid.bidder])
+}
+
+/// Commits to a hidden bid on a sealed-bid auction, saving the salt hiding it for `reveal`
+fn do_commit_bid(client: &RpcClient, bid: &Bid, escrow: &Escrow) -> Result<(), Error> {
+    let auction = escrow.sealed_auction.as_ref().unwrap();
+    if cluster_time(client)? >= auction.commit_end {
+        return Err("Bidding on the auction has closed".into());
+    }
+    let amount = bid.amount * LAMPORTS_PER_SOL;
+    let deposit = bid
+        .deposit
+        .map(|deposit| deposit * LAMPORTS_PER_SOL)
+        .unwrap_or(amount);
+    if amount < auction.reserve_price || amount > deposit {
+        return Err("Bid has to be at least the reserve price, and at most the deposit".into());
+    }
+
+    let bidder = bid.bidder.pubkey();
+    let (sealed_bid_account, _) =
+        program::find_sealed_bid_address(&bid.escrow_account, &bidder, &program_id());
+    if client
+        .get_account_with_commitment(&sealed_bid_account, client.commitment())?
+        .value
+        .is_some()
+    {
+        return Err("Bidder has already bid on this auction".into());
+    }
+    // a new keypair's secret key is 32 random bytes from the OS
+    let mut salt = [0; 32];
+    salt.copy_from_slice(&Keypair::new().to_bytes()[..32]);
+    // saved before bidding, so a bid that lands can always be revealed
+    let path = sealed_bid_path(&bid.escrow_account, &bidder)?;
+    save_sealed_bid(&path, amount, &salt)?;
+    println!("Saved the bid and its salt to {}", path.display());
+
+    let token_program = get_token_program(client, &escrow.buy_mint)?;
+    let deposit_account = get_associated_token_address(&bidder, &escrow.buy_mint, &token_program);
+    let (claim_vault, _) = program::find_claim_vault(
+        &bid.escrow_account,
+        &bidder,
+        &escrow.buy_mint,
+        &program_id(),
+    );
+    let (bid_vault, _) = program::find_bid_vault(&bid.escrow_account, &program_id());
+    let (pda, _) =
+        program::find_escrow_authority(&bid.escrow_account, &escrow.poster, &program_id());
+    println!(
+        "Sealing a bid of {} of {}, depositing {} from {}",
+        amount, escrow.buy_mint, deposit, deposit_account
+    );
+
+    let instructions = [Instruction::new_with_borsh(
+        program_id(),
+        &program::Instruction::CommitBid {
+            commitment: program::sealed_bid_commitment(&bidder, amount, &salt),
+            deposit,
+        },
+        vec![
+            AccountMeta::new(bidder, true),
+            AccountMeta::new(bid.escrow_account, false),
+            AccountMeta::new(deposit_account, false),
+            AccountMeta::new(claim_vault, false),
+            AccountMeta::new(bid_vault, false),
+            AccountMeta::new(sealed_bid_account, false),
+            AccountMeta::new_readonly(escrow.buy_mint, false),
+            AccountMeta::new_readonly(token_program, false),
+            AccountMeta::new_readonly(pda, false),
+            AccountMeta::new_readonly(solana_sdk::system_program::ID, false),
+        ],
+    )];
+    execute(client, &bid.bidder, &instructions, vec![&bid.bidder])
+}
+
+/// Reveals the bidder's sealed bid on an auction, from the amount and salt `bid` saved
+fn do_reveal(client: &RpcClient, reveal: &Reveal) -> Result<(), Error> {
+    let escrow = get_auction(client, &reveal.escrow_account)?;
+    let auction = escrow
+        .sealed_auction
+        .as_ref()
+        .ok_or("Offer is not a sealed-bid auction, there are no bids to reveal")?;
+    let now = cluster_time(client)?;
+    if now < auction.commit_end || now >= auction.reveal_end {
+        return Err(format!(
+            "Bids can only be revealed from unix time {} until {}",
+            auction.commit_end, auction.reveal_end
+        )
+        .into());
+    }
+    let bidder = reveal.bidder.pubkey();
+    let path = sealed_bid_path(&reveal.escrow_account, &bidder)?;
+    let (amount, salt) = read_sealed_bid(&path)?;
+    let (sealed_bid_account, _) =
+        program::find_sealed_bid_address(&reveal.escrow_account, &bidder, &program_id());
+    let sealed_bid =
+        SealedBid::deserialize(&mut client.get_account(&sealed_bid_account)?.data.as_slice())?;
+    let token_program = get_token_program(client, &escrow.buy_mint)?;
+    let (bid_vault, _) = program::find_bid_vault(&reveal.escrow_account, &program_id());
+    let (pda, _) =
+        program::find_escrow_authority(&reveal.escrow_account, &escrow.poster, &program_id());
+    let (claim_vault, _) = program::find_claim_vault(
+        &reveal.escrow_account,
+        &bidder,
+        &escrow.buy_mint,
+        &program_id(),
+    );
+    let best_claim_vault = match &auction.best {
+        Some(best) => {
+            program::find_claim_vault(
+                &reveal.escrow_account,
+                &best.bidder,
+                &escrow.buy_mint,
+                &program_id(),
+            )
+            .0
+        }
+        None => claim_vault,
+    };
+    println!(
+        "Revealing a bid of {} of {}, deposited {}",
+        amount, escrow.buy_mint, sealed_bid.deposit
+    );
+
+    let instructions = [Instruction::new_with_borsh(
+        program_id(),
+        &program::Instruction::RevealBid { amount, salt },
+        vec![
+            AccountMeta::new(bidder, true),
+            AccountMeta::new(reveal.escrow_account, false),
+            AccountMeta::new(sealed_bid_account, false),
+            AccountMeta::new(bid_vault, false),
+            AccountMeta::new(claim_vault, false),
+            AccountMeta::new(best_claim_vault, false),
+            AccountMeta::new_readonly(escrow.buy_mint, false),
+            AccountMeta::new_readonly(token_program, false),
+            AccountMeta::new_readonly(pda, false),
+            AccountMeta::new_readonly(solana_sdk::system_program::ID, false),
+        ],
+    )];
+    execute(client, &reveal.bidder, &instructions, vec![&reveal.bidder])?;
+    std::fs::remove_file(&path)?;
+    Ok(())
+}
+
+fn do_settle(client: &RpcClient, settle: &Settle) -> Result<(), Error> {
+    let escrow = get_auction(client, &settle.escrow_account)?;
+    let now = cluster_time(client)?;
+    let bid = match (&escrow.english_auction, &escrow.sealed_auction) {
+        (Some(auction), _) => {
+            if now < auction.end_time {
+                return Err(
+                    format!("Auction doesn't end until unix time {}", auction.end_time).into(),
+                );
+            }
+            auction.bid
+        }
+        (None, Some(auction)) => {
+            if now < auction.reveal_end {
+                return Err(format!(
+                    "Bids can be revealed until unix time {}",
+                    auction.reveal_end
+                )
+                .into());
+            }
+            release_deposits(client, settle, &escrow, auction)?;
+            auction.best
+        }
+        (None, None) => return Err("Offer is not an auction".into()),
+    };
+    let bid = bid.ok_or("Auction has no bids, the poster can cancel it instead")?;
+    println!(
+        "Selling to the highest bid of {} by {}",
+        bid.amount, bid.bidder
+    );
+
+    let caller = settle.caller.pubkey();
+    let (config_account, _) = get_config(client)?;
+    let token_programs = get_token_programs(client, &escrow)?;
+    let (bid_vault, _) = program::find_bid_vault(&settle.escrow_account, &program_id());
+    let (pda, _) =
+        program::find_escrow_authority(&settle.escrow_account, &escrow.poster, &program_id());
+    let (fee_authority, _) = program::find_fee_authority(&program_id());
+    let fee_token_account =
+        get_associated_token_address(&fee_authority, &escrow.buy_mint, &token_programs.buy);
+    let (winner_claim_vault, _) = program::find_claim_vault(
+        &settle.escrow_account,
+        &bid.bidder,
+        &escrow.sell_mint,
+        &program_id(),
+    );
+    let (poster_claim_vault, _) = program::find_claim_vault(
+        &settle.escrow_account,
+        &escrow.poster,
+        &escrow.buy_mint,
+        &program_id(),
+    );
+
+    let mut instructions = Vec::new();
+    if escrow.fee_bps > 0 {
+        add_associated_token_account(
+            client,
+            &fee_token_account,
+            &caller,
+            &fee_authority,
+            &escrow.buy_mint,
+            &token_programs.buy,
+            &mut instructions,
+        )?;
+    }
+    instructions.push(Instruction::new_with_borsh(
+        program_id(),
+        &program::Instruction::Settle {},
+        vec![
+            AccountMeta::new(caller, true),
+            AccountMeta::new(settle.escrow_account, false),
+            AccountMeta::new(escrow.token_account, false),
+            AccountMeta::new(escrow.poster, false),
+            AccountMeta::new(poster_claim_vault, false),
+            AccountMeta::new(bid_vault, false),
+            AccountMeta::new(winner_claim_vault, false),
+            AccountMeta::new(bid.bidder, false),
+            AccountMeta::new(escrow.sell_mint, false),
+            AccountMeta::new_readonly(token_programs.sell, false),
+            AccountMeta::new(escrow.buy_mint, false),
+            AccountMeta::new_readonly(token_programs.buy, false),
+            AccountMeta::new_readonly(pda, false),
+            AccountMeta::new_readonly(config_account, false),
+            AccountMeta::new(fee_token_account, false),
+            AccountMeta::new_readonly(solana_sdk::system_program::ID, false),
+        ],
+    ));
+    if escrow.sealed_auction.is_some() {
+        let (refund_claim_vault, _) = program::find_claim_vault(
+            &settle.escrow_account,
+            &bid.bidder,
+            &escrow.buy_mint,
+            &program_id(),
+        );
+        instructions
+            .last_mut()
+            .unwrap()
+            .accounts
+            .push(AccountMeta::new(refund_claim_vault, false));
+    }
+    execute(client, &settle.caller, &instructions, vec![&settle.caller])
+}
+
+/// Withdraws every claim vault of the owner into their associated token account for its mint, each in a
+/// transaction of its own
+fn do_claim(client: &RpcClient, claim: &Claim) -> Result<(), Error> {
+    let owner = claim.owner.pubkey();
+    let (claim_authority, _) = program::find_claim_authority(&owner, &program_id());
+    let mut claimed = false;
+    for token_program in [spl_token::id(), spl_token_2022::id()] {
+        let claim_vaults = client.get_token_accounts_by_owner(
+            &claim_authority,
+            TokenAccountsFilter::ProgramId(token_program),
+        )?;
+        for keyed_account in claim_vaults {
+            let claim_vault = Pubkey::from_str(&keyed_account.pubkey)?;
+            let data = client.get_account(&claim_vault)?.data;
+            let claim_info = StateWithExtensions::<TokenAccount>::unpack(&data)?.base;
+            let destination_account =
+                get_associated_token_address(&owner, &claim_info.mint, &token_program);
+            println!(
+                "Withdrawing {} of {} into {}",
+                claim_info.amount, claim_info.mint, destination_account
+            );
+
+            let mut instructions = Vec::new();
+            add_associated_token_account(
+                client,
+                &destination_account,
+                &owner,
+                &owner,
+                &claim_info.mint,
+                &token_program,
+                &mut instructions,
+            )?;
+            instructions.push(Instruction::new_with_borsh(
+                program_id(),
+                &program::Instruction::WithdrawClaim {},
+                vec![
+                    AccountMeta::new(owner, true),
+                    AccountMeta::new(claim_vault, false),
+                    AccountMeta::new(destination_account, false),
+                    AccountMeta::new(claim_info.mint, false),
+                    AccountMeta::new_readonly(token_program, false),
+                    AccountMeta::new_readonly(claim_authority, false),
+                ],
+            ));
+            execute(client, &claim.owner, &instructions, vec![&claim.owner])?;
+            claimed = true;
+        }
+    }
+    if !claimed {
+        println!("Nothing to claim");
+    }
+    Ok(())
+}
+
+/// Releases the deposit of every sealed bid on the auction that wasn't revealed, as it can't be settled until
+/// they are, each in a transaction of its own
+fn release_deposits(
+    client: &RpcClient,
+    settle: &Settle,
+    escrow: &Escrow,
+    auction: &SealedAuction,
+) -> Result<(), Error> {
+    let token_program = get_token_program(client, &escrow.buy_mint)?;
+    let (bid_vault, _) = program::find_bid_vault(&settle.escrow_account, &program_id());
+    let (pda, _) =
+        program::find_escrow_authority(&settle.escrow_account, &escrow.poster, &program_id());
+    for (sealed_bid_account, sealed_bid) in get_sealed_bids(client, &settle.escrow_account)? {
+        let owner = match auction.unrevealed {
+            UnrevealedPolicy::Refund => sealed_bid.bidder,
+            UnrevealedPolicy::Forfeit => escrow.poster,
+        };
+        let (destination_account, _) = program::find_claim_vault(
+            &settle.escrow_account,
+            &owner,
+            &escrow.buy_mint,
+            &program_id(),
+        );
+        println!(
+            "Releasing the unrevealed deposit of {} by {}",
+            sealed_bid.deposit, sealed_bid.bidder
+        );
+        let instructions = [Instruction::new_with_borsh(
+            program_id(),
+            &program::Instruction::ReleaseDeposit {},
+            vec![
+                AccountMeta::new(settle.caller.pubkey(), true),
+                AccountMeta::new(settle.escrow_account, false),
+                AccountMeta::new(sealed_bid_account, false),
+                AccountMeta::new(sealed_bid.bidder, false),
+                AccountMeta::new(bid_vault, false),
+                AccountMeta::new(destination_account, false),
+                AccountMeta::new_readonly(escrow.buy_mint, false),
+                AccountMeta::new_readonly(token_program, false),
+                AccountMeta::new_readonly(pda, false),
+                AccountMeta::new_readonly(solana_sdk::system_program::ID, false),
+            ],
+        )];
+        execute(client, &settle.caller, &instructions, vec![&settle.caller])?;
+    }
+    Ok(())
+}
+
+/// Reads an escrow account, failing unless it holds an English or sealed-bid auction
+fn get_auction(client: &RpcClient, escrow_account: &Pubkey) -> Result<Escrow, Error> {
+    let data = client.get_account(escrow_account)?.data;
+    if data.len() == Bundle::LEN {
+        return Err("Offer is a bundle, not an auction".into());
+    }
+    let escrow = Escrow::deserialize(&mut data.as_slice())?;
+    if escrow.english_auction.is_none() && escrow.sealed_auction.is_none() {
+        return Err("Offer is not an auction, use `take` instead".into());
+    }
+    Ok(escrow)
+}
+
+/// File a sealed bid's amount and salt are kept in until it is revealed
+fn sealed_bid_path(escrow_account: &Pubkey, bidder: &Pubkey) -> Result<PathBuf, Error> {
+    let home =
+        std::env::var("HOME").map_err(|_| "HOME is not set, sealed bids are kept under it")?;
+    Ok(PathBuf::from(home)
+        .join(".config/escrow/sealed-bids")
+        .join(format!("{}-{}", escrow_account, bidder)))
+}
+
+fn save_sealed_bid(path: &Path, amount: u64, salt: &[u8; 32]) -> Result<(), Error> {
+    if let Some(directory) = path.parent() {
+        std::fs::create_dir_all(directory)?;
+    }
+    std::fs::write(path, format!("{} {}\n", amount, encode_hex(salt)))?;
+    Ok(())
+}
+
+fn read_sealed_bid(path: &Path) -> Result<(u64, [u8; 32]), Error> {
+    let contents = std::fs::read_to_string(path).map_err(|error| {
+        format!(
+            "Could not read the sealed bid saved in {}: {}",
+            path.display(),
+            error
+        )
+    })?;
+    let (amount, salt_hex) = contents
+        .trim()
+        .split_once(' ')
+        .ok_or("Saved sealed bid is malformed")?;
+    let salt = decode_hex(salt_hex)?
+        .try_into()
+        .map_err(|_| "Saved sealed bid's salt is malformed")?;
+    Ok((amount.parse()?, salt))
+}
+
+//
+// Covered call options
+//
+
+fn do_write_option(client: &RpcClient, write: &OptionWrite) -> Result<(), Error> {
+    let writer = write.writer.pubkey();
+    let sell_token_program = get_token_program(client, &write.sell_token)?;
+    let buy_token_program = get_token_program(client, &write.buy_token)?;
+    let sell_account = owner_account(&writer, &write.sell_token, &sell_token_program);
+    // the premium and strike are paid into a token account, so SOL is received wrapped
+    let buy_account = get_associated_token_address(&writer, &write.buy_token, &buy_token_program);
+    let offer_id = match write.id {
+        Some(id) => id,
+        None => next_option_id(client, &writer)?,
+    };
+    let (escrow_account, _) = program::find_escrow_address(&writer, offer_id, &program_id());
+    let (vault_account, _) = program::find_escrow_vault(&escrow_account, &program_id());
+    let (option_mint, _) = program::find_option_mint(&escrow_account, &program_id());
+    println!("Creating escrow account {}", escrow_account);
+    println!("Offer id {}/{}", writer, offer_id);
+    println!("Option token mint {}", option_mint);
+    println!("Using buy account {}", buy_account);
+
+    let expires_at = cluster_time(client)? + write.expires_in;
+    let (config_account, config) = get_config(client)?;
+
+    let mut instructions = Vec::new();
+    add_associated_token_account(
+        client,
+        &buy_account,
+        &writer,
+        &writer,
+        &write.buy_token,
+        &buy_token_program,
+        &mut instructions,
+    )?;
+    instructions.push(Instruction::new_with_borsh(
+        program_id(),
+        &program::Instruction::WriteOption {
+            offer_id,
+            sell_amount: write.sell_amount * LAMPORTS_PER_SOL,
+            strike: write.strike * LAMPORTS_PER_SOL,
+            premium: write.premium * LAMPORTS_PER_SOL,
+            expires_at,
+        },
+        vec![
+            AccountMeta::new(writer, true),
+            AccountMeta::new(sell_account, false),
+            AccountMeta::new(vault_account, false),
+            AccountMeta::new_readonly(write.sell_token, false),
+            AccountMeta::new_readonly(buy_account, false),
+            AccountMeta::new(escrow_account, false),
+            AccountMeta::new_readonly(sell_token_program, false),
+            AccountMeta::new_readonly(solana_sdk::system_program::ID, false),
+            AccountMeta::new_readonly(config_account, false),
+            AccountMeta::new(config.fee_recipient, false),
+            AccountMeta::new(option_mint, false),
+            AccountMeta::new_readonly(spl_token::ID, false),
+        ],
+    ));
+    execute(client, &write.writer, &instructions, vec![&write.writer])
+}
+
+fn do_buy_option(client: &RpcClient, buy: &OptionBuy) -> Result<(), Error> {
+    let escrow = get_call_option(client, &buy.escrow_account)?;
+    let option = escrow.call_option.as_ref().unwrap();
+    if option.is_sold {
+        return Err("Option has already been bought".into());
+    }
+    if escrow.is_expired(cluster_time(client)?) {
+        return Err("Option has expired".into());
+    }
+    let buyer = buy.buyer.pubkey();
+    let token_programs = get_token_programs(client, &escrow)?;
+    let option_token_program = get_token_program(client, &option.option_mint)?;
+    let premium_account =
+        get_associated_token_address(&buyer, &escrow.buy_mint, &token_programs.buy);
+    let option_account =
+        get_associated_token_address(&buyer, &option.option_mint, &option_token_program);
+    let (pda, _) =
+        program::find_escrow_authority(&buy.escrow_account, &escrow.poster, &program_id());
+    let (config_account, _) = get_config(client)?;
+    let (fee_authority, _) = program::find_fee_authority(&program_id());
+    let fee_token_account =
+        get_associated_token_address(&fee_authority, &escrow.buy_mint, &token_programs.buy);
+    println!(
+        "Buying an option on {} of {} for a premium of {} of {}",
+        get_remaining_amount(client, &buy.escrow_account, &escrow)?,
+        escrow.sell_mint,
+        option.premium,
+        escrow.buy_mint
+    );
+    if let Some(expires_at) = escrow.expires_at {
+        println!(
+            "  exercised for {} until unix time {}",
+            escrow.buy_amount, expires_at
+        );
+    }
+    println!("Receiving the option token into {}", option_account);
+
+    let mut instructions = Vec::new();
+    add_associated_token_account(
+        client,
+        &option_account,
+        &buyer,
+        &buyer,
+        &option.option_mint,
+        &option_token_program,
+        &mut instructions,
+    )?;
+    if escrow.fee_bps > 0 {
+        add_associated_token_account(
+            client,
+            &fee_token_account,
+            &buyer,
+            &fee_authority,
+            &escrow.buy_mint,
+            &token_programs.buy,
+            &mut instructions,
+        )?;
+    }
+    instructions.push(Instruction::new_with_borsh(
+        program_id(),
+        &program::Instruction::BuyOption {},
+        vec![
+            AccountMeta::new_readonly(buyer, true),
+            AccountMeta::new(buy.escrow_account, false),
+            AccountMeta::new(premium_account, false),
+            AccountMeta::new(escrow.poster_buy_account, false),
+            AccountMeta::new(option_account, false),
+            AccountMeta::new(option.option_mint, false),
+            AccountMeta::new_readonly(escrow.buy_mint, false),
+            AccountMeta::new_readonly(token_programs.buy, false),
+            AccountMeta::new_readonly(option_token_program, false),
+            AccountMeta::new_readonly(pda, false),
+            AccountMeta::new_readonly(config_account, false),
+            AccountMeta::new(fee_token_account, false),
+        ],
+    ));
+    execute(client, &buy.buyer, &instructions, vec![&buy.buyer])
+}
+
+fn do_exercise(client: &RpcClient, exercise: &OptionExercise) -> Result<(), Error> {
+    let escrow = get_call_option(client, &exercise.escrow_account)?;
+    let option = escrow.call_option.as_ref().unwrap();
+    if !option.is_sold {
+        return Err("Option has not been bought".into());
+    }
+    if escrow.is_expired(cluster_time(client)?) {
+        return Err("Option has expired".into());
+    }
+    let holder = exercise.holder.pubkey();
+    let token_programs = get_token_programs(client, &escrow)?;
+    let option_token_program = get_token_program(client, &option.option_mint)?;
+    let strike_account =
+        get_associated_token_address(&holder, &escrow.buy_mint, &token_programs.buy);
+    // SOL the option is on is received wrapped, as the program only sends tokens to a token account
+    let receive_account =
+        get_associated_token_address(&holder, &escrow.sell_mint, &token_programs.sell);
+    let option_account =
+        get_associated_token_address(&holder, &option.option_mint, &option_token_program);
+    let (pda, _) =
+        program::find_escrow_authority(&exercise.escrow_account, &escrow.poster, &program_id());
+    let (config_account, _) = get_config(client)?;
+    let (fee_authority, _) = program::find_fee_authority(&program_id());
+    let fee_token_account =
+        get_associated_token_address(&fee_authority, &escrow.buy_mint, &token_programs.buy);
+    println!(
+        "Exercising the option, paying {} of {} for {} of {}",
+        escrow.buy_amount,
+        escrow.buy_mint,
+        get_remaining_amount(client, &exercise.escrow_account, &escrow)?,
+        escrow.sell_mint
+    );
+
+    let mut instructions = Vec::new();
+    add_associated_token_account(
+        client,
+        &receive_account,
+        &holder,
+        &holder,
+        &escrow.sell_mint,
+        &token_programs.sell,
+        &mut instructions,
+    )?;
+    if escrow.fee_bps > 0 {
+        add_associated_token_account(
+            client,
+            &fee_token_account,
+            &holder,
+            &fee_authority,
+            &escrow.buy_mint,
+            &token_programs.buy,
+            &mut instructions,
+        )?;
+    }
+    instructions.push(Instruction::new_with_borsh(
+        program_id(),
+        &program::Instruction::Exercise {},
+        vec![
+            AccountMeta::new_readonly(holder, true),
+            AccountMeta::new(exercise.escrow_account, false),
+            AccountMeta::new(escrow.token_account, false),
+            AccountMeta::new(escrow.poster, false),
+            AccountMeta::new(escrow.poster_buy_account, false),
+            AccountMeta::new(strike_account, false),
+            AccountMeta::new(receive_account, false),
+            AccountMeta::new(option_account, false),
+            AccountMeta::new(option.option_mint, false),
+            AccountMeta::new(escrow.sell_mint, false),
+            AccountMeta::new_readonly(token_programs.sell, false),
+            AccountMeta::new_readonly(escrow.buy_mint, false),
+            AccountMeta::new_readonly(token_programs.buy, false),
+            AccountMeta::new_readonly(option_token_program, false),
+            AccountMeta::new_readonly(pda, false),
+            AccountMeta::new_readonly(config_account, false),
+            AccountMeta::new(fee_token_account, false),
+        ],
+    ));
+    execute(
+        client,
+        &exercise.holder,
+        &instructions,
+        vec![&exercise.holder],
+    )
+}
+
+/// Reads an escrow account, failing unless it holds a call option
+fn get_call_option(client: &RpcClient, escrow_account: &Pubkey) -> Result<Escrow, Error> {
+    let data = client.get_account(escrow_account)?.data;
+    if data.len() == Bundle::LEN {
+        return Err("Offer is a bundle, not a call option".into());
+    }
+    let escrow = Escrow::deserialize(&mut data.as_slice())?;
+    if escrow.call_option.is_none() {
+        return Err("Offer is not a call option".into());
+    }
+    Ok(escrow)
+}
+
+/// Lowest offer id the writer has no open offer for, and whose option mint, if there is one, has no token left
+fn next_option_id(client: &RpcClient, writer: &Pubkey) -> Result<u64, Error> {
+    for offer_id in 0.. {
+        let (escrow_account, _) = program::find_escrow_address(writer, offer_id, &program_id());
+        if client
+            .get_account_with_commitment(&escrow_account, client.commitment())?
+            .value
+            .is_some()
+        {
+            continue;
+        }
+        let (option_mint, _) = program::find_option_mint(&escrow_account, &program_id());
+        match client
+            .get_account_with_commitment(&option_mint, client.commitment())?
+            .value
+        {
+            Some(account)
+                if StateWithExtensions::<Mint>::unpack(&account.data)?
+                    .base
+                    .supply
+                    > 0 =>
+            {
+                continue
+            }
+            _ => return Ok(offer_id),
+        }
+    }
+    Err("Writer has no free offer ids".into())
+}
+
+//
+// Signed orders
+//
+
+fn do_approve_orders(client: &RpcClient, approve: &OrderApprove) -> Result<(), Error> {
+    let maker = approve.maker.pubkey();
+    let token_program = get_token_program(client, &approve.token)?;
+    let sell_account = get_associated_token_address(&maker, &approve.token, &token_program);
+    let decimals = StateWithExtensions::<Mint>::unpack(&client.get_account(&approve.token)?.data)?
+        .base
+        .decimals;
+    let (order_authority, _) = program::find_order_authority(&program_id());
+    println!(
+        "Approving {} to sell {} from {} for signed orders",
+        order_authority, approve.amount, sell_account
+    );
+    let instruction = spl_token_2022::instruction::approve_checked(
+        &token_program,
+        &sell_account,
+        &approve.token,
+        &order_authority,
+        &maker,
+        &[],
+        approve.amount * LAMPORTS_PER_SOL,
+        decimals,
+    )?;
+    execute(client, &approve.maker, &[instruction], vec![&approve.maker])
+}
+
+fn do_sign_order(client: &RpcClient, sign: &OrderSign) -> Result<(), Error> {
+    let maker = sign.maker.pubkey();
+    let buy_token_program = get_token_program(client, &sign.buy_token)?;
+    // a random nonce, from the bytes of a fresh keypair like a sealed bid's salt
+    let nonce = match sign.nonce {
+        Some(nonce) => nonce,
+        None => u64::from_le_bytes(Keypair::new().to_bytes()[..8].try_into()?),
+    };
+    let order = SignedOrder {
+        maker,
+        sell_mint: sign.sell_token,
+        sell_amount: sign.sell_amount * LAMPORTS_PER_SOL,
+        buy_mint: sign.buy_token,
+        buy_amount: sign.buy_amount * LAMPORTS_PER_SOL,
+        maker_buy_account: get_associated_token_address(
+            &maker,
+            &sign.buy_token,
+            &buy_token_program,
+        ),
+        fee_bps: get_config(client)?.1.fee_bps,
+        allowed_taker: sign.taker,
+        expires_at: cluster_time(client)? + sign.expires_in,
+        nonce,
+    };
+    let signature = sign.maker.sign_message(&order.try_to_vec()?);
+    save_signed_order(&sign.file, &order, &signature)?;
+    println!(
+        "Signed order with nonce {} saved to {}",
+        nonce,
+        sign.file.display()
+    );
+    println!(
+        "The order can only be filled while at least {} of {} is approved with `order approve`",
+        sign.sell_amount, sign.sell_token
+    );
+    Ok(())
+}
+
+fn do_show_order(client: &RpcClient, show: &OrderShow) -> Result<(), Error> {
+    let (order, _) = read_signed_order(&show.file)?;
+    println!("Order by {} with nonce {}", order.maker, order.nonce);
+    println!("  sell {} of {}", order.sell_amount, order.sell_mint);
+    println!("  buy {} of {}", order.buy_amount, order.buy_mint);
+    println!("  paid into {}", order.maker_buy_account);
+    println!("  fee of {} basis points", order.fee_bps);
+    println!("  expires at unix time {}", order.expires_at);
+    if let Some(allowed_taker) = order.allowed_taker {
+        println!("  reserved for {}", allowed_taker);
+    }
+    if order.expires_at <= cluster_time(client)? {
+        println!("Order has expired");
+    }
+    if is_nonce_used(client, &order.maker, order.nonce)? {
+        println!("Order's nonce has been used, it can no longer be filled");
+    }
+    Ok(())
+}
+
+fn do_fill_order(client: &RpcClient, fill: &OrderFillArgs) -> Result<(), Error> {
+    let (order, signature) = read_signed_order(&fill.file)?;
+    if order.expires_at <= cluster_time(client)? {
+        return Err("Order has expired".into());
+    }
+    if is_nonce_used(client, &order.maker, order.nonce)? {
+        return Err("Order's nonce has been used, it can no longer be filled".into());
+    }
+    let taker = fill.taker.pubkey();
+    match order.allowed_taker {
+        Some(allowed_taker) if allowed_taker != taker => println!(
+            "Warning: this order is reserved for {}, filling it will fail",
+            allowed_taker
+        ),
+        _ => {}
+    }
+    let sell_token_program = get_token_program(client, &order.sell_mint)?;
+    let buy_token_program = get_token_program(client, &order.buy_mint)?;
+    let (_, config) = get_config(client)?;
+    if order.fee_bps < config.fee_bps {
+        return Err("Order was signed with a lower fee rate than the program now charges".into());
+    }
+    let fee = program::token_fee(order.buy_amount, order.fee_bps);
+    if !fill.force {
+        println!("Preparing to fill order:");
+        println!("  sell {} of {}", order.buy_amount, order.buy_mint);
+        if fee > 0 {
+            println!("    of which {} is taken as a fee", fee);
+        }
+        println!("  buy {} of {}", order.sell_amount, order.sell_mint);
+        println!("  from user {}", order.maker);
+        if !ask_to_continue()? {
+            return Err("Trade aborted".into());
+        }
+    }
+
+    let maker_sell_account =
+        get_associated_token_address(&order.maker, &order.sell_mint, &sell_token_program);
+    let taker_sell_account =
+        get_associated_token_address(&taker, &order.buy_mint, &buy_token_program);
+    let taker_buy_account =
+        get_associated_token_address(&taker, &order.sell_mint, &sell_token_program);
+    let (order_authority, _) = program::find_order_authority(&program_id());
+    let (nonce_page, _) =
+        program::find_nonce_page(&order.maker, NoncePage::page(order.nonce), &program_id());
+    let (config_account, _) = program::find_config_address(&program_id());
+    let (fee_authority, _) = program::find_fee_authority(&program_id());
+    let fee_token_account =
+        get_associated_token_address(&fee_authority, &order.buy_mint, &buy_token_program);
+
+    let mut instructions = Vec::new();
+    add_associated_token_account(
+        client,
+        &taker_buy_account,
+        &taker,
+        &taker,
+        &order.sell_mint,
+        &sell_token_program,
+        &mut instructions,
+    )?;
+    if fee > 0 {
+        add_associated_token_account(
+            client,
+            &fee_token_account,
+            &taker,
+            &fee_authority,
+            &order.buy_mint,
+            &buy_token_program,
+            &mut instructions,
+        )?;
+    }
+    // the program checks the signature was verified by the instruction just before the fill
+    instructions.push(ed25519_instruction(
+        &order.maker,
+        &signature,
+        &order.try_to_vec()?,
+    ));
+    instructions.push(Instruction::new_with_borsh(
+        program_id(),
+        &program::Instruction::FillSignedOrder { order },
+        vec![
+            AccountMeta::new(taker, true),
+            AccountMeta::new(maker_sell_account, false),
+            AccountMeta::new(order.maker_buy_account, false),
+            AccountMeta::new(taker_sell_account, false),
+            AccountMeta::new(taker_buy_account, false),
+            AccountMeta::new_readonly(order.sell_mint, false),
+            AccountMeta::new_readonly(sell_token_program, false),
+            AccountMeta::new_readonly(order.buy_mint, false),
+            AccountMeta::new_readonly(buy_token_program, false),
+            AccountMeta::new_readonly(order_authority, false),
+            AccountMeta::new(nonce_page, false),
+            AccountMeta::new_readonly(solana_sdk::sysvar::instructions::ID, false),
+            AccountMeta::new_readonly(solana_sdk::system_program::ID, false),
+            AccountMeta::new_readonly(config_account, false),
+            AccountMeta::new(fee_token_account, false),
+        ],
+    ));
+    execute(client, &fill.taker, &instructions, vec![&fill.taker])
+}
+
+fn do_cancel_signed_order(client: &RpcClient, cancel: &OrderCancel) -> Result<(), Error> {
+    let maker = cancel.maker.pubkey();
+    if is_nonce_used(client, &maker, cancel.nonce)? {
+        return Err("Nonce has already been used".into());
+    }
+    let (nonce_page, _) =
+        program::find_nonce_page(&maker, NoncePage::page(cancel.nonce), &program_id());
+    let instruction = Instruction::new_with_borsh(
+        program_id(),
+        &program::Instruction::CancelSignedOrder {
+            nonce: cancel.nonce,
+        },
+        vec![
+            AccountMeta::new(maker, true),
+            AccountMeta::new(nonce_page, false),
+            AccountMeta::new_readonly(solana_sdk::system_program::ID, false),
+        ],
+    );
+    execute(client, &cancel.maker, &[instruction], vec![&cancel.maker])
+}
+
+fn is_nonce_used(client: &RpcClient, maker: &Pubkey, nonce: u64) -> Result<bool, Error> {
+    let (nonce_page, _) = program::find_nonce_page(maker, NoncePage::page(nonce), &program_id());
+    match client
+        .get_account_with_commitment(&nonce_page, client.commitment())?
+        .value
+    {
+        Some(account) => Ok(NoncePage::deserialize(&mut account.data.as_slice())?.is_used(nonce)),
+        None => Ok(false),
+    }
+}
+
+/// An ed25519 program instruction verifying `signature` of `message` by `signer`, with all three in its own data.
+/// The SDK only builds these from a dalek keypair, and the taker only has the maker's signature
+fn ed25519_instruction(signer: &Pubkey, signature: &Signature, message: &[u8]) -> Instruction {
+    const DATA_START: u16 = 16;
+    let public_key_offset = DATA_START;
+    let signature_offset = public_key_offset + 32;
+    let message_offset = signature_offset + 64;
+    let mut data = vec![1, 0];
+    for offset in [
+        signature_offset,
+        u16::MAX,
+        public_key_offset,
+        u16::MAX,
+        message_offset,
+        message.len() as u16,
+        u16::MAX,
+    ] {
+        data.extend_from_slice(&offset.to_le_bytes());
+    }
+    data.extend_from_slice(signer.as_ref());
+    data.extend_from_slice(signature.as_ref());
+    data.extend_from_slice(message);
+    Instruction::new_with_bytes(solana_sdk::ed25519_program::ID, &data, vec![])
+}
+
+/// Writes a signed order as the hex of the order followed by its signature
+fn save_signed_order(path: &Path, order: &SignedOrder, signature: &Signature) -> Result<(), Error> {
+    let contents = format!("{} {}\n", encode_hex(&order.try_to_vec()?), signature);
+    std::fs::write(path, contents)?;
+    Ok(())
+}
+
+/// Reads a signed order saved by `save_signed_order`, checking it is signed by its maker
+fn read_signed_order(path: &Path) -> Result<(SignedOrder, Signature), Error> {
+    let contents = std::fs::read_to_string(path).map_err(|error| {
+        format!(
+            "Could not read the signed order in {}: {}",
+            path.display(),
+            error
+        )
+    })?;
+    let (order_hex, signature) = contents
+        .trim()
+        .split_once(' ')
+        .ok_or("Signed order is malformed")?;
+    let message = decode_hex(order_hex)?;
+    let order = SignedOrder::try_from_slice(&message)?;
+    let signature = Signature::from_str(signature)?;
+    if !signature.verify(order.maker.as_ref(), &message) {
+        return Err("Order is not signed by its maker".into());
+    }
+    Ok((order, signature))
+}
+
+//
+// Requests for quotes
+//
+
+/// Other quotes refunded when one is accepted, as many as fit in the transaction with it.
+/// Any more are left for their makers to cancel, or to be reclaimed once they expire
+const MAX_QUOTE_REFUNDS: usize = 2;
+
+fn do_create_rfq(client: &RpcClient, create: &RfqCreate) -> Result<(), Error> {
+    let requester = create.requester.pubkey();
+    let rfq_id = match create.id {
+        Some(id) => id,
+        None => next_rfq_id(client, &requester)?,
+    };
+    let (rfq_account, _) = program::find_rfq_address(&requester, rfq_id, &program_id());
+    println!("Creating request for quotes {}", rfq_account);
+    println!("Request id {}/{}", requester, rfq_id);
+
+    let instruction = Instruction::new_with_borsh(
+        program_id(),
+        &program::Instruction::CreateRfq {
+            rfq_id,
+            mint: create.token,
+            amount: create.amount * LAMPORTS_PER_SOL,
+            pay_mint: create.pay_token,
+            deadline: cluster_time(client)? + create.deadline_in,
+        },
+        vec![
+            AccountMeta::new(requester, true),
+            AccountMeta::new(rfq_account, false),
+            AccountMeta::new_readonly(solana_sdk::system_program::ID, false),
+        ],
+    );
+    execute(
+        client,
+        &create.requester,
+        &[instruction],
+        vec![&create.requester],
+    )
+}
+
+fn do_show_rfq(client: &RpcClient, show: &RfqShow) -> Result<(), Error> {
+    let rfq = get_rfq(client, &show.rfq_account)?;
+    println!(
+        "Request {}/{} by {}",
+        rfq.requester, rfq.rfq_id, rfq.requester
+    );
+    println!("  buy {} of {}", rfq.amount, rfq.mint);
+    println!("  paid in {}", rfq.pay_mint);
+    println!("  quotes until unix time {}", rfq.deadline);
+    let quotes = get_quotes(client, &show.rfq_account)?;
+    if quotes.is_empty() {
+        println!("No quotes yet");
+    }
+    for (escrow_account, escrow) in quotes {
+        println!(
+            "  {} by {}: {} of {}",
+            escrow_account, escrow.poster, escrow.buy_amount, escrow.buy_mint
+        );
+    }
+    Ok(())
+}
+
+fn do_post_quote(client: &RpcClient, quote: &RfqQuote) -> Result<(), Error> {
+    let rfq = get_rfq(client, &quote.rfq_account)?;
+    if rfq.deadline <= cluster_time(client)? {
+        return Err("Request for quotes has passed its deadline".into());
+    }
+    let maker = quote.maker.pubkey();
+    let sell_token_program = get_token_program(client, &rfq.mint)?;
+    let buy_token_program = get_token_program(client, &rfq.pay_mint)?;
+    let sell_account = owner_account(&maker, &rfq.mint, &sell_token_program);
+    let buy_account = owner_account(&maker, &rfq.pay_mint, &buy_token_program);
+    let offer_id = match quote.id {
+        Some(id) => id,
+        None => next_offer_id(client, &maker)?,
+    };
+    let (escrow_account, _) = program::find_escrow_address(&maker, offer_id, &program_id());
+    let (vault_account, _) = program::find_escrow_vault(&escrow_account, &program_id());
+    println!("Creating escrow account {}", escrow_account);
+    println!("Offer id {}/{}", maker, offer_id);
+    println!("Using buy account {}", buy_account);
+    let (config_account, config) = get_config(client)?;
+
+    let mut instructions = Vec::new();
+    if buy_account != maker {
+        add_associated_token_account(
+            client,
+            &buy_account,
+            &maker,
+            &maker,
+            &rfq.pay_mint,
+            &buy_token_program,
+            &mut instructions,
+        )?;
+    }
+    instructions.push(Instruction::new_with_borsh(
+        program_id(),
+        &program::Instruction::PostQuote {
+            offer_id,
+            price: quote.price * LAMPORTS_PER_SOL,
+        },
+        vec![
+            AccountMeta::new(maker, true),
+            AccountMeta::new(sell_account, false),
+            AccountMeta::new(vault_account, false),
+            AccountMeta::new_readonly(rfq.mint, false),
+            AccountMeta::new_readonly(buy_account, false),
+            AccountMeta::new(escrow_account, false),
+            AccountMeta::new_readonly(sell_token_program, false),
+            AccountMeta::new_readonly(solana_sdk::system_program::ID, false),
+            AccountMeta::new_readonly(config_account, false),
+            AccountMeta::new(config.fee_recipient, false),
+            AccountMeta::new_readonly(quote.rfq_account, false),
+        ],
+    ));
+    execute(client, &quote.maker, &instructions, vec![&quote.maker])
+}
+
+fn do_accept_quote(client: &RpcClient, accept: &RfqAccept) -> Result<(), Error> {
+    let rfq = get_rfq(client, &accept.rfq_account)?;
+    let escrow = Escrow::deserialize(&mut client.get_account(&accept.quote)?.data.as_slice())?;
+    if escrow.rfq != Some(accept.rfq_account) {
+        return Err("Offer is not a quote for this request".into());
+    }
+    if escrow.is_expired(cluster_time(client)?) {
+        return Err("Quote has expired".into());
+    }
+    let requester = accept.requester.pubkey();
+    let (_, config) = get_config(client)?;
+    let fee = program::token_fee(escrow.buy_amount, escrow.fee_bps);
+
+    // SOL is paid straight from the requester's account, otherwise they also pay any Token-2022 transfer fees
+    let pays_native = escrow.poster_buy_account == escrow.poster;
+    let epoch = client.get_epoch_info()?.epoch;
+    let sell_amount = if pays_native {
+        escrow.buy_amount
+    } else {
+        let mint_data = client.get_account(&rfq.pay_mint)?.data;
+        program::amount_with_transfer_fee(&mint_data, epoch, escrow.buy_amount - fee)?
+            + program::amount_with_transfer_fee(&mint_data, epoch, fee)?
+    };
+    let buy_amount = get_token_amount(client, &escrow.token_account)?;
+    if !accept.force {
+        println!("Preparing to accept quote:");
+        println!("  pay {} of {}", sell_amount, rfq.pay_mint);
+        if fee > 0 {
+            println!("    of which {} is taken as a fee", fee);
+        }
+        println!(
+            "  buy {} of {}",
+            program::amount_after_transfer_fee(
+                &client.get_account(&rfq.mint)?.data,
+                epoch,
+                buy_amount
+            )?,
+            rfq.mint
+        );
+        println!("  from user {}", escrow.poster);
+        if !ask_to_continue()? {
+            return Err("Trade aborted".into());
+        }
+    }
+
+    let token_programs = get_token_programs(client, &escrow)?;
+    let taker_sell_account = if pays_native {
+        requester
+    } else {
+        get_associated_token_address(&requester, &rfq.pay_mint, &token_programs.buy)
+    };
+    let taker_buy_account = owner_account(&requester, &rfq.mint, &token_programs.sell);
+
+    let mut instructions = Vec::new();
+    if taker_buy_account != requester {
+        add_associated_token_account(
+            client,
+            &taker_buy_account,
+            &requester,
+            &requester,
+            &rfq.mint,
+            &token_programs.sell,
+            &mut instructions,
+        )?;
+    }
+    let (fee_authority, _) = program::find_fee_authority(&program_id());
+    let fee_token_account = if pays_native {
+        config.fee_recipient
+    } else {
+        get_associated_token_address(&fee_authority, &rfq.pay_mint, &token_programs.buy)
+    };
+    if fee > 0 && !pays_native {
+        add_associated_token_account(
+            client,
+            &fee_token_account,
+            &requester,
+            &fee_authority,
+            &rfq.pay_mint,
+            &token_programs.buy,
+            &mut instructions,
+        )?;
+    }
+
+    let mut accounts = vec![AccountMeta::new(accept.rfq_account, false)];
+    accounts.extend(take_trade_accounts(
+        &requester,
+        &accept.quote,
+        &escrow,
+        &token_programs,
+        taker_sell_account,
+        taker_buy_account,
+        fee_token_account,
+    ));
+    // quotes whose makers have no token account to refund into are left for them to cancel
+    let other_quotes: Vec<_> = get_quotes(client, &accept.rfq_account)?
+        .into_iter()
+        .filter(|(escrow_account, _)| *escrow_account != accept.quote)
+        .map(|(escrow_account, other)| {
+            let refund_account =
+                owner_account(&other.poster, &other.sell_mint, &token_programs.sell);
+            (escrow_account, other, refund_account)
+        })
+        .filter(|(_, _, refund_account)| client.get_account(refund_account).is_ok())
+        .collect();
+    for (escrow_account, other, refund_account) in other_quotes.iter().take(MAX_QUOTE_REFUNDS) {
+        println!("Refunding quote {}", escrow_account);
+        accounts.extend(refund_accounts(
+            other,
+            *escrow_account,
+            *refund_account,
+            token_programs.sell,
+        ));
+    }
+    if other_quotes.len() > MAX_QUOTE_REFUNDS {
+        println!(
+            "{} other quotes are left for their makers to cancel, or to be reclaimed once they expire",
+            other_quotes.len() - MAX_QUOTE_REFUNDS
+        );
+    }
+    instructions.push(Instruction::new_with_borsh(
+        program_id(),
+        &program::Instruction::AcceptQuote { sell_amount },
+        accounts,
+    ));
+    execute(
+        client,
+        &accept.requester,
+        &instructions,
+        vec![&accept.requester],
+    )
+}
+
+fn do_cancel_rfq(client: &RpcClient, cancel: &RfqCancel) -> Result<(), Error> {
+    get_rfq(client, &cancel.rfq_account)?;
+    let instruction = Instruction::new_with_borsh(
+        program_id(),
+        &program::Instruction::CancelRfq {},
+        vec![
+            AccountMeta::new(cancel.requester.pubkey(), true),
+            AccountMeta::new(cancel.rfq_account, false),
+        ],
+    );
+    execute(
+        client,
+        &cancel.requester,
+        &[instruction],
+        vec![&cancel.requester],
+    )
+}
+
+fn get_rfq(client: &RpcClient, rfq_account: &Pubkey) -> Result<Rfq, Error> {
+    let data = client.get_account(rfq_account)?.data;
+    if data.len() != get_packed_len::<Rfq>() {
+        return Err("Account is not a request for quotes".into());
+    }
+    Ok(Rfq::deserialize(&mut data.as_slice())?)
+}
+
+/// Open quotes for the given request, cheapest first
+fn get_quotes(client: &RpcClient, rfq_account: &Pubkey) -> Result<Vec<(Pubkey, Escrow)>, Error> {
+    let now = cluster_time(client)?;
+    let mut quotes: Vec<_> = get_escrows(client)?
+        .into_iter()
+        .filter(|(_, escrow)| escrow.rfq == Some(*rfq_account) && !escrow.is_expired(now))
+        .collect();
+    quotes.sort_by_key(|(_, escrow)| escrow.buy_amount);
+    Ok(quotes)
+}
+
+/// Lowest request id the requester has no open request for
+fn next_rfq_id(client: &RpcClient, requester: &Pubkey) -> Result<u64, Error> {
+    for rfq_id in 0.. {
+        let (rfq_account, _) = program::find_rfq_address(requester, rfq_id, &program_id());
+        if client
+            .get_account_with_commitment(&rfq_account, client.commitment())?
+            .value
+            .is_none()
+        {
+            return Ok(rfq_id);
+        }
+    }
+    Err("Requester has no free request ids".into())
+}
+
+//
+// Common functions
+//
+
+fn program_id() -> Pubkey {
+    Pubkey::from_str("77zL4LfjPjZbeCb8baAQ1pDvcWxNKxDFcVoJz5cxSFCv").unwrap()
+}
+
+fn get_config(client: &RpcClient) -> Result<(Pubkey, Config), Error> {
+    let (config_account, _) = program::find_config_address(&program_id());
+    let config = Config::deserialize(&mut client.get_account(&config_account)?.data.as_slice())?;
+    Ok((config_account, config))
+}
+
+/// Sealed bids on the given auction that are yet to be revealed or released
+fn get_sealed_bids(
+    client: &RpcClient,
+    escrow_account: &Pubkey,
+) -> Result<Vec<(Pubkey, SealedBid)>, Error> {
+    let space = get_packed_len::<SealedBid>();
+    Ok(client
+        .get_program_accounts(&program_id())?
+        .into_iter()
+        .filter(|(_, account)| account.data.len() == space)
+        .filter_map(|(pubkey, account)| {
+            let sealed_bid = SealedBid::deserialize(&mut account.data.as_slice()).ok()?;
+            Some((pubkey, sealed_bid))
+        })
+        .filter(|(_, sealed_bid)| sealed_bid.escrow == *escrow_account)
+        .collect())
+}
+
+fn get_bundles(client: &RpcClient) -> Result<Vec<(Pubkey, Bundle)>, Error> {
+    Ok(client
+        .get_program_accounts(&program_id())?
+        .into_iter()
+        .filter(|(_, account)| account.data.len() == Bundle::LEN)
+        .filter_map(|(pubkey, account)| {
+            let bundle = Bundle::deserialize(&mut account.data.as_slice()).ok()?;
+            Some((pubkey, bundle))
+        })
+        .collect())
+}
+
+fn get_escrows(client: &RpcClient) -> Result<Vec<(Pubkey, Escrow)>, Error> {
+    let space = get_packed_len::<Escrow>();
+    Ok(client
+        .get_program_accounts(&program_id())?
+        .into_iter()
+        .filter(|(_, account)| account.data.len() == space)
+        .filter_map(|(pubkey, account)| {
+            let escrow = Escrow::deserialize(&mut account.data.as_slice()).ok()?;
+            Some((pubkey, escrow))
+        })
+        .collect())
+}
+
+/// Token program a mint belongs to, the original token program or Token-2022
+fn get_token_program(client: &RpcClient, mint: &Pubkey) -> Result<Pubkey, Error> {
+    Ok(client.get_account(mint)?.owner)
+}
+
+/// Token programs of the token an escrow is selling and the token it is buying
+struct TokenPrograms {
+    sell: Pubkey,
+    buy: Pubkey,
+}
+
+fn get_token_programs(client: &RpcClient, escrow: &Escrow) -> Result<TokenPrograms, Error> {
+    Ok(TokenPrograms {
+        sell: get_token_program(client, &escrow.sell_mint)?,
+        buy: get_token_program(client, &escrow.buy_mint)?,
+    })
+}
+
+fn cluster_time(client: &RpcClient) -> Result<i64, Error> {
+    let account = client.get_account(&solana_sdk::sysvar::clock::ID)?;
+    let clock: Clock = from_account(&account).ok_or("Could not read the cluster clock")?;
+    Ok(clock.unix_timestamp)
+}
+
+fn parse_escrow_address(offer: &str) -> Result<Pubkey, Error> {
+    match offer.split_once('/') {
+        Some((poster, offer_id)) => Ok(program::find_escrow_address(
+            &Pubkey::from_str(poster)?,
+            offer_id.parse()?,
+            &program_id(),
+        )
+        .0),
+        None => Ok(Pubkey::from_str(offer)?),
+    }
+}
+
+fn parse_rfq_address(rfq: &str) -> Result<Pubkey, Error> {
+    match rfq.split_once('/') {
+        Some((requester, rfq_id)) => Ok(program::find_rfq_address(
+            &Pubkey::from_str(requester)?,
+            rfq_id.parse()?,
+            &program_id(),
+        )
+        .0),
+        None => Ok(Pubkey::from_str(rfq)?),
+    }
+}
+
+/// Parses a token mint, accepting `SOL` for the native mint
+fn parse_mint(mint: &str) -> Result<Pubkey, Error> {
+    if mint.eq_ignore_ascii_case("SOL") {
+        return Ok(spl_token::native_mint::ID);
+    }
+    Ok(Pubkey::from_str(mint)?)
+}
+
+fn parse_side(side: &str) -> Result<Side, Error> {
+    match side {
+        "bid" => Ok(Side::Bid),
+        "ask" => Ok(Side::Ask),
+        _ => Err(format!("Unknown order side '{}', expected bid or ask", side).into()),
+    }
+}
+
+/// A token and an amount of it, given as `MINT:AMOUNT`
+struct TokenAmount {
+    mint: Pubkey,
+    amount: u64,
+}
+
+fn parse_token_amount(token_amount: &str) -> Result<TokenAmount, Error> {
+    let (mint, amount) = token_amount
+        .split_once(':')
+        .ok_or("Expected a token and amount as MINT:AMOUNT")?;
+    Ok(TokenAmount {
+        mint: parse_mint(mint)?,
+        amount: amount.parse()?,
+    })
+}
+
+/// Account `owner` holds `mint` in: their own account for native SOL, otherwise their associated token account
+fn owner_account(owner: &Pubkey, mint: &Pubkey, token_program: &Pubkey) -> Pubkey {
+    if *mint == spl_token::native_mint::ID {
+        return *owner;
+    }
+    get_associated_token_address(owner, mint, token_program)
+}
+
+/// Associated token account address under either token program, the associated token account crate
+/// only derives them for the original one
+fn get_associated_token_address(owner: &Pubkey, mint: &Pubkey, token_program: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(
+        &[owner.as_ref(), token_program.as_ref(), mint.as_ref()],
+        &spl_associated_token_account::id(),
+    )
+    .0
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn decode_hex(hex: &str) -> Result<Vec<u8>, Error> {
+    if hex.len() % 2 == 1 {
+        return Err("Hex string has an odd length".into());
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|index| Ok(u8::from_str_radix(&hex[index..index + 2], 16)?))
+        .collect()
+}
+
+fn parse_duration(duration: &str) -> Result<i64, Error> {
+    let (value, unit) = match duration.find(|c: char| !c.is_ascii_digit()) {
+        Some(index) => duration.split_at(index),
+        None => (duration, "s"),
+    };
+    let multiplier = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return Err(format!("Unknown duration unit '{}'", unit).into()),
+    };
+    Ok(value
+        .parse::<i64>()?
+        .checked_mul(multiplier)
+        .ok_or("Duration is too long")?)
+}
+
+fn add_associated_token_account(
+    client: &RpcClient,
+    associated_account_address: &Pubkey,
+    funding_address: &Pubkey,
+    wallet_address: &Pubkey,
+    spl_token_mint_address: &Pubkey,
+    token_program: &Pubkey,
+    instructions: &mut Vec<Instruction>,
+) -> Result<(), Error> {
+    let config = CommitmentConfig {
+        commitment: CommitmentLevel::Processed, // TODO is this the right way to check if account exists?
+    };
+    if client
+        .get_account_with_commitment(associated_account_address, config)?
+        .value
+        .is_some()
+    {
+        return Ok(());
+    }
+    instructions.push(Instruction::new_with_bytes(
+        spl_associated_token_account::id(),
+        &[],
+        vec![
+            AccountMeta::new(*funding_address, true),
+            AccountMeta::new(*associated_account_address, false),
+            AccountMeta::new_readonly(*wallet_address, false),
+            AccountMeta::new_readonly(*spl_token_mint_address, false),
+            AccountMeta::new_readonly(solana_sdk::system_program::ID, false),
+            AccountMeta::new_readonly(*token_program, false),
+            AccountMeta::new_readonly(solana_sdk::sysvar::rent::ID, false),
+        ],
+    ));
     Ok(())
 }
 
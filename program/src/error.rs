@@ -24,6 +24,96 @@ pub enum Error {
     AccountNotToken,
     #[error("Incorrect PDA account")]
     IncorrectPDA,
+    #[error("Fill amount is zero, exceeds the offer or would leave it unpriced")]
+    InvalidFillAmount,
+    #[error("Offer has expired")]
+    OfferExpired,
+    #[error("Offer has not expired yet")]
+    OfferNotExpired,
+    #[error("Token account is not for the mint recorded in the escrow account")]
+    MintMismatch,
+    #[error("Signer is not the admin in the config account")]
+    NotAdmin,
+    #[error("Fee account does not hold enough lamports above rent exemption")]
+    InsufficientFees,
+    #[error("Fee rate is more than 1000 basis points")]
+    InvalidFeeRate,
+    #[error("Offer is reserved for a different taker")]
+    NotAllowedTaker,
+    #[error("Amended amounts must not be zero")]
+    InvalidAmendment,
+    #[error("Offer has been amended since the taker read it")]
+    StaleOffer,
+    #[error("Bundle has too few or too many legs")]
+    InvalidBundle,
+    #[error("Offers' prices don't cross")]
+    OffersDontCross,
+    #[error("Offers asking for native SOL can't be matched")]
+    CannotMatchNative,
+    #[error("Order amounts must not be zero")]
+    InvalidOrder,
+    #[error("Order book is full")]
+    OrderBookFull,
+    #[error("Order not found in the book")]
+    OrderNotFound,
+    #[error("Auction prices must not be zero or rise, and it must end after it starts")]
+    InvalidAuction,
+    #[error("Offer is an English auction, it can only be bid on and settled")]
+    IsEnglishAuction,
+    #[error("Offer is not an English auction")]
+    NotEnglishAuction,
+    #[error(
+        "Bid is below the reserve price or doesn't beat the highest bid by the minimum increment"
+    )]
+    BidTooLow,
+    #[error("Auction has ended")]
+    AuctionEnded,
+    #[error("Auction has not ended yet")]
+    AuctionNotEnded,
+    #[error("Auction has bids, it can only be settled")]
+    AuctionHasBids,
+    #[error("Auction has no bids to settle")]
+    AuctionHasNoBids,
+    #[error("Offer is a sealed-bid auction, it can only be bid on and settled")]
+    IsSealedAuction,
+    #[error("Offer is not a sealed-bid auction")]
+    NotSealedAuction,
+    #[error("Sealed bids can only be revealed after bidding closes, until the reveal deadline")]
+    RevealNotOpen,
+    #[error("Amount and salt don't match the sealed bid")]
+    CommitmentMismatch,
+    #[error("Auction has sealed bids that haven't been revealed or released")]
+    UnresolvedBids,
+    #[error("Offer is a call option, it can only be bought and exercised")]
+    IsCallOption,
+    #[error("Offer is not a call option")]
+    NotCallOption,
+    #[error("Option has already been bought")]
+    OptionSold,
+    #[error("Option has not been bought")]
+    OptionNotSold,
+    #[error("Option mint for this offer id is still held by the buyer of an earlier option")]
+    OptionMintInUse,
+    #[error("Order is not signed by its maker in an ed25519 instruction just before this one")]
+    InvalidSignature,
+    #[error("Order's nonce has already been used by a filled or cancelled order")]
+    NonceUsed,
+    #[error("Accounts supplied do not match the signed order")]
+    DoesntMatchOrder,
+    #[error("Requested amount must not be zero and the deadline must be in the future")]
+    InvalidRfq,
+    #[error("Request for quotes has passed its deadline")]
+    RfqExpired,
+    #[error("Offer is a quote, it can only be accepted by the requester")]
+    IsQuote,
+    #[error("Quote does not answer this request for quotes")]
+    DoesntMatchRfq,
+    #[error("Config can only be initialized by the program's upgrade authority")]
+    NotUpgradeAuthority,
+    #[error("Offer is paid into a token account, so can't ask for native SOL")]
+    NativeNotAllowed,
+    #[error("Order's fee rate is below the config's")]
+    FeeRateTooLow,
 }
 
 impl From<Error> for ProgramError {
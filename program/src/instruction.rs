@@ -1,48 +1,830 @@
 use borsh::{BorshDeserialize, BorshSerialize};
+use solana_program::pubkey::Pubkey;
+
+use crate::state::{DutchAuction, Side, SignedOrder, UnrevealedPolicy};
 
 #[derive(BorshSerialize, BorshDeserialize)]
 pub enum Instruction {
-    /// Starts the trade by creating and populating an escrow account and transferring ownership of the given temp token account to the PDA
+    /// Starts the trade by creating and populating an escrow account (a PDA derived from the poster and offer id),
+    /// creating a vault token account for it owned by the escrow's PDA (derived from the escrow account and the poster),
+    /// and moving the tokens being sold into the vault.
+    /// Native SOL can be sold or asked for by passing the poster's own account in place of a token account,
+    /// SOL being sold is wrapped into the vault
     ///
     /// Accounts expected:
     ///
-    /// 0. `[signer]` The account of the poster: the person posting the trade
-    /// 1. `[writable]` Temporary token account that should be created prior to this instruction and owned by the poster
-    /// 2. `[]` The poster's token account for the token they will receive should the trade go through
-    /// 3. `[writable]` The escrow account, it will hold all necessary info about the trade.
-    /// 4. `[]` The token program
-    /// 5. `[]` The system program (to allow for transferring the fee to escrow account)
-    /// 6. `[writeable]` The fee account (for fee to be payed into, owned by program)
+    /// 0. `[signer, writable]` The account of the poster: the person posting the trade
+    /// 1. `[writable]` The poster's token account for the token they are selling, or the poster's account to sell SOL
+    /// 2. `[writable]` The vault token account, a PDA derived from the escrow account that will be created
+    /// 3. `[]` The mint of the token being sold (the native mint when selling SOL)
+    /// 4. `[]` The poster's token account for the token they will receive should the trade go through, or the poster's account to receive SOL
+    /// 5. `[writable]` The escrow account, a PDA derived from the poster and offer id that will be created to hold all necessary info about the trade.
+    /// 6. `[]` The token program of the token being sold, either the original token program or Token-2022
+    /// 7. `[]` The system program (to allow for creating the escrow and vault and transferring the fee to escrow account)
+    /// 8. `[]` The config account holding the fee settings
+    /// 9. `[writeable]` The fee account (for fee to be payed into, must match the config account)
     Post {
+        /// Id chosen by the poster to tell their offers apart
+        offer_id: u64,
+        /// Amount of token X party A moves into the vault, the vault receives this less any transfer fee
+        sell_amount: u64,
+        /// Amount party A expects to receive of token Y
+        buy_amount: u64,
+        /// Optional unix timestamp after which the trade can no longer be taken
+        expires_at: Option<i64>,
+        /// Optional account that the trade is reserved for, no one else can take it
+        allowed_taker: Option<Pubkey>,
+    },
+
+    /// Starts the trade like `Post`, but leaves the tokens being sold in the poster's account and
+    /// approves the escrow's PDA as a delegate over them instead of moving them into a vault
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer, writable]` The account of the poster: the person posting the trade
+    /// 1. `[writable]` The poster's token account for the token they are selling, which the PDA will be a delegate of
+    /// 2. `[]` The poster's token account for the token they will receive should the trade go through, or the poster's account to receive SOL
+    /// 3. `[writable]` The escrow account, a PDA derived from the poster and offer id that will be created to hold all necessary info about the trade.
+    /// 4. `[]` The token program of the token being sold, either the original token program or Token-2022
+    /// 5. `[]` The system program (to allow for creating the escrow and transferring the fee to escrow account)
+    /// 6. `[]` The config account holding the fee settings
+    /// 7. `[writeable]` The fee account (for fee to be payed into, must match the config account)
+    PostDelegated {
+        /// Id chosen by the poster to tell their offers apart
+        offer_id: u64,
+        /// Amount of token X the PDA is approved to transfer out of the poster's account
+        sell_amount: u64,
         /// Amount party A expects to receive of token Y
         buy_amount: u64,
+        /// Optional unix timestamp after which the trade can no longer be taken
+        expires_at: Option<i64>,
+        /// Optional account that the trade is reserved for, no one else can take it
+        allowed_taker: Option<Pubkey>,
     },
 
-    /// Takes a trade that a seller has Post-ed
+    /// Takes a trade that a seller has Post-ed, either in full or in part.
+    /// The vault token account and escrow account are only closed once the offer is fully consumed.
+    /// When the poster asked for SOL, the taker pays it from their own account. When the poster is selling SOL,
+    /// the taker can have it unwrapped into their own account through a temporary wrapped SOL account
     ///
     /// Accounts expected:
     ///
-    /// 0. `[signer]` The account of the taker (person taking the trade)
-    /// 1. `[writable]` The taker's token account for the token they send
-    /// 2. `[writable]` The taker's token account for the token they will receive should the trade go through
-    /// 3. `[writable]` The PDA's temp token account to get tokens from and eventually close
+    /// 0. `[signer, writable]` The account of the taker (person taking the trade)
+    /// 1. `[writable]` The taker's token account for the token they send, or the taker's account to pay SOL
+    /// 2. `[writable]` The taker's token account for the token they will receive should the trade go through,
+    ///    or the taker's account to receive SOL
+    /// 3. `[writable]` The escrow's token account to get tokens from: the vault, which is closed once empty, or the poster's delegated account
     /// 4. `[writable]` The poster's main account to send their rent fees to
     /// 5. `[writable]` The poster's token account that will receive tokens
     /// 6. `[writable]` The escrow account holding the escrow info
-    /// 7. `[]` The token program
-    /// 8. `[]` The PDA account
-    Take { buy_amount: u64, sell_amount: u64 },
+    /// 7. `[]` The token program of the escrowed token
+    /// 8. `[]` The escrow's PDA account
+    /// 9. `[]` The config account holding the fee settings
+    /// 10. `[writable]` The token account for the token the poster receives, owned by the fee PDA, to pay the fee into,
+    ///     or the config's fee account when paying SOL. Only checked when the offer's `fee_bps` is not zero
+    /// 11. `[]` The system program
+    /// 12. `[writable]` The temporary wrapped SOL account, a PDA derived from the escrow account and the taker.
+    ///     Only used when receiving SOL
+    /// 13. `[]` The native mint
+    /// 14. `[writable]` The mint of the escrowed token, any transfer fees withheld in the vault are harvested to it
+    /// 15. `[]` The mint of the token the poster receives
+    /// 16. `[]` The token program of the token the poster receives
+    Take {
+        /// Amount of the escrowed token taken out of the vault, at most the amount left in it.
+        /// The taker receives this less any transfer fee
+        buy_amount: u64,
+        /// Amount the taker pays: the pro-rata share of the escrow's buy amount, rounded up, plus any transfer fee
+        /// on it. The poster receives the share less the offer's basis-point fee.
+        /// For a Dutch auction this is the most the taker pays, they pay the price when the take is processed
+        sell_amount: u64,
+        /// The escrow's `version` the amounts were worked out from, the take fails if it has been amended since
+        version: u64,
+    },
 
     /// Cancel trade that a poster has Post-ed,
-    /// returning authority of temporary token account to poster
+    /// returning the tokens in the vault to the poster and closing it, or revoking the PDA's delegation
     ///
     /// Accounts expected:
     ///
-    /// 0. `[signer]` The account of the original poster
-    /// 1. `[writable]` The temporary token account (which will be reassigned back to poster)
+    /// 0. `[signer, writable]` The account of the original poster
+    /// 1. `[writable]` The escrow's token account (the vault will be emptied and closed)
     /// 2. `[writable]` The escrow account (which will be uninitialized)    
-    /// 3. `[writeable]` The taker's account to refund tokens to
-    /// 4. `[]` The token program
-    /// 8. `[]` The PDA account
+    /// 3. `[writeable]` The taker's account to refund tokens to, or the poster's account to unwrap SOL into
+    /// 4. `[]` The token program of the escrowed token
+    /// 5. `[]` The escrow's PDA account
+    /// 6. `[writable]` The mint of the escrowed token
     Cancel {},
+
+    /// Changes the terms of a trade that a poster has Post-ed, keeping its escrow account.
+    /// Bumps the escrow's `version`, so takes worked out from the old terms fail.
+    /// A Dutch auction becomes a fixed price offer at the new buy amount
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer, writable]` The account of the original poster
+    /// 1. `[writable]` The escrow account
+    /// 2. `[writable]` The escrow's token account
+    /// 3. `[writable]` The poster's token account for the token they are selling, to top up the vault from or withdraw into,
+    ///    or the poster's account to wrap or unwrap SOL
+    /// 4. `[]` The token program of the escrowed token
+    /// 5. `[]` The escrow's PDA account
+    /// 6. `[]` The system program
+    /// 7. `[writable]` The temporary wrapped SOL account, a PDA derived from the escrow account and the poster.
+    ///    Only used when withdrawing SOL
+    /// 8. `[]` The native mint
+    /// 9. `[]` The mint of the escrowed token
+    Amend {
+        /// New amount of token Y the poster expects to receive for what is left of the offer
+        buy_amount: u64,
+        /// New amount of token X left on offer, topping up or withdrawing from the vault (or changing the
+        /// delegated amount) as needed. Left as it is if not given
+        sell_amount: Option<u64>,
+    },
+
+    /// Reclaims an expired trade on behalf of its poster, returning the escrowed tokens and rent.
+    /// Anyone may call this, and the caller is paid a small reward out of the escrow account's rent
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer, writable]` The account of the caller, which receives the reward
+    /// 1. `[writable]` The escrow's token account (the vault will have its tokens returned and be closed)
+    /// 2. `[writable]` The escrow account (which will be closed)
+    /// 3. `[writable]` The poster's main account to send their rent fees to
+    /// 4. `[writable]` The poster's token account to refund tokens to, or the poster's main account again to unwrap SOL into
+    /// 5. `[]` The token program of the escrowed token
+    /// 6. `[]` The escrow's PDA account
+    /// 7. `[writable]` The mint of the escrowed token
+    Reclaim {},
+
+    /// Creates the program's config account, making the signer its admin.
+    /// Should be called once, straight after the program is deployed, by its upgrade authority
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer, writable]` The admin account, which must be the program's upgrade authority and pays for the config account
+    /// 1. `[writable]` The config account, a PDA derived from `CONFIG_SEED`
+    /// 2. `[]` The system program
+    /// 3. `[]` The program's ProgramData account, holding its upgrade authority
+    InitializeConfig {
+        /// Lamports charged to post a trade
+        fee: u64,
+        /// Account the fee is paid into
+        fee_recipient: Pubkey,
+        /// Fee in basis points taken from the tokens the poster receives, at most 1000.
+        /// Offers keep the rate in force when they were posted
+        fee_bps: u16,
+    },
+
+    /// Replaces the settings in the program's config account
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer]` The current admin account
+    /// 1. `[writable]` The config account
+    UpdateConfig {
+        /// The new admin account
+        admin: Pubkey,
+        /// Lamports charged to post a trade
+        fee: u64,
+        /// Account the fee is paid into
+        fee_recipient: Pubkey,
+        /// Fee in basis points taken from the tokens the poster receives, at most 1000.
+        /// Offers keep the rate in force when they were posted
+        fee_bps: u16,
+    },
+
+    /// Moves collected fees out of the program owned fee account, leaving it rent exempt
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer]` The admin account
+    /// 1. `[]` The config account
+    /// 2. `[writable]` The fee account, owned by the program
+    /// 3. `[writable]` The account to send the fees to
+    WithdrawFees {
+        /// Lamports to withdraw, or everything above rent exemption if not given
+        amount: Option<u64>,
+    },
+
+    /// Moves collected token fees out of a token account owned by the fee PDA
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer]` The admin account
+    /// 1. `[]` The config account
+    /// 2. `[writable]` The fee token account, owned by the fee PDA
+    /// 3. `[writable]` The token account to send the fees to
+    /// 4. `[]` The token program of the fee token
+    /// 5. `[]` The fee PDA account
+    /// 6. `[]` The mint of the fee token
+    WithdrawTokenFees {
+        /// Amount to withdraw, or the whole balance if not given
+        amount: Option<u64>,
+    },
+
+    /// Starts a bundle trade: several tokens offered at once for several others, taken all together.
+    /// Creates a bundle account at the address `Post` would use for the offer id, a vault for each token being sold
+    /// (PDAs derived from the bundle account and the leg's index), and moves the tokens being sold into them.
+    /// Native SOL has to be traded wrapped in a bundle
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer, writable]` The account of the poster: the person posting the trade
+    /// 1. `[writable]` The bundle account, a PDA derived from the poster and offer id that will be created
+    /// 2. `[]` The system program
+    /// 3. `[]` The config account holding the fee settings
+    /// 4. `[writeable]` The fee account (for fee to be payed into, must match the config account)
+    ///
+    /// Then for each token being sold, in the order of `sell_amounts`:
+    ///
+    /// 0. `[writable]` The poster's token account for the token
+    /// 1. `[writable]` The vault token account, a PDA derived from the bundle account and the leg's index
+    /// 2. `[]` The mint of the token
+    /// 3. `[]` The token program of the token
+    ///
+    /// Then for each token asked for, in the order of `buy_amounts`:
+    ///
+    /// 0. `[]` The poster's token account that will receive the token
+    PostBundle {
+        /// Id chosen by the poster to tell their offers apart
+        offer_id: u64,
+        /// Amount of each token moved into its vault, the vault receives this less any transfer fee
+        sell_amounts: Vec<u64>,
+        /// Amount of each token the poster expects to receive
+        buy_amounts: Vec<u64>,
+        /// Optional unix timestamp after which the trade can no longer be taken
+        expires_at: Option<i64>,
+        /// Optional account that the trade is reserved for, no one else can take it
+        allowed_taker: Option<Pubkey>,
+    },
+
+    /// Takes a trade that a poster has PostBundle-d, paying for every token asked for and receiving every token
+    /// on offer in one go. The vaults and the bundle account are closed
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer, writable]` The account of the taker (person taking the trade)
+    /// 1. `[writable]` The poster's main account to send their rent fees to
+    /// 2. `[writable]` The bundle account
+    /// 3. `[]` The bundle's PDA account
+    /// 4. `[]` The config account holding the fee settings
+    ///
+    /// Then for each token the poster asked for, in the bundle's order:
+    ///
+    /// 0. `[writable]` The taker's token account to pay from
+    /// 1. `[writable]` The poster's token account that receives the token
+    /// 2. `[writable]` The token account for the token, owned by the fee PDA, to pay the fee into.
+    ///    Only checked when the bundle's `fee_bps` is not zero
+    /// 3. `[]` The mint of the token
+    /// 4. `[]` The token program of the token
+    ///
+    /// Then for each token on offer, in the bundle's order:
+    ///
+    /// 0. `[writable]` The vault token account, which is emptied and closed
+    /// 1. `[writable]` The taker's token account that receives the token
+    /// 2. `[writable]` The mint of the token, any transfer fees withheld in the vault are harvested to it
+    /// 3. `[]` The token program of the token
+    TakeBundle {
+        /// Amount of each token the taker expects to receive, before any transfer fee, must match the vaults
+        sell_amounts: Vec<u64>,
+        /// Amount of each token the taker expects to pay, including the basis-point fee and any transfer fee
+        buy_amounts: Vec<u64>,
+    },
+
+    /// Cancel a trade that a poster has PostBundle-d, returning the tokens in every vault to the poster
+    /// and closing the vaults and the bundle account
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer, writable]` The account of the original poster
+    /// 1. `[writable]` The bundle account (which will be closed)
+    /// 2. `[]` The bundle's PDA account
+    ///
+    /// Then for each token on offer, in the bundle's order:
+    ///
+    /// 0. `[writable]` The vault token account, which is emptied and closed
+    /// 1. `[writable]` The poster's token account to refund the token to
+    /// 2. `[writable]` The mint of the token
+    /// 3. `[]` The token program of the token
+    CancelBundle {},
+
+    /// Settles two Post-ed trades whose prices cross against each other, one selling the token the other wants.
+    /// Anyone may call this. The offer whose ask fits in what is left of the other is filled in full, at the other
+    /// offer's price, and whatever it offered beyond that goes to the caller. Each poster receives what they are owed
+    /// less their offer's basis-point fee and any transfer fee. Offers asking for native SOL can't be matched
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer]` The account of the matcher, the person settling the trades
+    /// 1. `[]` The config account holding the fee settings
+    ///
+    /// Then for each of the two trades:
+    ///
+    /// 0. `[writable]` The escrow account, closed if the trade is filled in full
+    /// 1. `[writable]` The escrow's token account: the vault, which is closed once empty, or the poster's delegated account
+    /// 2. `[writable]` The poster's main account to send their rent fees to
+    /// 3. `[writable]` The poster's token account that will receive tokens
+    /// 4. `[]` The escrow's PDA account
+    /// 5. `[writable]` The mint of the escrowed token
+    /// 6. `[]` The token program of the escrowed token
+    /// 7. `[writable]` The token account for the escrowed token, owned by the fee PDA, to pay the fee into.
+    ///    Only checked when the other offer's `fee_bps` is not zero
+    /// 8. `[writable]` The matcher's token account for the escrowed token, to receive any surplus into
+    Match {},
+
+    /// Creates the order book for trading a base mint against a quote mint: a market account (a PDA derived from
+    /// the two mints) and a vault token account for each mint, owned by the market account. Anyone may call this,
+    /// and pays the rent
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer, writable]` The account paying for the market
+    /// 1. `[writable]` The market account, a PDA derived from `MARKET_SEED` and the two mints that will be created
+    /// 2. `[]` The base mint
+    /// 3. `[]` The quote mint
+    /// 4. `[writable]` The base vault token account, a PDA derived from the market account and the base mint
+    /// 5. `[writable]` The quote vault token account, a PDA derived from the market account and the quote mint
+    /// 6. `[]` The token program of the base mint
+    /// 7. `[]` The token program of the quote mint
+    /// 8. `[]` The system program
+    CreateMarket {},
+
+    /// Places an order in a market's book, moving what it pays with into the market's vault: the base tokens
+    /// for an ask, the quote tokens for a bid. The order owner pays any transfer fee on top, so the vault
+    /// receives the full amount. Orders rest in the book until `MatchOrders` fills them or they are cancelled.
+    /// When that side of the book is full, the order takes the place of the worst priced order there if it has a
+    /// better price, refunding that order into its owner's claim vault
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer, writable]` The account of the order owner, which pays for the claim vault of the order taken
+    ///    out if it doesn't exist yet
+    /// 1. `[writable]` The market account
+    /// 2. `[writable]` The owner's token account to pay from, for the base token for an ask or the quote token
+    ///    for a bid
+    /// 3. `[writable]` The market's vault for the token the order pays with
+    /// 4. `[]` The mint of the token the order pays with
+    /// 5. `[]` The token program of the token the order pays with
+    /// 6. `[writable]` The claim vault, for the token the order pays with, of the owner of the worst priced order
+    ///    on the same side, a PDA derived from the market account, that owner and the mint. Only used when that
+    ///    side of the book is full
+    /// 7. `[]` The system program
+    /// 8. `[]` The config account, whose `fee_bps` the order is charged when it is filled as an ask
+    PlaceOrder {
+        side: Side,
+        /// Amount of base tokens to buy or sell
+        base_amount: u64,
+        /// Amount of quote tokens to pay for them, or to ask for them
+        quote_amount: u64,
+    },
+
+    /// Removes an order from a market's book, refunding what is left of what it paid with
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer]` The account of the order owner
+    /// 1. `[writable]` The market account
+    /// 2. `[writable]` The market's vault for the token the order paid with
+    /// 3. `[writable]` The owner's token account for that token to refund into
+    /// 4. `[]` The mint of the token the order paid with
+    /// 5. `[]` The token program of the token the order paid with
+    CancelOrder { side: Side, order_id: u64 },
+
+    /// Fills the best bid against the best ask in a market's book, for as long as their prices cross and up to
+    /// `limit` times. Each fill is at the price of whichever order was placed first. Anyone may call this.
+    /// Asks are paid the quote tokens less the basis-point fee in force when they were placed, and fully filled
+    /// bids are refunded any quote tokens they didn't need. Everything is paid into the claim vaults of the orders' owners, a PDA
+    /// derived from the market account, the owner and the mint, to be withdrawn with `WithdrawClaim`
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer, writable]` The account of the matcher, which pays for any claim vault that doesn't exist yet
+    /// 1. `[writable]` The market account
+    /// 2. `[writable]` The market's base vault
+    /// 3. `[writable]` The market's quote vault
+    /// 4. `[]` The base mint
+    /// 5. `[]` The quote mint
+    /// 6. `[]` The token program of the base mint
+    /// 7. `[]` The token program of the quote mint
+    /// 8. `[]` The config account holding the fee settings
+    /// 9. `[writable]` The token account for the quote mint, owned by the fee PDA, to pay the fee into.
+    ///    Only checked when a filled ask's `fee_bps` is not zero
+    /// 10. `[]` The system program
+    ///
+    /// Then for each fill, in order:
+    ///
+    /// 0. `[writable]` The claim vault for the quote mint of the ask's owner
+    /// 1. `[writable]` The claim vault for the base mint of the bid's owner
+    /// 2. `[writable]` The claim vault for the quote mint of the bid's owner, only used for a refund
+    MatchOrders {
+        /// Most fills to make
+        limit: u8,
+    },
+
+    /// Starts a trade like `Post`, but as a Dutch auction: the amount of token Y asked falls linearly from the
+    /// auction's start price to its end price over its duration, and stays at the end price after.
+    /// Takes pay the price at the time they are processed
+    ///
+    /// Accounts expected:
+    ///
+    /// The same as `Post`
+    PostDutchAuction {
+        /// Id chosen by the poster to tell their offers apart
+        offer_id: u64,
+        /// Amount of token X party A moves into the vault, the vault receives this less any transfer fee
+        sell_amount: u64,
+        /// The amounts of token Y asked at the start and end of the auction, and when it starts and ends
+        auction: DutchAuction,
+        /// Optional unix timestamp after which the trade can no longer be taken
+        expires_at: Option<i64>,
+        /// Optional account that the trade is reserved for, no one else can take it
+        allowed_taker: Option<Pubkey>,
+    },
+
+    /// Starts a trade like `Post`, but as an English auction: instead of being taken, the offer is bid on in
+    /// token Y until its end time, and then sold to the highest bid with `Settle`. Bids are held in a bid vault,
+    /// a PDA derived from the escrow account, created by the first bid. Bids can't be in native SOL
+    ///
+    /// Accounts expected:
+    ///
+    /// The same as `Post`, with the poster's token account for token Y as the account receiving the winning bid
+    PostEnglishAuction {
+        /// Id chosen by the poster to tell their offers apart
+        offer_id: u64,
+        /// Amount of token X party A moves into the vault, the vault receives this less any transfer fee
+        sell_amount: u64,
+        /// Lowest first bid, in token Y
+        reserve_price: u64,
+        /// Least each bid has to beat the one before by
+        min_increment: u64,
+        /// Unix timestamp bidding closes at
+        end_time: i64,
+        /// Optional account that the auction is reserved for, no one else can bid
+        allowed_taker: Option<Pubkey>,
+    },
+
+    /// Bids on an English auction, moving the bid into the bid vault and refunding the bid it beats into its
+    /// bidder's claim vault, to be withdrawn with `WithdrawClaim`.
+    /// The bidder pays any transfer fee on top, so the vault receives the full bid
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer, writable]` The account of the bidder, which pays for the bid vault on the first bid, for
+    ///    their claim vault if they don't have one yet, and for the beaten bidder's if theirs was withdrawn
+    /// 1. `[writable]` The escrow account
+    /// 2. `[writable]` The bidder's token account for token Y to bid from
+    /// 3. `[writable]` The bidder's claim vault for token Y, a PDA derived from the escrow account, the bidder
+    ///    and the mint
+    /// 4. `[writable]` The bid vault token account, a PDA derived from the escrow account
+    /// 5. `[writable]` The claim vault for token Y of the bidder being beaten, only used when there is one
+    /// 6. `[]` The mint of token Y
+    /// 7. `[]` The token program of token Y
+    /// 8. `[]` The escrow's PDA account
+    /// 9. `[]` The system program
+    Bid {
+        /// Amount of token Y bid
+        amount: u64,
+    },
+
+    /// Sells the escrowed tokens of an English or sealed-bid auction to its highest bid once bidding has closed,
+    /// paying the bid into the poster's claim vault for token Y less the offer's basis-point fee, and the escrowed
+    /// tokens into the winner's claim vault for token X. Anyone may call this. The vaults and the escrow account are closed, the rent of
+    /// the bid vault going to the winner.
+    /// Auctions without bids are cancelled by the poster with `Cancel` instead.
+    /// A sealed-bid auction can only be settled once revealing has closed and every unrevealed bid's deposit has
+    /// been released with `ReleaseDeposit`
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer, writable]` The account of the caller, which pays for the poster's and the winner's claim
+    ///    vaults if they don't have them yet
+    /// 1. `[writable]` The escrow account
+    /// 2. `[writable]` The escrow's vault
+    /// 3. `[writable]` The poster's main account to send their rent fees to
+    /// 4. `[writable]` The poster's claim vault for token Y, that receives the winning bid, a PDA derived from the
+    ///    escrow account, the poster and the mint
+    /// 5. `[writable]` The bid vault
+    /// 6. `[writable]` The winner's claim vault for token X, a PDA derived from the escrow account, the winner
+    ///    and the mint
+    /// 7. `[writable]` The winner's main account, to send the bid vault's rent to
+    /// 8. `[writable]` The mint of token X
+    /// 9. `[]` The token program of token X
+    /// 10. `[writable]` The mint of token Y
+    /// 11. `[]` The token program of token Y
+    /// 12. `[]` The escrow's PDA account
+    /// 13. `[]` The config account holding the fee settings
+    /// 14. `[writable]` The token account for token Y, owned by the fee PDA, to pay the fee into.
+    ///     Only checked when the offer's `fee_bps` is not zero
+    /// 15. `[]` The system program
+    /// 16. `[writable]` The winner's claim vault for token Y, refunded what they deposited beyond their bid.
+    ///     Only needed for sealed-bid auctions
+    Settle {},
+
+    /// Starts a trade like `Post`, but as a sealed-bid auction: until `commit_end` bidders commit to hidden bids
+    /// with `CommitBid`, then until `reveal_end` they reveal them with `RevealBid`, after which the offer is sold
+    /// to the highest revealed bid with `Settle`
+    ///
+    /// Accounts expected: the same as `Post`
+    PostSealedAuction {
+        /// Id chosen by the poster to tell their offers apart
+        offer_id: u64,
+        /// Amount of token X party A moves into the vault, the vault receives this less any transfer fee
+        sell_amount: u64,
+        /// Lowest bid that can win, in token Y
+        reserve_price: u64,
+        /// Unix timestamp bidding closes and revealing opens at
+        commit_end: i64,
+        /// Unix timestamp revealing closes at
+        reveal_end: i64,
+        /// What happens to the deposits of bids that aren't revealed
+        unrevealed: UnrevealedPolicy,
+        /// Optional account that the auction is reserved for, no one else can bid
+        allowed_taker: Option<Pubkey>,
+    },
+
+    /// Commits to a hidden bid on a sealed-bid auction, creating the bidder's sealed bid account and moving a
+    /// deposit into the bid vault. The deposit is the most the bid can be, and can be made larger than the bid to
+    /// hide it. The bidder pays any transfer fee on top, so the vault receives the full deposit.
+    /// Each bidder can commit once per auction
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer, writable]` The account of the bidder, which pays for the sealed bid account, for their claim
+    ///    vault if they don't have one yet, and for the bid vault on the first bid
+    /// 1. `[writable]` The escrow account
+    /// 2. `[writable]` The bidder's token account for token Y to deposit from
+    /// 3. `[writable]` The bidder's claim vault for token Y, a PDA derived from the escrow account, the bidder
+    ///    and the mint, that the deposit is refunded into later
+    /// 4. `[writable]` The bid vault token account, a PDA derived from the escrow account
+    /// 5. `[writable]` The sealed bid account, a PDA derived from the escrow account and the bidder
+    /// 6. `[]` The mint of token Y
+    /// 7. `[]` The token program of token Y
+    /// 8. `[]` The escrow's PDA account
+    /// 9. `[]` The system program
+    CommitBid {
+        /// Hash of the bidder, the amount and a secret salt, made with `sealed_bid_commitment`
+        commitment: [u8; 32],
+        /// Amount of token Y deposited
+        deposit: u64,
+    },
+
+    /// Reveals a sealed bid once bidding has closed, closing the sealed bid account. A bid of at least the
+    /// reserve price and at most its deposit that beats the highest bid so far becomes the highest, refunding
+    /// the one it beats, otherwise its deposit is refunded. Refunds are paid into the bidder's claim vault.
+    /// If that leaves the bid vault empty, with no bid to settle, it is closed too
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer, writable]` The account of the bidder, to send the sealed bid account's rent to, which pays
+    ///    for any claim vault refunded into that has been withdrawn
+    /// 1. `[writable]` The escrow account
+    /// 2. `[writable]` The sealed bid account
+    /// 3. `[writable]` The bid vault
+    /// 4. `[writable]` The bidder's claim vault for token Y
+    /// 5. `[writable]` The claim vault for token Y of the highest bidder so far, only used when there is one
+    /// 6. `[]` The mint of token Y
+    /// 7. `[]` The token program of token Y
+    /// 8. `[]` The escrow's PDA account
+    /// 9. `[]` The system program
+    RevealBid {
+        /// Amount of token Y bid
+        amount: u64,
+        /// Salt the commitment was made with
+        salt: [u8; 32],
+    },
+
+    /// Releases the deposit of a sealed bid that wasn't revealed in time, refunding it into the bidder's claim
+    /// vault or paying it into the poster's depending on the auction's `UnrevealedPolicy`, and closing the sealed
+    /// bid account.
+    /// Anyone may call this once revealing has closed. If that leaves the bid vault empty, with no bid to settle,
+    /// it is closed too, its rent going to the bidder
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer, writable]` The account of the caller, which pays for the claim vault if there isn't one yet
+    /// 1. `[writable]` The escrow account
+    /// 2. `[writable]` The sealed bid account
+    /// 3. `[writable]` The bidder's main account, to send the sealed bid account's rent to
+    /// 4. `[writable]` The bid vault
+    /// 5. `[writable]` The bidder's claim vault for token Y to refund the deposit into, or the poster's claim vault
+    ///    for token Y if deposits are forfeited
+    /// 6. `[]` The mint of token Y
+    /// 7. `[]` The token program of token Y
+    /// 8. `[]` The escrow's PDA account
+    /// 9. `[]` The system program
+    ReleaseDeposit {},
+
+    /// Writes a covered call: starts a trade like `Post`, moving token X into the vault, and creates the option
+    /// mint. Once bought with `BuyOption`, whoever holds the option token can buy the escrowed tokens for the
+    /// strike with `Exercise` until the option expires. Until it is bought the writer can `Cancel` it, and once
+    /// it has expired anyone can `Reclaim` the escrowed tokens for the writer.
+    /// The mint of an earlier option written under the same offer id is reused, as long as its token was burned
+    ///
+    /// Accounts expected: the same as `Post`, followed by
+    ///
+    /// 10. `[writable]` The option mint, a PDA derived from the escrow account
+    /// 11. `[]` The token program of the option mint
+    WriteOption {
+        /// Id chosen by the poster to tell their offers apart
+        offer_id: u64,
+        /// Amount of token X party A moves into the vault, the vault receives this less any transfer fee
+        sell_amount: u64,
+        /// Amount of token Y the holder pays to exercise the option
+        strike: u64,
+        /// Amount of token Y the buyer of the option pays for it
+        premium: u64,
+        /// Unix timestamp the option expires at
+        expires_at: i64,
+    },
+
+    /// Buys a covered call, paying the premium to the writer less the offer's basis-point fee and minting the
+    /// option token to the buyer. The buyer pays any transfer fee on top, so the writer receives their full share
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer]` The account of the buyer
+    /// 1. `[writable]` The escrow account
+    /// 2. `[writable]` The buyer's token account for token Y to pay the premium from
+    /// 3. `[writable]` The writer's token account for token Y, that receives the premium
+    /// 4. `[writable]` The buyer's token account for the option token
+    /// 5. `[writable]` The option mint
+    /// 6. `[]` The mint of token Y
+    /// 7. `[]` The token program of token Y
+    /// 8. `[]` The token program of the option mint
+    /// 9. `[]` The escrow's PDA account
+    /// 10. `[]` The config account holding the fee settings
+    /// 11. `[writable]` The token account for token Y, owned by the fee PDA, to pay the fee into.
+    ///     Only checked when the offer's `fee_bps` is not zero
+    BuyOption {},
+
+    /// Exercises a covered call before it expires, burning the holder's option token and paying the strike to
+    /// the writer less the offer's basis-point fee, for the escrowed tokens. The holder pays any transfer fee
+    /// on top of the strike. The vault and the escrow account are closed
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer]` The account of the holder of the option token
+    /// 1. `[writable]` The escrow account
+    /// 2. `[writable]` The escrow's vault
+    /// 3. `[writable]` The writer's main account to send their rent fees to
+    /// 4. `[writable]` The writer's token account for token Y, that receives the strike
+    /// 5. `[writable]` The holder's token account for token Y to pay the strike from
+    /// 6. `[writable]` The holder's token account for token X to receive the escrowed tokens into
+    /// 7. `[writable]` The holder's token account for the option token
+    /// 8. `[writable]` The option mint
+    /// 9. `[writable]` The mint of token X
+    /// 10. `[]` The token program of token X
+    /// 11. `[]` The mint of token Y
+    /// 12. `[]` The token program of token Y
+    /// 13. `[]` The token program of the option mint
+    /// 14. `[]` The escrow's PDA account
+    /// 15. `[]` The config account holding the fee settings
+    /// 16. `[writable]` The token account for token Y, owned by the fee PDA, to pay the fee into.
+    ///     Only checked when the offer's `fee_bps` is not zero
+    Exercise {},
+
+    /// Fills a maker's signed order in full. The instruction just before this one in the transaction has to be
+    /// an ed25519 program instruction verifying the maker's signature of the order, with the signature, public key
+    /// and message all in its own data. The taker pays the buy amount to the maker less the basis-point fee signed
+    /// in the order, which has to be at least the config's, with any transfer fee on top, and the sell amount is moved out of the maker's account by the order PDA,
+    /// which the maker approved as its delegate. The order's nonce is marked used in the maker's nonce page,
+    /// which the taker pays for if it is the first nonce used on it
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer, writable]` The account of the taker
+    /// 1. `[writable]` The maker's token account for token X, with the order PDA as its delegate
+    /// 2. `[writable]` The maker's token account for token Y, as given in the order
+    /// 3. `[writable]` The taker's token account for token Y to pay from
+    /// 4. `[writable]` The taker's token account for token X to receive into
+    /// 5. `[]` The mint of token X
+    /// 6. `[]` The token program of token X
+    /// 7. `[]` The mint of token Y
+    /// 8. `[]` The token program of token Y
+    /// 9. `[]` The order PDA, derived from `ORDER_SEED`
+    /// 10. `[writable]` The maker's nonce page for the order's nonce, a PDA derived from the maker and the page
+    /// 11. `[]` The instructions sysvar
+    /// 12. `[]` The system program
+    /// 13. `[]` The config account holding the fee settings
+    /// 14. `[writable]` The token account for token Y, owned by the fee PDA, to pay the fee into.
+    ///     Only checked when the order's `fee_bps` is not zero
+    FillSignedOrder {
+        /// The order, exactly as the maker signed it
+        order: SignedOrder,
+    },
+
+    /// Cancels every signed order of the maker's with the given nonce by marking it used, creating the nonce page
+    /// if it is the first nonce used on it
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer, writable]` The account of the maker
+    /// 1. `[writable]` The maker's nonce page for the nonce, a PDA derived from the maker and the page
+    /// 2. `[]` The system program
+    CancelSignedOrder {
+        /// Nonce of the orders to cancel
+        nonce: u64,
+    },
+
+    /// Requests quotes to buy an amount of a token, creating the request's account. Makers answer it with
+    /// `PostQuote` until the deadline
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer, writable]` The account of the requester, which pays for the request's account
+    /// 1. `[writable]` The request's account, a PDA derived from the requester and the request id
+    /// 2. `[]` The system program
+    CreateRfq {
+        /// Id chosen by the requester to tell their requests apart
+        rfq_id: u64,
+        /// Mint of the token the requester wants to buy
+        mint: Pubkey,
+        /// Amount of the token the requester wants to buy
+        amount: u64,
+        /// Mint of the token the requester pays with, the native mint to pay in SOL
+        pay_mint: Pubkey,
+        /// Unix timestamp quotes can be posted and accepted until
+        deadline: i64,
+    },
+
+    /// Withdraws a request for quotes, closing its account. Quotes already posted on it stay open until their
+    /// makers cancel them or they expire at the deadline
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer, writable]` The account of the requester, to send the request's rent to
+    /// 1. `[writable]` The request's account
+    CancelRfq {},
+
+    /// Quotes a price for a request for quotes: starts a trade like `Post`, moving the requested amount of the
+    /// token into the vault, reserved for the requester and expiring at the request's deadline. A quote can't be
+    /// taken or amended, only accepted with `AcceptQuote`, cancelled by its maker, or reclaimed once expired
+    ///
+    /// Accounts expected: the same as `Post`, with the requested token sold and the poster's account to receive the
+    /// token the requester pays with, followed by
+    ///
+    /// 10. `[]` The request's account
+    PostQuote {
+        /// Id chosen by the poster to tell their offers apart
+        offer_id: u64,
+        /// Amount of the token the requester pays with that the poster asks for
+        price: u64,
+    },
+
+    /// Accepts a quote, taking it in full as `Take` would, and closes the request. Other quotes on the request
+    /// whose `Reclaim` accounts are passed after the accounts of `Take` are refunded to their makers as `Reclaim`
+    /// would, without a reward. Quotes that aren't passed are not refunded: they stay open, holding their makers'
+    /// tokens even though the request is closed, until their makers cancel them or they expire at the deadline
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[writable]` The request's account, which is closed
+    /// 1. - 17. The accounts of `Take`, for the quote being accepted, with the requester as the taker
+    ///
+    /// followed by, for every other quote being refunded, the accounts of `Reclaim` without the caller:
+    ///
+    /// 0. `[writable]` The quote's vault
+    /// 1. `[writable]` The quote's escrow account
+    /// 2. `[writable]` The maker's main account to send their rent fees to
+    /// 3. `[writable]` The maker's token account to refund tokens to, or their main account again to unwrap SOL into
+    /// 4. `[]` The token program of the requested token
+    /// 5. `[]` The quote's escrow PDA account
+    /// 6. `[writable]` The mint of the requested token
+    AcceptQuote {
+        /// Amount the requester pays, as for `Take`
+        sell_amount: u64,
+    },
+
+    /// Withdraws everything in one of the owner's claim vaults, holding what an auction or a market owes them,
+    /// and closes it. Refunds of beaten bids, the tokens won in an auction, the winning bid paid to its poster and
+    /// the proceeds of orders in a market's book go into claim vaults rather than the owner's own token accounts, so no one can stop an auction going
+    /// ahead, or a book being matched, by closing theirs
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer, writable]` The account of the claim vault's owner, to send its rent to
+    /// 1. `[writable]` The claim vault
+    /// 2. `[writable]` The owner's token account to withdraw into
+    /// 3. `[writable]` The mint of the claim vault's token
+    /// 4. `[]` The token program of the claim vault's token
+    /// 5. `[]` The owner's claim authority, the PDA owning their claim vaults
+    WithdrawClaim {},
+
+    /// Returns the tokens of an expired bundle to the poster, closing its vaults and the bundle account.
+    /// Anyone may call this, and the caller is paid a small reward out of the bundle account's rent
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer, writable]` The account of the caller, which receives the reward
+    /// 1. `[writable]` The bundle account (which will be closed)
+    /// 2. `[writable]` The poster's main account to send their rent fees to
+    /// 3. `[]` The bundle's PDA account
+    ///
+    /// Then for each token on offer, in the bundle's order:
+    ///
+    /// 0. `[writable]` The vault token account, which is emptied and closed
+    /// 1. `[writable]` The poster's token account to refund the token to
+    /// 2. `[writable]` The mint of the token
+    /// 3. `[]` The token program of the token
+    ReclaimBundle {},
 }
@@ -5,6 +5,64 @@ mod processor;
 mod state;
 
 pub use instruction::Instruction;
-pub use processor::fee_account_pubkey;
+pub use processor::amount_after_transfer_fee;
+pub use processor::amount_with_transfer_fee;
+pub use processor::fill_price;
+pub use processor::find_bid_vault;
+pub use processor::find_bundle_vault;
+pub use processor::find_claim_authority;
+pub use processor::find_claim_vault;
+pub use processor::find_config_address;
+pub use processor::find_escrow_address;
+pub use processor::find_escrow_authority;
+pub use processor::find_escrow_vault;
+pub use processor::find_fee_authority;
+pub use processor::find_market_address;
+pub use processor::find_market_vault;
+pub use processor::find_nonce_page;
+pub use processor::find_option_mint;
+pub use processor::find_order_authority;
+pub use processor::find_rfq_address;
+pub use processor::find_sealed_bid_address;
+pub use processor::find_unwrap_account;
+pub use processor::match_fill;
+pub use processor::match_orders;
+pub use processor::sealed_bid_commitment;
+pub use processor::token_fee;
+pub use processor::MatchFill;
+pub use processor::OrderFill;
+pub use processor::BID_SEED;
+pub use processor::CLAIM_SEED;
+pub use processor::CONFIG_SEED;
 pub use processor::ESCROW_SEED;
+pub use processor::FEE_SEED;
+pub use processor::MARKET_SEED;
+pub use processor::NONCE_SEED;
+pub use processor::OFFER_SEED;
+pub use processor::OPTION_SEED;
+pub use processor::ORDER_SEED;
+pub use processor::RECLAIM_REWARD;
+pub use processor::RFQ_SEED;
+pub use processor::UNWRAP_SEED;
+pub use processor::VAULT_SEED;
+pub use state::Bid;
+pub use state::Bundle;
+pub use state::CallOption;
+pub use state::Config;
+pub use state::Custody;
+pub use state::DutchAuction;
+pub use state::EnglishAuction;
 pub use state::Escrow;
+pub use state::Leg;
+pub use state::Market;
+pub use state::NoncePage;
+pub use state::Order;
+pub use state::Rfq;
+pub use state::SealedAuction;
+pub use state::SealedBid;
+pub use state::Side;
+pub use state::SignedOrder;
+pub use state::UnrevealedPolicy;
+pub use state::MAX_BUNDLE_LEGS;
+pub use state::MAX_ORDERS;
+pub use state::NONCES_PER_PAGE;
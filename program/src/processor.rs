@@ -1,28 +1,411 @@
-use std::str::FromStr;
-
 use crate::instruction::Instruction;
-use crate::{error::Error, state::Escrow};
+use crate::{
+    error::Error,
+    state::{
+        Bid, Bundle, CallOption, Config, Custody, DutchAuction, EnglishAuction, Escrow, Leg,
+        Market, NoncePage, Order, Rfq, SealedAuction, SealedBid, Side, SignedOrder,
+        UnrevealedPolicy, MAX_BUNDLE_LEGS, NONCES_PER_PAGE,
+    },
+};
 
 use borsh::{BorshDeserialize, BorshSerialize};
-use solana_program::native_token::LAMPORTS_PER_SOL;
 use solana_program::{
     account_info::{next_account_info, AccountInfo},
+    borsh::get_packed_len,
+    bpf_loader_upgradeable::{self, UpgradeableLoaderState},
+    clock::Clock,
     entrypoint::ProgramResult,
     msg,
     program::{invoke, invoke_signed},
     program_error::ProgramError,
+    program_option::COption,
     program_pack::Pack,
+    program_utils::limited_deserialize,
     pubkey::Pubkey,
     rent::Rent,
-    sysvar::Sysvar,
+    sysvar::{
+        instructions::{load_current_index_checked, load_instruction_at_checked},
+        Sysvar,
+    },
+};
+use spl_token_2022::{
+    extension::{
+        transfer_fee::{TransferFee, TransferFeeAmount, TransferFeeConfig},
+        ExtensionType, StateWithExtensions,
+    },
+    state::{Account as TokenAccount, Mint},
 };
 
 pub const ESCROW_SEED: &[u8] = b"escrow";
 
-pub const FEE: u64 = LAMPORTS_PER_SOL;
+pub const OFFER_SEED: &[u8] = b"offer";
+
+pub const VAULT_SEED: &[u8] = b"vault";
+
+pub const CONFIG_SEED: &[u8] = b"config";
+
+pub const FEE_SEED: &[u8] = b"fee";
+
+pub const UNWRAP_SEED: &[u8] = b"unwrap";
+
+pub const MARKET_SEED: &[u8] = b"market";
+
+pub const BID_SEED: &[u8] = b"bid";
+
+pub const OPTION_SEED: &[u8] = b"option";
+
+pub const ORDER_SEED: &[u8] = b"order";
+
+pub const NONCE_SEED: &[u8] = b"nonce";
+
+pub const RFQ_SEED: &[u8] = b"rfq";
+
+pub const CLAIM_SEED: &[u8] = b"claim";
+
+const MAX_FEE_BPS: u16 = 1_000;
+
+/// Where the public key starts in the data of an ed25519 program instruction with one signature
+const ED25519_DATA_START: usize = 16;
+
+/// Lamports paid out of an expired escrow's rent to whoever reclaims it
+pub const RECLAIM_REWARD: u64 = 100_000;
+
+/// Address of the program's config account
+pub fn find_config_address(program_id: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[CONFIG_SEED], program_id)
+}
+
+/// Address of the PDA that owns the token accounts basis-point fees are paid into
+pub fn find_fee_authority(program_id: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[FEE_SEED], program_id)
+}
+
+/// Price the taker pays for `fill_amount` out of the `remaining_amount` left in an offer asking `buy_amount`.
+/// Rounds up so the poster never receives less than their asking rate.
+pub fn fill_price(fill_amount: u64, remaining_amount: u64, buy_amount: u64) -> Option<u64> {
+    if fill_amount == 0 || fill_amount > remaining_amount {
+        return None;
+    }
+    let numerator = (fill_amount as u128).checked_mul(buy_amount as u128)?;
+    let remaining_amount = remaining_amount as u128;
+    let mut price = numerator / remaining_amount;
+    if numerator % remaining_amount != 0 {
+        price += 1;
+    }
+    u64::try_from(price).ok()
+}
+
+/// How `Match` settles two crossing offers
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MatchFill {
+    /// Whether the second offer is the one filled in full, rather than the first
+    pub second_filled: bool,
+    /// Amount of the other offer's token the fully filled offer's poster receives, what they asked for
+    pub amount: u64,
+    /// Amount of the fully filled offer's token the other offer's poster receives for it, at their price
+    pub price: u64,
+    /// What is left of the fully filled offer's token, paid to the matcher
+    pub surplus: u64,
+}
+
+/// Works out how `Match` settles two offers, each given as what is left on offer and the amount asked for it.
+/// The second offer is filled in full if what it asks for fits in what is left of the first, otherwise the first is.
+/// `None` if the offers' prices don't cross
+pub fn match_fill(
+    first_remaining: u64,
+    first_buy: u64,
+    second_remaining: u64,
+    second_buy: u64,
+) -> Option<MatchFill> {
+    let (second_filled, filled_remaining, filled_buy, other_remaining, other_buy) =
+        if second_buy <= first_remaining {
+            (
+                true,
+                second_remaining,
+                second_buy,
+                first_remaining,
+                first_buy,
+            )
+        } else {
+            (
+                false,
+                first_remaining,
+                first_buy,
+                second_remaining,
+                second_buy,
+            )
+        };
+    let price = fill_price(filled_buy, other_remaining, other_buy)?;
+    // a partial fill has to leave the other offer asking for something
+    if price > filled_remaining || (filled_buy < other_remaining && price >= other_buy) {
+        return None;
+    }
+    Some(MatchFill {
+        second_filled,
+        amount: filled_buy,
+        price,
+        surplus: filled_remaining - price,
+    })
+}
+
+/// One fill of a bid against an ask made by `MatchOrders`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OrderFill {
+    /// Owner of the ask, paid its quote tokens
+    pub ask_owner: Pubkey,
+    /// Owner of the bid, paid its base tokens and refunded its unneeded quote tokens
+    pub bid_owner: Pubkey,
+    /// Amount of base tokens sold
+    pub base_amount: u64,
+    /// Amount of quote tokens paid for them
+    pub quote_amount: u64,
+    /// Quote tokens the bid paid in that it no longer needs, once it is filled in full
+    pub bid_refund: u64,
+    /// Basis-point fee taken from the quote tokens paid to the ask, its rate when it was placed
+    pub ask_fee_bps: u16,
+}
+
+/// Fills the best bid against the best ask of `market` for as long as their prices cross, up to `limit` times,
+/// taking filled orders out of the book. Each fill is at the price of whichever order was placed first,
+/// and leaves each order asking the rest of its price for the rest of its tokens
+pub fn match_orders(market: &mut Market, limit: usize) -> Vec<OrderFill> {
+    let mut fills = Vec::new();
+    while fills.len() < limit {
+        let (ask, bid) = match (market.asks.first(), market.bids.first()) {
+            (Some(ask), Some(bid)) => (*ask, *bid),
+            _ => break,
+        };
+        if (bid.quote_amount as u128 * ask.base_amount as u128)
+            < (ask.quote_amount as u128 * bid.base_amount as u128)
+        {
+            break;
+        }
+        let base_amount = ask.base_amount.min(bid.base_amount);
+        let (ask_share, bid_share) = match (
+            fill_price(base_amount, ask.base_amount, ask.quote_amount),
+            fill_price(base_amount, bid.base_amount, bid.quote_amount),
+        ) {
+            (Some(ask_share), Some(bid_share)) => (ask_share, bid_share),
+            _ => break,
+        };
+        let quote_amount = if ask.id < bid.id {
+            ask_share
+        } else {
+            bid_share
+        };
+        // a partial fill has to leave each order asking for something
+        if (base_amount < ask.base_amount && ask_share >= ask.quote_amount)
+            || (base_amount < bid.base_amount && quote_amount >= bid.quote_amount)
+        {
+            break;
+        }
+
+        let mut bid_refund = 0;
+        let best_ask = &mut market.asks[0];
+        best_ask.base_amount -= base_amount;
+        best_ask.quote_amount -= ask_share;
+        if best_ask.base_amount == 0 {
+            market.asks.remove(0);
+        }
+        let best_bid = &mut market.bids[0];
+        best_bid.base_amount -= base_amount;
+        best_bid.quote_amount -= quote_amount;
+        if best_bid.base_amount == 0 {
+            bid_refund = best_bid.quote_amount;
+            market.bids.remove(0);
+        }
+        fills.push(OrderFill {
+            ask_owner: ask.owner,
+            bid_owner: bid.owner,
+            base_amount,
+            quote_amount,
+            bid_refund,
+            ask_fee_bps: ask.fee_bps,
+        });
+    }
+    fills
+}
+
+/// Basis-point fee taken out of `amount`, rounded down in favour of the poster
+pub fn token_fee(amount: u64, fee_bps: u16) -> u64 {
+    (amount as u128 * fee_bps as u128 / 10_000) as u64
+}
+
+/// Amount of a token that has to be sent for `amount` to arrive, given the mint's account data.
+/// Only differs from `amount` for Token-2022 mints with a transfer fee
+pub fn amount_with_transfer_fee(
+    mint_data: &[u8],
+    epoch: u64,
+    amount: u64,
+) -> Result<u64, ProgramError> {
+    match epoch_transfer_fee(mint_data, epoch)? {
+        Some(transfer_fee) => {
+            gross_amount(&transfer_fee, amount).ok_or_else(|| Error::AmountOverflow.into())
+        }
+        None => Ok(amount),
+    }
+}
+
+/// Amount of a token that arrives when `amount` is sent, given the mint's account data
+pub fn amount_after_transfer_fee(
+    mint_data: &[u8],
+    epoch: u64,
+    amount: u64,
+) -> Result<u64, ProgramError> {
+    match epoch_transfer_fee(mint_data, epoch)? {
+        Some(transfer_fee) => {
+            let fee = transfer_fee
+                .calculate(amount)
+                .ok_or(Error::AmountOverflow)?;
+            Ok(amount - fee)
+        }
+        None => Ok(amount),
+    }
+}
+
+fn epoch_transfer_fee(mint_data: &[u8], epoch: u64) -> Result<Option<TransferFee>, ProgramError> {
+    let mint = StateWithExtensions::<Mint>::unpack(mint_data)?;
+    Ok(mint
+        .get_extension::<TransferFeeConfig>()
+        .ok()
+        .map(|config| *config.get_epoch_fee(epoch)))
+}
+
+/// Smallest amount that is at least `amount` once the transfer fee is taken out of it.
+/// The amount left after the fee never decreases as the amount sent grows, so it can be searched for
+fn gross_amount(transfer_fee: &TransferFee, amount: u64) -> Option<u64> {
+    let mut low = amount;
+    let mut high = amount.checked_add(u64::from(transfer_fee.maximum_fee))?;
+    while low < high {
+        let middle = low + (high - low) / 2;
+        if middle - transfer_fee.calculate(middle)? >= amount {
+            high = middle;
+        } else {
+            low = middle + 1;
+        }
+    }
+    Some(low)
+}
+
+/// Address of the escrow account for the poster's offer with the given id
+pub fn find_escrow_address(poster: &Pubkey, offer_id: u64, program_id: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[OFFER_SEED, poster.as_ref(), &offer_id.to_le_bytes()],
+        program_id,
+    )
+}
+
+/// Address of the PDA that holds authority over the token account of the given escrow
+pub fn find_escrow_authority(
+    escrow: &Pubkey,
+    poster: &Pubkey,
+    program_id: &Pubkey,
+) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[ESCROW_SEED, escrow.as_ref(), poster.as_ref()], program_id)
+}
+
+/// Address of the token account the program creates to hold the given escrow's tokens
+pub fn find_escrow_vault(escrow: &Pubkey, program_id: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[VAULT_SEED, escrow.as_ref()], program_id)
+}
+
+/// Address of the vault holding the bundle's token on offer at `index`
+pub fn find_bundle_vault(bundle: &Pubkey, index: u8, program_id: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[VAULT_SEED, bundle.as_ref(), &[index]], program_id)
+}
+
+/// Address of the token account holding the highest bid on the given escrow's English auction
+pub fn find_bid_vault(escrow: &Pubkey, program_id: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[BID_SEED, escrow.as_ref()], program_id)
+}
+
+/// Address of the token account holding what the auction of the given escrow, or the given market, owes `owner`
+/// in `mint`, until they withdraw it
+pub fn find_claim_vault(
+    escrow: &Pubkey,
+    owner: &Pubkey,
+    mint: &Pubkey,
+    program_id: &Pubkey,
+) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[CLAIM_SEED, escrow.as_ref(), owner.as_ref(), mint.as_ref()],
+        program_id,
+    )
+}
+
+/// Address of the PDA owning every claim vault of `owner`, which only `owner` can withdraw from
+pub fn find_claim_authority(owner: &Pubkey, program_id: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[CLAIM_SEED, owner.as_ref()], program_id)
+}
+
+/// Address of the account holding a bidder's commitment to the given escrow's sealed-bid auction
+pub fn find_sealed_bid_address(
+    escrow: &Pubkey,
+    bidder: &Pubkey,
+    program_id: &Pubkey,
+) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[BID_SEED, escrow.as_ref(), bidder.as_ref()], program_id)
+}
+
+/// Commitment to a sealed bid of `amount` by `bidder`, hidden by a secret `salt` until it is revealed.
+/// The bidder is hashed in so no one can copy another's commitment and reveal it once theirs is public
+pub fn sealed_bid_commitment(bidder: &Pubkey, amount: u64, salt: &[u8; 32]) -> [u8; 32] {
+    solana_program::hash::hashv(&[bidder.as_ref(), &amount.to_le_bytes(), salt]).to_bytes()
+}
+
+/// Address of the mint of the option token for the covered call written on the given escrow
+pub fn find_option_mint(escrow: &Pubkey, program_id: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[OPTION_SEED, escrow.as_ref()], program_id)
+}
+
+/// Address of the PDA makers approve as the delegate of the tokens their signed orders sell
+pub fn find_order_authority(program_id: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[ORDER_SEED], program_id)
+}
+
+/// Address of the maker's nonce page recording the given page of their nonces, see `NoncePage::page`
+pub fn find_nonce_page(maker: &Pubkey, page: u64, program_id: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[NONCE_SEED, maker.as_ref(), &page.to_le_bytes()],
+        program_id,
+    )
+}
+
+/// Address of the requester's request for quotes with the given id
+pub fn find_rfq_address(requester: &Pubkey, rfq_id: u64, program_id: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[RFQ_SEED, requester.as_ref(), &rfq_id.to_le_bytes()],
+        program_id,
+    )
+}
+
+/// Address of the order book for trading `base_mint` against `quote_mint`
+pub fn find_market_address(
+    base_mint: &Pubkey,
+    quote_mint: &Pubkey,
+    program_id: &Pubkey,
+) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[MARKET_SEED, base_mint.as_ref(), quote_mint.as_ref()],
+        program_id,
+    )
+}
 
-pub fn fee_account_pubkey() -> Pubkey {
-    Pubkey::from_str("Btun84XLwZEtu4XjHwskq2Xu5qQK5FYw17UDDCfVHTbY").unwrap()
+/// Address of the token account holding a market's tokens of `mint`
+pub fn find_market_vault(market: &Pubkey, mint: &Pubkey, program_id: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[VAULT_SEED, market.as_ref(), mint.as_ref()], program_id)
+}
+
+/// Address of the temporary wrapped SOL account used to pay native SOL out of the given escrow to `recipient`
+pub fn find_unwrap_account(
+    escrow: &Pubkey,
+    recipient: &Pubkey,
+    program_id: &Pubkey,
+) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[UNWRAP_SEED, escrow.as_ref(), recipient.as_ref()],
+        program_id,
+    )
 }
 
 pub fn process(
@@ -34,19 +417,283 @@ pub fn process(
         Instruction::try_from_slice(instruction_data).map_err(|_| Error::InvalidInstruction)?;
 
     match instruction {
-        Instruction::Post { buy_amount } => process_post(program_id, accounts, buy_amount),
+        Instruction::Post {
+            offer_id,
+            sell_amount,
+            buy_amount,
+            expires_at,
+            allowed_taker,
+        } => process_post(
+            program_id,
+            accounts,
+            offer_id,
+            sell_amount,
+            buy_amount,
+            expires_at,
+            allowed_taker,
+        ),
+        Instruction::PostDelegated {
+            offer_id,
+            sell_amount,
+            buy_amount,
+            expires_at,
+            allowed_taker,
+        } => process_post_delegated(
+            program_id,
+            accounts,
+            offer_id,
+            sell_amount,
+            buy_amount,
+            expires_at,
+            allowed_taker,
+        ),
         Instruction::Take {
             buy_amount,
             sell_amount,
-        } => process_take(program_id, accounts, buy_amount, sell_amount),
+            version,
+        } => process_take(program_id, accounts, buy_amount, sell_amount, version),
         Instruction::Cancel {} => process_cancel(program_id, accounts),
+        Instruction::Amend {
+            buy_amount,
+            sell_amount,
+        } => process_amend(program_id, accounts, buy_amount, sell_amount),
+        Instruction::Reclaim {} => process_reclaim(program_id, accounts),
+        Instruction::InitializeConfig {
+            fee,
+            fee_recipient,
+            fee_bps,
+        } => process_initialize_config(program_id, accounts, fee, fee_recipient, fee_bps),
+        Instruction::UpdateConfig {
+            admin,
+            fee,
+            fee_recipient,
+            fee_bps,
+        } => process_update_config(program_id, accounts, admin, fee, fee_recipient, fee_bps),
+        Instruction::WithdrawFees { amount } => process_withdraw_fees(program_id, accounts, amount),
+        Instruction::WithdrawTokenFees { amount } => {
+            process_withdraw_token_fees(program_id, accounts, amount)
+        }
+        Instruction::PostBundle {
+            offer_id,
+            sell_amounts,
+            buy_amounts,
+            expires_at,
+            allowed_taker,
+        } => process_post_bundle(
+            program_id,
+            accounts,
+            offer_id,
+            &sell_amounts,
+            &buy_amounts,
+            expires_at,
+            allowed_taker,
+        ),
+        Instruction::TakeBundle {
+            sell_amounts,
+            buy_amounts,
+        } => process_take_bundle(program_id, accounts, &sell_amounts, &buy_amounts),
+        Instruction::CancelBundle {} => process_cancel_bundle(program_id, accounts),
+        Instruction::Match {} => process_match(program_id, accounts),
+        Instruction::CreateMarket {} => process_create_market(program_id, accounts),
+        Instruction::PlaceOrder {
+            side,
+            base_amount,
+            quote_amount,
+        } => process_place_order(program_id, accounts, side, base_amount, quote_amount),
+        Instruction::CancelOrder { side, order_id } => {
+            process_cancel_order(program_id, accounts, side, order_id)
+        }
+        Instruction::MatchOrders { limit } => process_match_orders(program_id, accounts, limit),
+        Instruction::PostDutchAuction {
+            offer_id,
+            sell_amount,
+            auction,
+            expires_at,
+            allowed_taker,
+        } => process_post_dutch_auction(
+            program_id,
+            accounts,
+            offer_id,
+            sell_amount,
+            auction,
+            expires_at,
+            allowed_taker,
+        ),
+        Instruction::PostEnglishAuction {
+            offer_id,
+            sell_amount,
+            reserve_price,
+            min_increment,
+            end_time,
+            allowed_taker,
+        } => process_post_english_auction(
+            program_id,
+            accounts,
+            offer_id,
+            sell_amount,
+            EnglishAuction {
+                reserve_price,
+                min_increment,
+                end_time,
+                bid: None,
+            },
+            allowed_taker,
+        ),
+        Instruction::Bid { amount } => process_bid(program_id, accounts, amount),
+        Instruction::Settle {} => process_settle(program_id, accounts),
+        Instruction::PostSealedAuction {
+            offer_id,
+            sell_amount,
+            reserve_price,
+            commit_end,
+            reveal_end,
+            unrevealed,
+            allowed_taker,
+        } => process_post_sealed_auction(
+            program_id,
+            accounts,
+            offer_id,
+            sell_amount,
+            SealedAuction {
+                reserve_price,
+                commit_end,
+                reveal_end,
+                unrevealed,
+                commitments: 0,
+                best: None,
+                best_deposit: 0,
+            },
+            allowed_taker,
+        ),
+        Instruction::CommitBid {
+            commitment,
+            deposit,
+        } => process_commit_bid(program_id, accounts, commitment, deposit),
+        Instruction::RevealBid { amount, salt } => {
+            process_reveal_bid(program_id, accounts, amount, salt)
+        }
+        Instruction::ReleaseDeposit {} => process_release_deposit(program_id, accounts),
+        Instruction::WriteOption {
+            offer_id,
+            sell_amount,
+            strike,
+            premium,
+            expires_at,
+        } => process_write_option(
+            program_id,
+            accounts,
+            offer_id,
+            sell_amount,
+            strike,
+            premium,
+            expires_at,
+        ),
+        Instruction::BuyOption {} => process_buy_option(program_id, accounts),
+        Instruction::Exercise {} => process_exercise(program_id, accounts),
+        Instruction::FillSignedOrder { order } => {
+            process_fill_signed_order(program_id, accounts, &order)
+        }
+        Instruction::CancelSignedOrder { nonce } => {
+            process_cancel_signed_order(program_id, accounts, nonce)
+        }
+        Instruction::CreateRfq {
+            rfq_id,
+            mint,
+            amount,
+            pay_mint,
+            deadline,
+        } => process_create_rfq(
+            program_id, accounts, rfq_id, mint, amount, pay_mint, deadline,
+        ),
+        Instruction::CancelRfq {} => process_cancel_rfq(program_id, accounts),
+        Instruction::PostQuote { offer_id, price } => {
+            process_post_quote(program_id, accounts, offer_id, price)
+        }
+        Instruction::AcceptQuote { sell_amount } => {
+            process_accept_quote(program_id, accounts, sell_amount)
+        }
+        Instruction::WithdrawClaim {} => process_withdraw_claim(program_id, accounts),
+        Instruction::ReclaimBundle {} => process_reclaim_bundle(program_id, accounts),
     }
 }
 
-fn process_post(program_id: &Pubkey, accounts: &[AccountInfo], buy_amount: u64) -> ProgramResult {
+fn process_post(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    offer_id: u64,
+    sell_amount: u64,
+    buy_amount: u64,
+    expires_at: Option<i64>,
+    allowed_taker: Option<Pubkey>,
+) -> ProgramResult {
     msg!("Instruction: Post");
 
-    let rent = Rent::get()?;
+    post_offer(
+        program_id,
+        accounts,
+        OfferTerms {
+            offer_id,
+            sell_amount,
+            buy_amount,
+            expires_at,
+            allowed_taker,
+            kind: OfferKind::Fixed,
+        },
+    )
+}
+
+/// How an offer started by `post_offer` is sold
+enum OfferKind {
+    /// Taken at the asked price
+    Fixed,
+    DutchAuction(DutchAuction),
+    EnglishAuction(EnglishAuction),
+    SealedAuction(SealedAuction),
+    /// A covered call sold for the premium, its option mint follows the accounts of `Post`
+    CallOption {
+        premium: u64,
+    },
+    /// A quote answering the request for quotes at `address`
+    Quote {
+        address: Pubkey,
+        rfq: Rfq,
+    },
+}
+
+impl OfferKind {
+    /// Auctions and options are paid into token accounts, so can't ask for native SOL
+    fn pays_into_token_account(&self) -> bool {
+        matches!(
+            self,
+            OfferKind::EnglishAuction(_)
+                | OfferKind::SealedAuction(_)
+                | OfferKind::CallOption { .. }
+        )
+    }
+}
+
+/// Terms of an offer started by `Post` or one of its variants
+struct OfferTerms {
+    offer_id: u64,
+    sell_amount: u64,
+    buy_amount: u64,
+    expires_at: Option<i64>,
+    allowed_taker: Option<Pubkey>,
+    kind: OfferKind,
+}
+
+/// Accounts of `Post`, which its variants follow with their own
+const POST_ACCOUNTS: usize = 10;
+
+fn post_offer(program_id: &Pubkey, accounts: &[AccountInfo], terms: OfferTerms) -> ProgramResult {
+    let OfferTerms {
+        offer_id,
+        sell_amount,
+        buy_amount,
+        expires_at,
+        allowed_taker,
+        kind,
+    } = terms;
 
     //
     // deserialize accounts info
@@ -58,295 +705,4728 @@ fn process_post(program_id: &Pubkey, accounts: &[AccountInfo], buy_amount: u64)
         return Err(ProgramError::MissingRequiredSignature);
     }
 
-    let token_account = next_account_info(&mut accounts_iter)?;
-    if *token_account.owner != spl_token::id() {
-        return Err(Error::AccountNotToken.into());
-    }
-    if !rent.is_exempt(token_account.lamports(), token_account.data_len()) {
-        return Err(Error::NotRentExempt.into());
+    // The poster sells native SOL by passing their own account instead of a token account
+    let sell_account = next_account_info(&mut accounts_iter)?;
+    let sells_native = sell_account.key == poster.key;
+    let vault_account = next_account_info(&mut accounts_iter)?;
+    let sell_mint = next_account_info(&mut accounts_iter)?;
+    if sells_native {
+        if *sell_mint.key != spl_token::native_mint::id() {
+            return Err(Error::MintMismatch.into());
+        }
+    } else {
+        if !is_token_program(sell_account.owner) {
+            return Err(Error::AccountNotToken.into());
+        }
+        let sell_info = unpack_token_account(sell_account)?;
+        if *sell_mint.key != sell_info.mint {
+            return Err(Error::MintMismatch.into());
+        }
     }
 
     let buy_account = next_account_info(&mut accounts_iter)?;
-    if *buy_account.owner != spl_token::id() {
-        return Err(Error::AccountNotToken.into());
+    if buy_account.key == poster.key && kind.pays_into_token_account() {
+        return Err(Error::NativeNotAllowed.into());
     }
+    let buy_mint = receiving_mint(poster, buy_account)?;
 
     let escrow_account = next_account_info(&mut accounts_iter)?;
-    if !rent.is_exempt(escrow_account.lamports(), escrow_account.data_len()) {
-        return Err(Error::NotRentExempt.into());
+    let token_program = next_account_info(&mut accounts_iter)?;
+    check_token_program(token_program)?;
+    if sell_mint.owner != token_program.key {
+        return Err(Error::AccountNotToken.into());
     }
 
-    let token_program = next_account_info(&mut accounts_iter)?;
     let system_program = next_account_info(&mut accounts_iter)?;
+    let config_account = next_account_info(&mut accounts_iter)?;
+    let config = load_config(program_id, config_account)?;
+
     let fee_account = next_account_info(&mut accounts_iter)?;
-    if *fee_account.key != fee_account_pubkey() {
+    if *fee_account.key != config.fee_recipient {
         return Err(Error::IncorrectFeeAccount.into());
     }
 
     //
     // Take fee
     //
-    invoke(
-        &solana_program::system_instruction::transfer(poster.key, fee_account.key, FEE),
-        &[poster.clone(), fee_account.clone(), system_program.clone()],
+    take_posting_fee(&config, poster, fee_account, system_program)?;
+
+    //
+    // create escrow account
+    //
+    create_escrow_account(
+        program_id,
+        poster,
+        escrow_account,
+        system_program,
+        offer_id,
+        get_packed_len::<Escrow>(),
     )?;
 
     //
     // set escrow info
     //
-
-    let mut escrow_info = Escrow::try_from_slice(&escrow_account.try_borrow_data()?)
-        .map_err(|_| Error::InvalidEscrowAccount)?;
-    if escrow_info.is_initialized {
-        return Err(ProgramError::AccountAlreadyInitialized);
+    let (pda, bump_seed) = find_escrow_authority(escrow_account.key, poster.key, program_id);
+    let (vault, vault_bump_seed) = find_escrow_vault(escrow_account.key, program_id);
+    if *vault_account.key != vault {
+        return Err(Error::IncorrectPDA.into());
     }
 
-    escrow_info.is_initialized = true;
-    escrow_info.poster = *poster.key;
-    escrow_info.token_account = *token_account.key;
-    escrow_info.poster_buy_account = *buy_account.key;
-    escrow_info.buy_amount = buy_amount;
-
+    let mut escrow_info = Escrow {
+        is_initialized: true,
+        poster: *poster.key,
+        offer_id,
+        version: 0,
+        token_account: vault,
+        authority_bump: bump_seed,
+        custody: Custody::Vault,
+        sell_mint: *sell_mint.key,
+        sell_amount,
+        poster_buy_account: *buy_account.key,
+        buy_mint,
+        buy_amount,
+        fee_bps: config.fee_bps,
+        expires_at,
+        allowed_taker,
+        dutch_auction: None,
+        english_auction: None,
+        sealed_auction: None,
+        call_option: None,
+        rfq: None,
+    };
+    match kind {
+        OfferKind::Fixed => {}
+        OfferKind::DutchAuction(auction) => escrow_info.dutch_auction = Some(auction),
+        OfferKind::EnglishAuction(auction) => escrow_info.english_auction = Some(auction),
+        OfferKind::SealedAuction(auction) => escrow_info.sealed_auction = Some(auction),
+        OfferKind::CallOption { premium } => {
+            let option_mint = next_account_info(&mut accounts_iter)?;
+            let option_token_program = next_account_info(&mut accounts_iter)?;
+            check_token_program(option_token_program)?;
+            escrow_info.call_option = Some(CallOption {
+                premium,
+                option_mint: open_option_mint(
+                    program_id,
+                    poster,
+                    escrow_account,
+                    option_mint,
+                    option_token_program,
+                    system_program,
+                    &pda,
+                )?,
+                is_sold: false,
+            });
+        }
+        OfferKind::Quote { address, rfq } => {
+            if rfq.mint != *sell_mint.key || rfq.pay_mint != buy_mint {
+                return Err(Error::MintMismatch.into());
+            }
+            escrow_info.rfq = Some(address);
+        }
+    }
     escrow_info.serialize(&mut *escrow_account.try_borrow_mut_data()?)?;
 
     //
-    // transfer ownsership of trade account to PDA
+    // create the vault token account, owned by the escrow's PDA, with room for
+    // any extensions the mint requires of its token accounts
     //
-    let (pda, _bump_seed) = Pubkey::find_program_address(&[ESCROW_SEED], program_id);
-    let owner_change_instruction = spl_token::instruction::set_authority(
-        token_program.key,
-        token_account.key,
-        Some(&pda),
-        spl_token::instruction::AuthorityType::AccountOwner,
-        poster.key,
-        &[poster.key],
+    msg!("Creating vault token account");
+    create_vault(
+        poster,
+        vault_account,
+        sell_mint,
+        token_program,
+        system_program,
+        &pda,
+        &[VAULT_SEED, escrow_account.key.as_ref(), &[vault_bump_seed]],
     )?;
 
-    msg!("Calling the token program to transfer token account ownership...");
-    invoke(
-        &owner_change_instruction,
-        &[token_account.clone(), poster.clone(), token_program.clone()],
-    )?;
+    //
+    // move the tokens being sold into the vault
+    //
+    if sells_native {
+        msg!("Wrapping SOL into the vault");
+        wrap_native(
+            token_program,
+            system_program,
+            poster,
+            vault_account,
+            sell_amount,
+        )?;
+    } else {
+        msg!("Transferring tokens into the vault");
+        transfer_tokens(
+            token_program,
+            sell_account,
+            sell_mint,
+            vault_account,
+            poster,
+            sell_amount,
+            &[],
+        )?;
+    }
 
     Ok(())
 }
 
-fn process_take(
+fn process_post_dutch_auction(
     program_id: &Pubkey,
     accounts: &[AccountInfo],
-    buy_amount: u64,
+    offer_id: u64,
     sell_amount: u64,
+    auction: DutchAuction,
+    expires_at: Option<i64>,
+    allowed_taker: Option<Pubkey>,
 ) -> ProgramResult {
-    msg!("Instruction: Take");
+    msg!("Instruction: PostDutchAuction");
+
+    if auction.end_price == 0
+        || auction.start_price < auction.end_price
+        || auction.end_time <= auction.start_time
+    {
+        return Err(Error::InvalidAuction.into());
+    }
+
+    //
+    // post the offer at its end price
+    //
+    post_offer(
+        program_id,
+        accounts,
+        OfferTerms {
+            offer_id,
+            sell_amount,
+            buy_amount: auction.end_price,
+            expires_at,
+            allowed_taker,
+            kind: OfferKind::DutchAuction(auction),
+        },
+    )
+}
+
+fn process_post_english_auction(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    offer_id: u64,
+    sell_amount: u64,
+    auction: EnglishAuction,
+    allowed_taker: Option<Pubkey>,
+) -> ProgramResult {
+    msg!("Instruction: PostEnglishAuction");
+
+    if auction.reserve_price == 0 || auction.end_time <= Clock::get()?.unix_timestamp {
+        return Err(Error::InvalidAuction.into());
+    }
+
+    //
+    // post the offer at its reserve price
+    //
+    post_offer(
+        program_id,
+        accounts,
+        OfferTerms {
+            offer_id,
+            sell_amount,
+            buy_amount: auction.reserve_price,
+            expires_at: None,
+            allowed_taker,
+            kind: OfferKind::EnglishAuction(auction),
+        },
+    )
+}
+
+fn process_bid(program_id: &Pubkey, accounts: &[AccountInfo], amount: u64) -> ProgramResult {
+    msg!("Instruction: Bid");
 
     //
     // deserialize accounts info
     //
-    msg!("Deserializing accounts");
     let mut accounts_iter = accounts.iter();
 
-    let taker = next_account_info(&mut accounts_iter)?;
-    if !taker.is_signer {
+    let bidder = next_account_info(&mut accounts_iter)?;
+    if !bidder.is_signer {
         return Err(ProgramError::MissingRequiredSignature);
     }
-
-    let taker_sell_account = next_account_info(&mut accounts_iter)?;
-    let taker_buy_account = next_account_info(&mut accounts_iter)?;
-    let token_account = next_account_info(&mut accounts_iter)?;
-    let poster = next_account_info(&mut accounts_iter)?;
-    let poster_buy_account = next_account_info(&mut accounts_iter)?;
     let escrow_account = next_account_info(&mut accounts_iter)?;
+    let bid_account = next_account_info(&mut accounts_iter)?;
+    let claim_vault = next_account_info(&mut accounts_iter)?;
+    let bid_vault = next_account_info(&mut accounts_iter)?;
+    let outbid_claim_vault = next_account_info(&mut accounts_iter)?;
+    let buy_mint = next_account_info(&mut accounts_iter)?;
     let token_program = next_account_info(&mut accounts_iter)?;
-    let pda_account = next_account_info(&mut accounts_iter)?;
-
-    //
-    // Deserialize token account info
-    //
-    msg!("Deserializing token account");
-    let token_info = spl_token::state::Account::unpack(&token_account.try_borrow_data()?)?;
-    if buy_amount != token_info.amount {
-        return Err(Error::ExpectedAmountMismatch.into());
+    check_token_program(token_program)?;
+    if buy_mint.owner != token_program.key {
+        return Err(Error::AccountNotToken.into());
     }
+    let pda_account = next_account_info(&mut accounts_iter)?;
+    let system_program = next_account_info(&mut accounts_iter)?;
 
     //
     // Deserialize escrow account info
     //
     msg!("Deserializing escrow info");
-    let escrow_info = Escrow::deserialize(&mut escrow_account.try_borrow_data()?.as_ref())?;
-    if escrow_info.token_account != *token_account.key {
-        return Err(Error::DoesntMatchEscrow.into());
+    let mut escrow_info = load_escrow(program_id, escrow_account)?;
+    let mut auction = escrow_info
+        .english_auction
+        .ok_or(Error::NotEnglishAuction)?;
+    if Clock::get()?.unix_timestamp >= auction.end_time {
+        return Err(Error::AuctionEnded.into());
     }
-    if escrow_info.poster != *poster.key {
-        return Err(Error::DoesntMatchEscrow.into());
+    if matches!(escrow_info.allowed_taker, Some(allowed_taker) if allowed_taker != *bidder.key) {
+        return Err(Error::NotAllowedTaker.into());
     }
-    if escrow_info.poster_buy_account != *poster_buy_account.key {
-        return Err(Error::DoesntMatchEscrow.into());
+    if escrow_info.buy_mint != *buy_mint.key
+        || unpack_token_account(bid_account)?.mint != escrow_info.buy_mint
+    {
+        return Err(Error::MintMismatch.into());
     }
-    if escrow_info.buy_amount != sell_amount {
-        return Err(Error::ExpectedAmountMismatch.into());
+    if amount < auction.min_bid().ok_or(Error::AmountOverflow)? {
+        return Err(Error::BidTooLow.into());
+    }
+    let (vault, vault_bump_seed) = find_bid_vault(escrow_account.key, program_id);
+    if *bid_vault.key != vault {
+        return Err(Error::IncorrectPDA.into());
     }
+    let authority = EscrowAuthority::new(
+        program_id,
+        pda_account,
+        escrow_account.key,
+        &escrow_info.poster,
+        escrow_info.authority_bump,
+    )?;
 
     //
-    // Send token Y amount from taker's to poster's account
+    // open the bidder's claim vault, so it is there to be refunded into if they are outbid
     //
-    msg!("Sending token Y from Taker to Poster");
-    invoke(
-        &spl_token::instruction::transfer(
-            token_program.key,
-            taker_sell_account.key,
-            poster_buy_account.key,
-            taker.key,
-            &[taker.key],
-            escrow_info.buy_amount,
-        )?,
-        &[
-            taker_sell_account.clone(),
-            poster_buy_account.clone(),
-            taker.clone(),
-            token_program.clone(),
-        ],
+    let claim = ClaimVault::new(
+        program_id,
+        claim_vault,
+        escrow_account.key,
+        bidder.key,
+        buy_mint.key,
     )?;
+    claim.open(bidder, buy_mint, token_program, system_program)?;
 
     //
-    // Send token X amount from token account to taker's account, then close account
+    // refund the bid being beaten into its bidder's claim vault, or create the bid vault for the first bid
     //
-    msg!("Sending token X from Poster to Taker");
-    transfer_and_close(
-        program_id,
+    match auction.bid {
+        Some(previous_bid) => {
+            let outbid_claim = ClaimVault::new(
+                program_id,
+                outbid_claim_vault,
+                escrow_account.key,
+                &previous_bid.bidder,
+                buy_mint.key,
+            )?;
+            outbid_claim.open(bidder, buy_mint, token_program, system_program)?;
+            msg!("Refunding the previous bid into its claim vault");
+            transfer_from_pda(
+                token_program,
+                bid_vault,
+                buy_mint,
+                outbid_claim_vault,
+                &authority,
+                previous_bid.amount,
+            )?;
+        }
+        None => {
+            msg!("Creating bid vault token account");
+            create_vault(
+                bidder,
+                bid_vault,
+                buy_mint,
+                token_program,
+                system_program,
+                pda_account.key,
+                &[BID_SEED, escrow_account.key.as_ref(), &[vault_bump_seed]],
+            )?;
+        }
+    }
+
+    //
+    // move the bid into the vault, the bidder paying any transfer fee on top
+    //
+    let gross_amount = {
+        let mint_data = buy_mint.try_borrow_data()?;
+        amount_with_transfer_fee(&mint_data, Clock::get()?.epoch, amount)?
+    };
+    msg!("Transferring bid into the vault");
+    transfer_tokens(
         token_program,
-        token_account,
-        taker_buy_account,
-        poster,
-        pda_account,
-        token_info.amount,
+        bid_account,
+        buy_mint,
+        bid_vault,
+        bidder,
+        gross_amount,
+        &[],
     )?;
 
     //
-    // Close escrow account
+    // record the new highest bid
     //
-    close_escrow(escrow_account, poster)?;
+    auction.bid = Some(Bid {
+        bidder: *bidder.key,
+        amount,
+    });
+    escrow_info.english_auction = Some(auction);
+    escrow_info.serialize(&mut *escrow_account.try_borrow_mut_data()?)?;
 
     Ok(())
 }
 
-fn process_cancel(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+fn process_settle(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    msg!("Instruction: Settle");
+
     //
     // deserialize accounts info
     //
-    msg!("Deserializing accounts");
     let mut accounts_iter = accounts.iter();
 
-    let poster = next_account_info(&mut accounts_iter)?;
-    if !poster.is_signer {
+    let caller = next_account_info(&mut accounts_iter)?;
+    if !caller.is_signer {
         return Err(ProgramError::MissingRequiredSignature);
     }
-
+    let escrow_account = next_account_info(&mut accounts_iter)?;
     let token_account = next_account_info(&mut accounts_iter)?;
-    if *token_account.owner != spl_token::id() {
+    let poster = next_account_info(&mut accounts_iter)?;
+    let poster_claim_vault = next_account_info(&mut accounts_iter)?;
+    let bid_vault = next_account_info(&mut accounts_iter)?;
+    let winner_claim_vault = next_account_info(&mut accounts_iter)?;
+    let winner = next_account_info(&mut accounts_iter)?;
+    let sell_mint = next_account_info(&mut accounts_iter)?;
+    let token_program = next_account_info(&mut accounts_iter)?;
+    let buy_mint = next_account_info(&mut accounts_iter)?;
+    let buy_token_program = next_account_info(&mut accounts_iter)?;
+    check_token_program(token_program)?;
+    check_token_program(buy_token_program)?;
+    if token_account.owner != token_program.key || bid_vault.owner != buy_token_program.key {
         return Err(Error::AccountNotToken.into());
     }
-
-    let escrow = next_account_info(&mut accounts_iter)?;
-    let refund_account = next_account_info(&mut accounts_iter)?;
-    let token_program = next_account_info(&mut accounts_iter)?;
     let pda_account = next_account_info(&mut accounts_iter)?;
+    let config_account = next_account_info(&mut accounts_iter)?;
+    load_config(program_id, config_account)?;
+    let fee_token_account = next_account_info(&mut accounts_iter)?;
+    let system_program = next_account_info(&mut accounts_iter)?;
 
     //
-    // Deserialize token account info
+    // Deserialize escrow account info
     //
-    msg!("Deserializing token account");
-    let token_info = spl_token::state::Account::unpack(&token_account.try_borrow_data()?)?;
+    msg!("Deserializing escrow info");
+    let escrow_info = load_escrow(program_id, escrow_account)?;
+    let now = Clock::get()?.unix_timestamp;
+    let (bid, excess_deposit) = match (escrow_info.english_auction, escrow_info.sealed_auction) {
+        (Some(auction), _) => {
+            if now < auction.end_time {
+                return Err(Error::AuctionNotEnded.into());
+            }
+            (auction.bid.ok_or(Error::AuctionHasNoBids)?, 0)
+        }
+        (None, Some(auction)) => {
+            if now < auction.reveal_end {
+                return Err(Error::AuctionNotEnded.into());
+            }
+            if auction.commitments > 0 {
+                return Err(Error::UnresolvedBids.into());
+            }
+            let best = auction.best.ok_or(Error::AuctionHasNoBids)?;
+            (best, auction.best_deposit - best.amount)
+        }
+        (None, None) => return Err(Error::NotEnglishAuction.into()),
+    };
+    if escrow_info.token_account != *token_account.key
+        || escrow_info.poster != *poster.key
+        || find_bid_vault(escrow_account.key, program_id).0 != *bid_vault.key
+        || bid.bidder != *winner.key
+    {
+        return Err(Error::DoesntMatchEscrow.into());
+    }
+    if escrow_info.sell_mint != *sell_mint.key || escrow_info.buy_mint != *buy_mint.key {
+        return Err(Error::MintMismatch.into());
+    }
+    let authority = EscrowAuthority::new(
+        program_id,
+        pda_account,
+        escrow_account.key,
+        &escrow_info.poster,
+        escrow_info.authority_bump,
+    )?;
+
+    //
+    // Pay the winning bid into the poster's claim vault, less the fee, and close the bid vault
+    //
+    let poster_claim = ClaimVault::new(
+        program_id,
+        poster_claim_vault,
+        escrow_account.key,
+        poster.key,
+        buy_mint.key,
+    )?;
+    poster_claim.open(caller, buy_mint, buy_token_program, system_program)?;
+    msg!("Sending the winning bid to the poster's claim vault");
+    let fee = token_fee(bid.amount, escrow_info.fee_bps);
+    transfer_from_pda(
+        buy_token_program,
+        bid_vault,
+        buy_mint,
+        poster_claim_vault,
+        &authority,
+        bid.amount - fee,
+    )?;
+    if fee > 0 {
+        msg!("Sending {} of token Y as fee", fee);
+        let fee_token_info = unpack_token_account(fee_token_account)?;
+        if fee_token_info.owner != find_fee_authority(program_id).0
+            || fee_token_info.mint != escrow_info.buy_mint
+        {
+            return Err(Error::IncorrectFeeAccount.into());
+        }
+        transfer_from_pda(
+            buy_token_program,
+            bid_vault,
+            buy_mint,
+            fee_token_account,
+            &authority,
+            fee,
+        )?;
+    }
+    if excess_deposit > 0 {
+        let refund_claim_vault = next_account_info(&mut accounts_iter)?;
+        let refund_claim = ClaimVault::new(
+            program_id,
+            refund_claim_vault,
+            escrow_account.key,
+            winner.key,
+            buy_mint.key,
+        )?;
+        refund_claim.open(caller, buy_mint, buy_token_program, system_program)?;
+        msg!("Refunding the winner's deposit beyond their bid into their claim vault");
+        transfer_from_pda(
+            buy_token_program,
+            bid_vault,
+            buy_mint,
+            refund_claim_vault,
+            &authority,
+            excess_deposit,
+        )?;
+    }
+    close_vault(buy_token_program, bid_vault, buy_mint, winner, &authority)?;
+
+    //
+    // Send the escrowed tokens to the winner's claim vault, closing the vault
+    //
+    let winner_claim = ClaimVault::new(
+        program_id,
+        winner_claim_vault,
+        escrow_account.key,
+        winner.key,
+        sell_mint.key,
+    )?;
+    winner_claim.open(caller, sell_mint, token_program, system_program)?;
+    msg!("Sending token X from Poster to the winner's claim vault");
+    let vault_amount = unpack_token_account(token_account)?.amount;
+    transfer_and_close(
+        token_program,
+        token_account,
+        sell_mint,
+        winner_claim_vault,
+        poster,
+        &authority,
+        vault_amount,
+    )?;
+
+    //
+    // Close escrow account
+    //
+    close_escrow(escrow_account, poster)?;
+
+    Ok(())
+}
+
+fn process_withdraw_claim(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    msg!("Instruction: WithdrawClaim");
+
+    //
+    // deserialize accounts info
+    //
+    let mut accounts_iter = accounts.iter();
+
+    let owner = next_account_info(&mut accounts_iter)?;
+    if !owner.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    let claim_vault = next_account_info(&mut accounts_iter)?;
+    let destination_account = next_account_info(&mut accounts_iter)?;
+    let mint = next_account_info(&mut accounts_iter)?;
+    let token_program = next_account_info(&mut accounts_iter)?;
+    check_token_program(token_program)?;
+    if claim_vault.owner != token_program.key {
+        return Err(Error::AccountNotToken.into());
+    }
+    let claim_authority = next_account_info(&mut accounts_iter)?;
+    let (authority, bump_seed) = find_claim_authority(owner.key, program_id);
+    if *claim_authority.key != authority {
+        return Err(Error::IncorrectPDA.into());
+    }
+    let claim_info = unpack_token_account(claim_vault)?;
+    if claim_info.owner != authority {
+        return Err(Error::IncorrectPDA.into());
+    }
+
+    //
+    // Send everything in the claim vault to the owner, closing it
+    //
+    msg!("Withdrawing {} from the claim vault", claim_info.amount);
+    let seeds: &[&[u8]] = &[CLAIM_SEED, owner.key.as_ref(), &[bump_seed]];
+    transfer_tokens(
+        token_program,
+        claim_vault,
+        mint,
+        destination_account,
+        claim_authority,
+        claim_info.amount,
+        &[seeds],
+    )?;
+    close_token_account(
+        token_program,
+        claim_vault,
+        mint,
+        owner,
+        claim_authority,
+        &[seeds],
+    )
+}
+
+fn process_post_sealed_auction(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    offer_id: u64,
+    sell_amount: u64,
+    auction: SealedAuction,
+    allowed_taker: Option<Pubkey>,
+) -> ProgramResult {
+    msg!("Instruction: PostSealedAuction");
+
+    if auction.reserve_price == 0
+        || auction.commit_end <= Clock::get()?.unix_timestamp
+        || auction.reveal_end <= auction.commit_end
+    {
+        return Err(Error::InvalidAuction.into());
+    }
+
+    //
+    // post the offer at its reserve price
+    //
+    post_offer(
+        program_id,
+        accounts,
+        OfferTerms {
+            offer_id,
+            sell_amount,
+            buy_amount: auction.reserve_price,
+            expires_at: None,
+            allowed_taker,
+            kind: OfferKind::SealedAuction(auction),
+        },
+    )
+}
+
+fn process_commit_bid(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    commitment: [u8; 32],
+    deposit: u64,
+) -> ProgramResult {
+    msg!("Instruction: CommitBid");
+
+    //
+    // deserialize accounts info
+    //
+    let mut accounts_iter = accounts.iter();
+
+    let bidder = next_account_info(&mut accounts_iter)?;
+    if !bidder.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    let escrow_account = next_account_info(&mut accounts_iter)?;
+    let deposit_account = next_account_info(&mut accounts_iter)?;
+    let claim_vault = next_account_info(&mut accounts_iter)?;
+    let bid_vault = next_account_info(&mut accounts_iter)?;
+    let sealed_bid_account = next_account_info(&mut accounts_iter)?;
+    let buy_mint = next_account_info(&mut accounts_iter)?;
+    let token_program = next_account_info(&mut accounts_iter)?;
+    check_token_program(token_program)?;
+    if buy_mint.owner != token_program.key {
+        return Err(Error::AccountNotToken.into());
+    }
+    let pda_account = next_account_info(&mut accounts_iter)?;
+    let system_program = next_account_info(&mut accounts_iter)?;
+
+    //
+    // Deserialize escrow account info
+    //
+    msg!("Deserializing escrow info");
+    let mut escrow_info = load_escrow(program_id, escrow_account)?;
+    let mut auction = escrow_info.sealed_auction.ok_or(Error::NotSealedAuction)?;
+    if Clock::get()?.unix_timestamp >= auction.commit_end {
+        return Err(Error::AuctionEnded.into());
+    }
+    if matches!(escrow_info.allowed_taker, Some(allowed_taker) if allowed_taker != *bidder.key) {
+        return Err(Error::NotAllowedTaker.into());
+    }
+    if escrow_info.buy_mint != *buy_mint.key
+        || unpack_token_account(deposit_account)?.mint != escrow_info.buy_mint
+    {
+        return Err(Error::MintMismatch.into());
+    }
+    // a deposit below the reserve price could never back a winning bid
+    if deposit < auction.reserve_price {
+        return Err(Error::BidTooLow.into());
+    }
+    let (vault, vault_bump_seed) = find_bid_vault(escrow_account.key, program_id);
+    if *bid_vault.key != vault {
+        return Err(Error::IncorrectPDA.into());
+    }
+    let (sealed_bid, sealed_bid_bump_seed) =
+        find_sealed_bid_address(escrow_account.key, bidder.key, program_id);
+    if *sealed_bid_account.key != sealed_bid {
+        return Err(Error::IncorrectPDA.into());
+    }
+    EscrowAuthority::new(
+        program_id,
+        pda_account,
+        escrow_account.key,
+        &escrow_info.poster,
+        escrow_info.authority_bump,
+    )?;
+
+    //
+    // open the bidder's claim vault, so it is there to be refunded into
+    //
+    let claim = ClaimVault::new(
+        program_id,
+        claim_vault,
+        escrow_account.key,
+        bidder.key,
+        buy_mint.key,
+    )?;
+    claim.open(bidder, buy_mint, token_program, system_program)?;
+
+    //
+    // create the bid vault for the first bid, deposits can only leave it once bidding has closed
+    //
+    if auction.commitments == 0 {
+        msg!("Creating bid vault token account");
+        create_vault(
+            bidder,
+            bid_vault,
+            buy_mint,
+            token_program,
+            system_program,
+            pda_account.key,
+            &[BID_SEED, escrow_account.key.as_ref(), &[vault_bump_seed]],
+        )?;
+    }
+
+    //
+    // create the sealed bid account, which fails if the bidder has already committed
+    //
+    msg!("Creating sealed bid account");
+    let space = get_packed_len::<SealedBid>();
+    create_pda_account(
+        bidder,
+        sealed_bid_account,
+        space,
+        program_id,
+        system_program,
+        &[
+            BID_SEED,
+            escrow_account.key.as_ref(),
+            bidder.key.as_ref(),
+            &[sealed_bid_bump_seed],
+        ],
+    )?;
+    SealedBid {
+        is_initialized: true,
+        escrow: *escrow_account.key,
+        bidder: *bidder.key,
+        commitment,
+        deposit,
+    }
+    .serialize(&mut *sealed_bid_account.try_borrow_mut_data()?)?;
+
+    //
+    // move the deposit into the vault, the bidder paying any transfer fee on top
+    //
+    let gross_amount = {
+        let mint_data = buy_mint.try_borrow_data()?;
+        amount_with_transfer_fee(&mint_data, Clock::get()?.epoch, deposit)?
+    };
+    msg!("Transferring deposit into the vault");
+    transfer_tokens(
+        token_program,
+        deposit_account,
+        buy_mint,
+        bid_vault,
+        bidder,
+        gross_amount,
+        &[],
+    )?;
+
+    auction.commitments = auction
+        .commitments
+        .checked_add(1)
+        .ok_or(Error::AmountOverflow)?;
+    escrow_info.sealed_auction = Some(auction);
+    escrow_info.serialize(&mut *escrow_account.try_borrow_mut_data()?)?;
+
+    Ok(())
+}
+
+fn process_reveal_bid(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    amount: u64,
+    salt: [u8; 32],
+) -> ProgramResult {
+    msg!("Instruction: RevealBid");
+
+    //
+    // deserialize accounts info
+    //
+    let mut accounts_iter = accounts.iter();
+
+    let bidder = next_account_info(&mut accounts_iter)?;
+    if !bidder.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    let escrow_account = next_account_info(&mut accounts_iter)?;
+    let sealed_bid_account = next_account_info(&mut accounts_iter)?;
+    let bid_vault = next_account_info(&mut accounts_iter)?;
+    let claim_vault = next_account_info(&mut accounts_iter)?;
+    let best_claim_vault = next_account_info(&mut accounts_iter)?;
+    let buy_mint = next_account_info(&mut accounts_iter)?;
+    let token_program = next_account_info(&mut accounts_iter)?;
+    check_token_program(token_program)?;
+    if bid_vault.owner != token_program.key {
+        return Err(Error::AccountNotToken.into());
+    }
+    let pda_account = next_account_info(&mut accounts_iter)?;
+    let system_program = next_account_info(&mut accounts_iter)?;
+
+    //
+    // Deserialize escrow and sealed bid account info
+    //
+    msg!("Deserializing escrow info");
+    let mut escrow_info = load_escrow(program_id, escrow_account)?;
+    let mut auction = escrow_info.sealed_auction.ok_or(Error::NotSealedAuction)?;
+    let now = Clock::get()?.unix_timestamp;
+    if now < auction.commit_end || now >= auction.reveal_end {
+        return Err(Error::RevealNotOpen.into());
+    }
+    let sealed_bid = load_sealed_bid(program_id, sealed_bid_account, escrow_account.key)?;
+    if sealed_bid.bidder != *bidder.key {
+        return Err(Error::DoesntMatchEscrow.into());
+    }
+    if sealed_bid_commitment(bidder.key, amount, &salt) != sealed_bid.commitment {
+        return Err(Error::CommitmentMismatch.into());
+    }
+    if escrow_info.buy_mint != *buy_mint.key {
+        return Err(Error::MintMismatch.into());
+    }
+    if find_bid_vault(escrow_account.key, program_id).0 != *bid_vault.key {
+        return Err(Error::IncorrectPDA.into());
+    }
+    let authority = EscrowAuthority::new(
+        program_id,
+        pda_account,
+        escrow_account.key,
+        &escrow_info.poster,
+        escrow_info.authority_bump,
+    )?;
+
+    //
+    // a valid bid beating the highest so far takes its place, refunding it, any other bid is refunded.
+    // Refunds are paid into the bidder's claim vault
+    //
+    let is_valid = amount >= auction.reserve_price && amount <= sealed_bid.deposit;
+    let beats_best = match auction.best {
+        Some(best) => amount > best.amount,
+        None => true,
+    };
+    if is_valid && beats_best {
+        if let Some(best) = auction.best {
+            let best_claim = ClaimVault::new(
+                program_id,
+                best_claim_vault,
+                escrow_account.key,
+                &best.bidder,
+                buy_mint.key,
+            )?;
+            best_claim.open(bidder, buy_mint, token_program, system_program)?;
+            msg!("Refunding the bid beaten into its claim vault");
+            transfer_from_pda(
+                token_program,
+                bid_vault,
+                buy_mint,
+                best_claim_vault,
+                &authority,
+                auction.best_deposit,
+            )?;
+        }
+        msg!("Recording the new highest bid");
+        auction.best = Some(Bid {
+            bidder: *bidder.key,
+            amount,
+        });
+        auction.best_deposit = sealed_bid.deposit;
+    } else {
+        let claim = ClaimVault::new(
+            program_id,
+            claim_vault,
+            escrow_account.key,
+            bidder.key,
+            buy_mint.key,
+        )?;
+        claim.open(bidder, buy_mint, token_program, system_program)?;
+        msg!("Refunding the deposit into the claim vault");
+        transfer_from_pda(
+            token_program,
+            bid_vault,
+            buy_mint,
+            claim_vault,
+            &authority,
+            sealed_bid.deposit,
+        )?;
+    }
+
+    resolve_sealed_bid(
+        &mut auction,
+        sealed_bid_account,
+        bidder,
+        bid_vault,
+        buy_mint,
+        token_program,
+        &authority,
+    )?;
+    escrow_info.sealed_auction = Some(auction);
+    escrow_info.serialize(&mut *escrow_account.try_borrow_mut_data()?)?;
+
+    Ok(())
+}
+
+fn process_release_deposit(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    msg!("Instruction: ReleaseDeposit");
+
+    //
+    // deserialize accounts info
+    //
+    let mut accounts_iter = accounts.iter();
+
+    let caller = next_account_info(&mut accounts_iter)?;
+    if !caller.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    let escrow_account = next_account_info(&mut accounts_iter)?;
+    let sealed_bid_account = next_account_info(&mut accounts_iter)?;
+    let bidder = next_account_info(&mut accounts_iter)?;
+    let bid_vault = next_account_info(&mut accounts_iter)?;
+    let destination_account = next_account_info(&mut accounts_iter)?;
+    let buy_mint = next_account_info(&mut accounts_iter)?;
+    let token_program = next_account_info(&mut accounts_iter)?;
+    check_token_program(token_program)?;
+    if bid_vault.owner != token_program.key {
+        return Err(Error::AccountNotToken.into());
+    }
+    let pda_account = next_account_info(&mut accounts_iter)?;
+    let system_program = next_account_info(&mut accounts_iter)?;
+
+    //
+    // Deserialize escrow and sealed bid account info
+    //
+    msg!("Deserializing escrow info");
+    let mut escrow_info = load_escrow(program_id, escrow_account)?;
+    let mut auction = escrow_info.sealed_auction.ok_or(Error::NotSealedAuction)?;
+    if Clock::get()?.unix_timestamp < auction.reveal_end {
+        return Err(Error::AuctionNotEnded.into());
+    }
+    let sealed_bid = load_sealed_bid(program_id, sealed_bid_account, escrow_account.key)?;
+    if sealed_bid.bidder != *bidder.key {
+        return Err(Error::DoesntMatchEscrow.into());
+    }
+    if escrow_info.buy_mint != *buy_mint.key {
+        return Err(Error::MintMismatch.into());
+    }
+    if find_bid_vault(escrow_account.key, program_id).0 != *bid_vault.key {
+        return Err(Error::IncorrectPDA.into());
+    }
+    let authority = EscrowAuthority::new(
+        program_id,
+        pda_account,
+        escrow_account.key,
+        &escrow_info.poster,
+        escrow_info.authority_bump,
+    )?;
+
+    //
+    // Release the deposit as the auction's policy says, into the bidder's or the poster's claim vault
+    //
+    let owner = match auction.unrevealed {
+        UnrevealedPolicy::Refund => {
+            msg!("Refunding the unrevealed deposit into the bidder's claim vault");
+            *bidder.key
+        }
+        UnrevealedPolicy::Forfeit => {
+            msg!("Forfeiting the unrevealed deposit into the poster's claim vault");
+            escrow_info.poster
+        }
+    };
+    let claim = ClaimVault::new(
+        program_id,
+        destination_account,
+        escrow_account.key,
+        &owner,
+        buy_mint.key,
+    )?;
+    claim.open(caller, buy_mint, token_program, system_program)?;
+    transfer_from_pda(
+        token_program,
+        bid_vault,
+        buy_mint,
+        destination_account,
+        &authority,
+        sealed_bid.deposit,
+    )?;
+
+    resolve_sealed_bid(
+        &mut auction,
+        sealed_bid_account,
+        bidder,
+        bid_vault,
+        buy_mint,
+        token_program,
+        &authority,
+    )?;
+    escrow_info.sealed_auction = Some(auction);
+    escrow_info.serialize(&mut *escrow_account.try_borrow_mut_data()?)?;
+
+    Ok(())
+}
+
+/// Reads a sealed bid account, checking the program owns it and it is for `escrow`
+fn load_sealed_bid(
+    program_id: &Pubkey,
+    sealed_bid_account: &AccountInfo,
+    escrow: &Pubkey,
+) -> Result<SealedBid, ProgramError> {
+    if sealed_bid_account.owner != program_id {
+        return Err(Error::DoesntMatchEscrow.into());
+    }
+    let sealed_bid = SealedBid::deserialize(&mut sealed_bid_account.try_borrow_data()?.as_ref())?;
+    if !sealed_bid.is_initialized || sealed_bid.escrow != *escrow {
+        return Err(Error::DoesntMatchEscrow.into());
+    }
+    Ok(sealed_bid)
+}
+
+/// Closes a sealed bid account whose deposit has been paid out, and the bid vault too once it is left empty
+/// with no winning bid, sending their rent to the bidder
+fn resolve_sealed_bid<'a>(
+    auction: &mut SealedAuction,
+    sealed_bid_account: &AccountInfo<'a>,
+    bidder: &AccountInfo<'a>,
+    bid_vault: &AccountInfo<'a>,
+    buy_mint: &AccountInfo<'a>,
+    token_program: &AccountInfo<'a>,
+    authority: &EscrowAuthority<'a, '_>,
+) -> ProgramResult {
+    auction.commitments -= 1;
+    if auction.commitments == 0 && auction.best.is_none() {
+        msg!("Closing the empty bid vault");
+        close_vault(token_program, bid_vault, buy_mint, bidder, authority)?;
+    }
+    msg!("Closing sealed bid account");
+    close_account(sealed_bid_account, bidder)
+}
+
+fn process_write_option(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    offer_id: u64,
+    sell_amount: u64,
+    strike: u64,
+    premium: u64,
+    expires_at: i64,
+) -> ProgramResult {
+    msg!("Instruction: WriteOption");
+
+    //
+    // post the offer at the strike, expiring with the option
+    //
+    post_offer(
+        program_id,
+        accounts,
+        OfferTerms {
+            offer_id,
+            sell_amount,
+            buy_amount: strike,
+            expires_at: Some(expires_at),
+            allowed_taker: None,
+            kind: OfferKind::CallOption { premium },
+        },
+    )
+}
+
+/// Creates the option mint of an escrow, with the escrow's PDA as its mint authority. The mint of an earlier
+/// option written under the same offer id is reused instead, as long as its token was burned
+fn open_option_mint<'a>(
+    program_id: &Pubkey,
+    poster: &AccountInfo<'a>,
+    escrow_account: &AccountInfo<'a>,
+    option_mint: &AccountInfo<'a>,
+    option_token_program: &AccountInfo<'a>,
+    system_program: &AccountInfo<'a>,
+    pda: &Pubkey,
+) -> Result<Pubkey, ProgramError> {
+    let (mint, mint_bump_seed) = find_option_mint(escrow_account.key, program_id);
+    if *option_mint.key != mint {
+        return Err(Error::IncorrectPDA.into());
+    }
+    if option_mint.data_is_empty() {
+        msg!("Creating option mint");
+        create_pda_account(
+            poster,
+            option_mint,
+            Mint::LEN,
+            option_token_program.key,
+            system_program,
+            &[OPTION_SEED, escrow_account.key.as_ref(), &[mint_bump_seed]],
+        )?;
+        invoke(
+            &spl_token_2022::instruction::initialize_mint2(
+                option_token_program.key,
+                option_mint.key,
+                pda,
+                None,
+                0,
+            )?,
+            &[option_mint.clone(), option_token_program.clone()],
+        )?;
+    } else {
+        msg!("Reusing option mint");
+        if option_mint.owner != option_token_program.key {
+            return Err(Error::AccountNotToken.into());
+        }
+        let mint_info = StateWithExtensions::<Mint>::unpack(&option_mint.try_borrow_data()?)?.base;
+        if mint_info.supply != 0 || mint_info.mint_authority != COption::Some(*pda) {
+            return Err(Error::OptionMintInUse.into());
+        }
+    }
+    Ok(mint)
+}
+
+fn process_buy_option(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    msg!("Instruction: BuyOption");
+
+    //
+    // deserialize accounts info
+    //
+    let mut accounts_iter = accounts.iter();
+
+    let buyer = next_account_info(&mut accounts_iter)?;
+    if !buyer.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    let escrow_account = next_account_info(&mut accounts_iter)?;
+    let premium_account = next_account_info(&mut accounts_iter)?;
+    let poster_buy_account = next_account_info(&mut accounts_iter)?;
+    let option_account = next_account_info(&mut accounts_iter)?;
+    let option_mint = next_account_info(&mut accounts_iter)?;
+    let buy_mint = next_account_info(&mut accounts_iter)?;
+    let buy_token_program = next_account_info(&mut accounts_iter)?;
+    let option_token_program = next_account_info(&mut accounts_iter)?;
+    check_token_program(buy_token_program)?;
+    check_token_program(option_token_program)?;
+    if buy_mint.owner != buy_token_program.key || option_mint.owner != option_token_program.key {
+        return Err(Error::AccountNotToken.into());
+    }
+    let pda_account = next_account_info(&mut accounts_iter)?;
+    let config_account = next_account_info(&mut accounts_iter)?;
+    load_config(program_id, config_account)?;
+    let fee_token_account = next_account_info(&mut accounts_iter)?;
+
+    //
+    // Deserialize escrow account info
+    //
+    msg!("Deserializing escrow info");
+    let mut escrow_info = load_escrow(program_id, escrow_account)?;
+    let mut option = escrow_info.call_option.ok_or(Error::NotCallOption)?;
+    if option.is_sold {
+        return Err(Error::OptionSold.into());
+    }
+    if escrow_info.is_expired(Clock::get()?.unix_timestamp) {
+        return Err(Error::OfferExpired.into());
+    }
+    if escrow_info.poster_buy_account != *poster_buy_account.key
+        || option.option_mint != *option_mint.key
+    {
+        return Err(Error::DoesntMatchEscrow.into());
+    }
+    if escrow_info.buy_mint != *buy_mint.key {
+        return Err(Error::MintMismatch.into());
+    }
+    let authority = EscrowAuthority::new(
+        program_id,
+        pda_account,
+        escrow_account.key,
+        &escrow_info.poster,
+        escrow_info.authority_bump,
+    )?;
+
+    //
+    // Pay the premium to the writer, less the fee
+    //
+    msg!("Sending the premium from Buyer to Writer");
+    let fee = token_fee(option.premium, escrow_info.fee_bps);
+    transfer_fee_on_top(
+        buy_token_program,
+        premium_account,
+        buy_mint,
+        poster_buy_account,
+        buyer,
+        option.premium - fee,
+    )?;
+    if fee > 0 {
+        msg!("Sending {} of token Y from Buyer as fee", fee);
+        check_fee_token_account(program_id, fee_token_account, &escrow_info.buy_mint)?;
+        transfer_fee_on_top(
+            buy_token_program,
+            premium_account,
+            buy_mint,
+            fee_token_account,
+            buyer,
+            fee,
+        )?;
+    }
+
+    //
+    // Mint the option token to the buyer
+    //
+    msg!("Minting the option token to Buyer");
+    invoke_signed(
+        &spl_token_2022::instruction::mint_to_checked(
+            option_token_program.key,
+            option_mint.key,
+            option_account.key,
+            pda_account.key,
+            &[],
+            1,
+            0,
+        )?,
+        &[
+            option_mint.clone(),
+            option_account.clone(),
+            pda_account.clone(),
+            option_token_program.clone(),
+        ],
+        &[&authority.seeds()],
+    )?;
+
+    option.is_sold = true;
+    escrow_info.call_option = Some(option);
+    escrow_info.serialize(&mut *escrow_account.try_borrow_mut_data()?)?;
+
+    Ok(())
+}
+
+fn process_exercise(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    msg!("Instruction: Exercise");
+
+    //
+    // deserialize accounts info
+    //
+    let mut accounts_iter = accounts.iter();
+
+    let holder = next_account_info(&mut accounts_iter)?;
+    if !holder.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    let escrow_account = next_account_info(&mut accounts_iter)?;
+    let token_account = next_account_info(&mut accounts_iter)?;
+    let poster = next_account_info(&mut accounts_iter)?;
+    let poster_buy_account = next_account_info(&mut accounts_iter)?;
+    let strike_account = next_account_info(&mut accounts_iter)?;
+    let receive_account = next_account_info(&mut accounts_iter)?;
+    let option_account = next_account_info(&mut accounts_iter)?;
+    let option_mint = next_account_info(&mut accounts_iter)?;
+    let sell_mint = next_account_info(&mut accounts_iter)?;
+    let token_program = next_account_info(&mut accounts_iter)?;
+    let buy_mint = next_account_info(&mut accounts_iter)?;
+    let buy_token_program = next_account_info(&mut accounts_iter)?;
+    let option_token_program = next_account_info(&mut accounts_iter)?;
+    check_token_program(token_program)?;
+    check_token_program(buy_token_program)?;
+    check_token_program(option_token_program)?;
+    if token_account.owner != token_program.key
+        || buy_mint.owner != buy_token_program.key
+        || option_mint.owner != option_token_program.key
+    {
+        return Err(Error::AccountNotToken.into());
+    }
+    let pda_account = next_account_info(&mut accounts_iter)?;
+    let config_account = next_account_info(&mut accounts_iter)?;
+    load_config(program_id, config_account)?;
+    let fee_token_account = next_account_info(&mut accounts_iter)?;
+
+    //
+    // Deserialize escrow account info
+    //
+    msg!("Deserializing escrow info");
+    let escrow_info = load_escrow(program_id, escrow_account)?;
+    let option = escrow_info.call_option.ok_or(Error::NotCallOption)?;
+    if !option.is_sold {
+        return Err(Error::OptionNotSold.into());
+    }
+    if escrow_info.is_expired(Clock::get()?.unix_timestamp) {
+        return Err(Error::OfferExpired.into());
+    }
+    if escrow_info.token_account != *token_account.key
+        || escrow_info.poster != *poster.key
+        || escrow_info.poster_buy_account != *poster_buy_account.key
+        || option.option_mint != *option_mint.key
+    {
+        return Err(Error::DoesntMatchEscrow.into());
+    }
+    if escrow_info.sell_mint != *sell_mint.key || escrow_info.buy_mint != *buy_mint.key {
+        return Err(Error::MintMismatch.into());
+    }
+    let authority = EscrowAuthority::new(
+        program_id,
+        pda_account,
+        escrow_account.key,
+        &escrow_info.poster,
+        escrow_info.authority_bump,
+    )?;
+
+    //
+    // Burn the holder's option token, which fails unless they hold it
+    //
+    msg!("Burning the option token");
+    invoke(
+        &spl_token_2022::instruction::burn_checked(
+            option_token_program.key,
+            option_account.key,
+            option_mint.key,
+            holder.key,
+            &[],
+            1,
+            0,
+        )?,
+        &[
+            option_account.clone(),
+            option_mint.clone(),
+            holder.clone(),
+            option_token_program.clone(),
+        ],
+    )?;
+
+    //
+    // Pay the strike to the writer, less the fee
+    //
+    msg!("Sending the strike from Holder to Writer");
+    let fee = token_fee(escrow_info.buy_amount, escrow_info.fee_bps);
+    transfer_fee_on_top(
+        buy_token_program,
+        strike_account,
+        buy_mint,
+        poster_buy_account,
+        holder,
+        escrow_info.buy_amount - fee,
+    )?;
+    if fee > 0 {
+        msg!("Sending {} of token Y from Holder as fee", fee);
+        check_fee_token_account(program_id, fee_token_account, &escrow_info.buy_mint)?;
+        transfer_fee_on_top(
+            buy_token_program,
+            strike_account,
+            buy_mint,
+            fee_token_account,
+            holder,
+            fee,
+        )?;
+    }
+
+    //
+    // Send the escrowed tokens to the holder, closing the vault
+    //
+    msg!("Sending token X from Writer to Holder");
+    let vault_amount = unpack_token_account(token_account)?.amount;
+    transfer_and_close(
+        token_program,
+        token_account,
+        sell_mint,
+        receive_account,
+        poster,
+        &authority,
+        vault_amount,
+    )?;
+
+    //
+    // Close escrow account
+    //
+    close_escrow(escrow_account, poster)?;
+
+    Ok(())
+}
+
+fn process_fill_signed_order(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    order: &SignedOrder,
+) -> ProgramResult {
+    msg!("Instruction: FillSignedOrder");
+
+    //
+    // deserialize accounts info
+    //
+    let mut accounts_iter = accounts.iter();
+
+    let taker = next_account_info(&mut accounts_iter)?;
+    if !taker.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    let maker_sell_account = next_account_info(&mut accounts_iter)?;
+    let maker_buy_account = next_account_info(&mut accounts_iter)?;
+    let taker_sell_account = next_account_info(&mut accounts_iter)?;
+    let taker_buy_account = next_account_info(&mut accounts_iter)?;
+    let sell_mint = next_account_info(&mut accounts_iter)?;
+    let sell_token_program = next_account_info(&mut accounts_iter)?;
+    let buy_mint = next_account_info(&mut accounts_iter)?;
+    let buy_token_program = next_account_info(&mut accounts_iter)?;
+    check_token_program(sell_token_program)?;
+    check_token_program(buy_token_program)?;
+    if sell_mint.owner != sell_token_program.key || buy_mint.owner != buy_token_program.key {
+        return Err(Error::AccountNotToken.into());
+    }
+    let pda_account = next_account_info(&mut accounts_iter)?;
+    let nonce_page_account = next_account_info(&mut accounts_iter)?;
+    let instructions_sysvar = next_account_info(&mut accounts_iter)?;
+    let system_program = next_account_info(&mut accounts_iter)?;
+    let config_account = next_account_info(&mut accounts_iter)?;
+    let config = load_config(program_id, config_account)?;
+    let fee_token_account = next_account_info(&mut accounts_iter)?;
+
+    //
+    // Check the order is signed by its maker and can be filled
+    //
+    msg!("Verifying the maker's signature");
+    check_order_signature(instructions_sysvar, order)?;
+    if order.sell_amount == 0 || order.buy_amount == 0 {
+        return Err(Error::InvalidOrder.into());
+    }
+    if order.fee_bps > MAX_FEE_BPS {
+        return Err(Error::InvalidFeeRate.into());
+    }
+    if order.fee_bps < config.fee_bps {
+        return Err(Error::FeeRateTooLow.into());
+    }
+    if order.sell_mint != *sell_mint.key || order.buy_mint != *buy_mint.key {
+        return Err(Error::MintMismatch.into());
+    }
+    if order.maker_buy_account != *maker_buy_account.key
+        || unpack_token_account(maker_sell_account)?.owner != order.maker
+    {
+        return Err(Error::DoesntMatchOrder.into());
+    }
+    if Clock::get()?.unix_timestamp >= order.expires_at {
+        return Err(Error::OfferExpired.into());
+    }
+    if matches!(order.allowed_taker, Some(allowed_taker) if allowed_taker != *taker.key) {
+        return Err(Error::NotAllowedTaker.into());
+    }
+    let (pda, bump_seed) = find_order_authority(program_id);
+    if *pda_account.key != pda {
+        return Err(Error::IncorrectPDA.into());
+    }
+    use_nonce(
+        program_id,
+        &order.maker,
+        order.nonce,
+        nonce_page_account,
+        taker,
+        system_program,
+    )?;
+
+    //
+    // Send token Y from taker's to maker's account, less the fee
+    //
+    msg!("Sending token Y from Taker to Maker");
+    let fee = token_fee(order.buy_amount, order.fee_bps);
+    transfer_fee_on_top(
+        buy_token_program,
+        taker_sell_account,
+        buy_mint,
+        maker_buy_account,
+        taker,
+        order.buy_amount - fee,
+    )?;
+    if fee > 0 {
+        msg!("Sending {} of token Y from Taker as fee", fee);
+        check_fee_token_account(program_id, fee_token_account, &order.buy_mint)?;
+        transfer_fee_on_top(
+            buy_token_program,
+            taker_sell_account,
+            buy_mint,
+            fee_token_account,
+            taker,
+            fee,
+        )?;
+    }
+
+    //
+    // Send token X from maker's account to taker's, as its delegate
+    //
+    msg!("Sending token X from Maker to Taker");
+    transfer_tokens(
+        sell_token_program,
+        maker_sell_account,
+        sell_mint,
+        taker_buy_account,
+        pda_account,
+        order.sell_amount,
+        &[&[ORDER_SEED, &[bump_seed]]],
+    )
+}
+
+fn process_cancel_signed_order(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    nonce: u64,
+) -> ProgramResult {
+    msg!("Instruction: CancelSignedOrder");
+
+    let mut accounts_iter = accounts.iter();
+
+    let maker = next_account_info(&mut accounts_iter)?;
+    if !maker.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    let nonce_page_account = next_account_info(&mut accounts_iter)?;
+    let system_program = next_account_info(&mut accounts_iter)?;
+
+    use_nonce(
+        program_id,
+        maker.key,
+        nonce,
+        nonce_page_account,
+        maker,
+        system_program,
+    )
+}
+
+/// Checks the instruction just before this one is the ed25519 program verifying `order.maker`'s signature of
+/// `order`, with the signature, public key and message all in that instruction's own data
+fn check_order_signature(instructions_sysvar: &AccountInfo, order: &SignedOrder) -> ProgramResult {
+    let current_index = load_current_index_checked(instructions_sysvar)?;
+    if current_index == 0 {
+        return Err(Error::InvalidSignature.into());
+    }
+    let instruction = load_instruction_at_checked(current_index as usize - 1, instructions_sysvar)?;
+    if instruction.program_id != solana_program::ed25519_program::id() {
+        return Err(Error::InvalidSignature.into());
+    }
+
+    // the data starts with the number of signatures and a padding byte, followed by the offsets of each:
+    // signature, signature instruction, public key, public key instruction, message, message size and
+    // message instruction, an instruction index of u16::MAX meaning the ed25519 instruction itself
+    let data = &instruction.data;
+    if data.len() < ED25519_DATA_START || data[0] != 1 {
+        return Err(Error::InvalidSignature.into());
+    }
+    let offset =
+        |index: usize| u16::from_le_bytes([data[2 + 2 * index], data[3 + 2 * index]]) as usize;
+    if offset(1) != u16::MAX as usize
+        || offset(3) != u16::MAX as usize
+        || offset(6) != u16::MAX as usize
+    {
+        return Err(Error::InvalidSignature.into());
+    }
+    let public_key = data.get(offset(2)..offset(2) + 32);
+    let message = data.get(offset(4)..offset(4) + offset(5));
+    if public_key != Some(order.maker.as_ref()) || message != Some(order.try_to_vec()?.as_slice()) {
+        return Err(Error::InvalidSignature.into());
+    }
+    Ok(())
+}
+
+/// Marks `maker`'s `nonce` used in its nonce page, creating the page at `payer`'s expense if it doesn't exist yet.
+/// Fails if the nonce has already been used
+fn use_nonce<'a>(
+    program_id: &Pubkey,
+    maker: &Pubkey,
+    nonce: u64,
+    nonce_page_account: &AccountInfo<'a>,
+    payer: &AccountInfo<'a>,
+    system_program: &AccountInfo<'a>,
+) -> ProgramResult {
+    let page = NoncePage::page(nonce);
+    let (address, bump_seed) = find_nonce_page(maker, page, program_id);
+    if *nonce_page_account.key != address {
+        return Err(Error::IncorrectPDA.into());
+    }
+
+    let mut nonce_page = if nonce_page_account.data_is_empty() {
+        msg!("Creating nonce page");
+        let space = get_packed_len::<NoncePage>();
+        create_pda_account(
+            payer,
+            nonce_page_account,
+            space,
+            program_id,
+            system_program,
+            &[
+                NONCE_SEED,
+                maker.as_ref(),
+                &page.to_le_bytes(),
+                &[bump_seed],
+            ],
+        )?;
+        NoncePage {
+            is_initialized: true,
+            used: [0; NONCES_PER_PAGE as usize / 64],
+        }
+    } else {
+        if nonce_page_account.owner != program_id {
+            return Err(Error::IncorrectPDA.into());
+        }
+        NoncePage::deserialize(&mut nonce_page_account.try_borrow_data()?.as_ref())?
+    };
+
+    if nonce_page.is_used(nonce) {
+        return Err(Error::NonceUsed.into());
+    }
+    nonce_page.mark_used(nonce);
+    nonce_page.serialize(&mut *nonce_page_account.try_borrow_mut_data()?)?;
+    Ok(())
+}
+
+fn process_create_rfq(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    rfq_id: u64,
+    mint: Pubkey,
+    amount: u64,
+    pay_mint: Pubkey,
+    deadline: i64,
+) -> ProgramResult {
+    msg!("Instruction: CreateRfq");
+
+    let mut accounts_iter = accounts.iter();
+
+    let requester = next_account_info(&mut accounts_iter)?;
+    if !requester.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    let rfq_account = next_account_info(&mut accounts_iter)?;
+    let system_program = next_account_info(&mut accounts_iter)?;
+
+    if amount == 0 || deadline <= Clock::get()?.unix_timestamp {
+        return Err(Error::InvalidRfq.into());
+    }
+    let (address, bump_seed) = find_rfq_address(requester.key, rfq_id, program_id);
+    if *rfq_account.key != address {
+        return Err(Error::IncorrectPDA.into());
+    }
+
+    msg!("Creating request for quotes account");
+    let space = get_packed_len::<Rfq>();
+    create_pda_account(
+        requester,
+        rfq_account,
+        space,
+        program_id,
+        system_program,
+        &[
+            RFQ_SEED,
+            requester.key.as_ref(),
+            &rfq_id.to_le_bytes(),
+            &[bump_seed],
+        ],
+    )?;
+
+    let rfq = Rfq {
+        is_initialized: true,
+        requester: *requester.key,
+        rfq_id,
+        mint,
+        amount,
+        pay_mint,
+        deadline,
+    };
+    rfq.serialize(&mut *rfq_account.try_borrow_mut_data()?)?;
+
+    Ok(())
+}
+
+fn process_cancel_rfq(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    msg!("Instruction: CancelRfq");
+
+    let mut accounts_iter = accounts.iter();
+
+    let requester = next_account_info(&mut accounts_iter)?;
+    if !requester.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    let rfq_account = next_account_info(&mut accounts_iter)?;
+    let rfq = load_rfq(program_id, rfq_account)?;
+    if rfq.requester != *requester.key {
+        return Err(Error::DoesntMatchRfq.into());
+    }
+
+    msg!("Closing request for quotes account");
+    close_account(rfq_account, requester)
+}
+
+fn process_post_quote(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    offer_id: u64,
+    price: u64,
+) -> ProgramResult {
+    msg!("Instruction: PostQuote");
+
+    let mut accounts_iter = accounts.iter().skip(POST_ACCOUNTS);
+    let rfq_account = next_account_info(&mut accounts_iter)?;
+    let rfq = load_rfq(program_id, rfq_account)?;
+    if rfq.deadline <= Clock::get()?.unix_timestamp {
+        return Err(Error::RfqExpired.into());
+    }
+
+    //
+    // post the requested amount at the quoted price, for the requester only until the deadline
+    //
+    post_offer(
+        program_id,
+        accounts,
+        OfferTerms {
+            offer_id,
+            sell_amount: rfq.amount,
+            buy_amount: price,
+            expires_at: Some(rfq.deadline),
+            allowed_taker: Some(rfq.requester),
+            kind: OfferKind::Quote {
+                address: *rfq_account.key,
+                rfq,
+            },
+        },
+    )
+}
+
+/// Accounts of `Take`, which `AcceptQuote` passes on for the quote it accepts
+const TAKE_ACCOUNTS: usize = 17;
+
+fn process_accept_quote(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    sell_amount: u64,
+) -> ProgramResult {
+    msg!("Instruction: AcceptQuote");
+
+    //
+    // deserialize accounts info
+    //
+    let rfq_account = accounts.first().ok_or(ProgramError::NotEnoughAccountKeys)?;
+    let rfq = load_rfq(program_id, rfq_account)?;
+    if accounts.len() <= TAKE_ACCOUNTS {
+        return Err(ProgramError::NotEnoughAccountKeys);
+    }
+    let (take_accounts, refund_accounts) = accounts[1..].split_at(TAKE_ACCOUNTS);
+    let requester = &take_accounts[0];
+    let vault = &take_accounts[3];
+    let escrow_account = &take_accounts[6];
+    if rfq.requester != *requester.key {
+        return Err(Error::DoesntMatchRfq.into());
+    }
+    let escrow_info = load_escrow(program_id, escrow_account)?;
+    if escrow_info.sell_mint != rfq.mint || escrow_info.buy_mint != rfq.pay_mint {
+        return Err(Error::MintMismatch.into());
+    }
+
+    //
+    // Take the whole quote
+    //
+    take_offer(
+        program_id,
+        take_accounts,
+        unpack_token_account(vault)?.amount,
+        sell_amount,
+        escrow_info.version,
+        Some(rfq_account.key),
+    )?;
+
+    //
+    // Refund the other quotes given
+    //
+    let mut accounts_iter = refund_accounts.iter();
+    while accounts_iter.len() > 0 {
+        let refund = Refund::load(program_id, &mut accounts_iter)?;
+        if refund.escrow_info.rfq != Some(*rfq_account.key) {
+            return Err(Error::DoesntMatchRfq.into());
+        }
+        refund.return_tokens()?;
+        close_escrow(refund.escrow, refund.poster)?;
+    }
+
+    msg!("Closing request for quotes account");
+    close_account(rfq_account, requester)
+}
+
+fn load_rfq(program_id: &Pubkey, rfq_account: &AccountInfo) -> Result<Rfq, ProgramError> {
+    if rfq_account.owner != program_id || rfq_account.data_len() != get_packed_len::<Rfq>() {
+        return Err(Error::DoesntMatchRfq.into());
+    }
+    let rfq = Rfq::deserialize(&mut rfq_account.try_borrow_data()?.as_ref())?;
+    if !rfq.is_initialized {
+        return Err(ProgramError::UninitializedAccount);
+    }
+    Ok(rfq)
+}
+
+fn process_post_delegated(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    offer_id: u64,
+    sell_amount: u64,
+    buy_amount: u64,
+    expires_at: Option<i64>,
+    allowed_taker: Option<Pubkey>,
+) -> ProgramResult {
+    msg!("Instruction: PostDelegated");
+
+    //
+    // deserialize accounts info
+    //
+    let mut accounts_iter = accounts.iter();
+
+    let poster = next_account_info(&mut accounts_iter)?;
+    if !poster.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    let sell_account = next_account_info(&mut accounts_iter)?;
+    let sell_info = unpack_token_account(sell_account)?;
+
+    let buy_account = next_account_info(&mut accounts_iter)?;
+    let buy_mint = receiving_mint(poster, buy_account)?;
+
+    let escrow_account = next_account_info(&mut accounts_iter)?;
+    let token_program = next_account_info(&mut accounts_iter)?;
+    check_token_program(token_program)?;
+    if sell_account.owner != token_program.key {
+        return Err(Error::AccountNotToken.into());
+    }
+
+    let system_program = next_account_info(&mut accounts_iter)?;
+    let config_account = next_account_info(&mut accounts_iter)?;
+    let config = load_config(program_id, config_account)?;
+
+    let fee_account = next_account_info(&mut accounts_iter)?;
+    if *fee_account.key != config.fee_recipient {
+        return Err(Error::IncorrectFeeAccount.into());
+    }
+
+    //
+    // Take fee
+    //
+    take_posting_fee(&config, poster, fee_account, system_program)?;
+
+    //
+    // create escrow account
+    //
+    create_escrow_account(
+        program_id,
+        poster,
+        escrow_account,
+        system_program,
+        offer_id,
+        get_packed_len::<Escrow>(),
+    )?;
+
+    //
+    // set escrow info
+    //
+    let (pda, bump_seed) = find_escrow_authority(escrow_account.key, poster.key, program_id);
+    let escrow_info = Escrow {
+        is_initialized: true,
+        poster: *poster.key,
+        offer_id,
+        version: 0,
+        token_account: *sell_account.key,
+        authority_bump: bump_seed,
+        custody: Custody::Delegated,
+        sell_mint: sell_info.mint,
+        sell_amount,
+        poster_buy_account: *buy_account.key,
+        buy_mint,
+        buy_amount,
+        fee_bps: config.fee_bps,
+        expires_at,
+        allowed_taker,
+        dutch_auction: None,
+        english_auction: None,
+        sealed_auction: None,
+        call_option: None,
+        rfq: None,
+    };
+    escrow_info.serialize(&mut *escrow_account.try_borrow_mut_data()?)?;
+
+    //
+    // approve the escrow's PDA to spend the tokens being sold
+    //
+    msg!("Approving the escrow's PDA as delegate");
+    invoke(
+        &spl_token_2022::instruction::approve(
+            token_program.key,
+            sell_account.key,
+            &pda,
+            poster.key,
+            &[poster.key],
+            sell_amount,
+        )?,
+        &[sell_account.clone(), poster.clone(), token_program.clone()],
+    )?;
+
+    Ok(())
+}
+
+/// Mint of the token the poster receives into `buy_account`.
+/// A poster asks for native SOL by passing their own account instead of a token account
+fn receiving_mint(poster: &AccountInfo, buy_account: &AccountInfo) -> Result<Pubkey, ProgramError> {
+    if buy_account.key == poster.key {
+        return Ok(spl_token::native_mint::id());
+    }
+    if !is_token_program(buy_account.owner) {
+        return Err(Error::AccountNotToken.into());
+    }
+    Ok(unpack_token_account(buy_account)?.mint)
+}
+
+fn take_posting_fee<'a>(
+    config: &Config,
+    poster: &AccountInfo<'a>,
+    fee_account: &AccountInfo<'a>,
+    system_program: &AccountInfo<'a>,
+) -> ProgramResult {
+    if config.fee == 0 {
+        return Ok(());
+    }
+    invoke(
+        &solana_program::system_instruction::transfer(poster.key, fee_account.key, config.fee),
+        &[poster.clone(), fee_account.clone(), system_program.clone()],
+    )
+}
+
+fn create_escrow_account<'a>(
+    program_id: &Pubkey,
+    poster: &AccountInfo<'a>,
+    escrow_account: &AccountInfo<'a>,
+    system_program: &AccountInfo<'a>,
+    offer_id: u64,
+    space: usize,
+) -> ProgramResult {
+    let (escrow, bump_seed) = find_escrow_address(poster.key, offer_id, program_id);
+    if *escrow_account.key != escrow {
+        return Err(Error::IncorrectPDA.into());
+    }
+
+    msg!("Creating escrow account");
+    create_pda_account(
+        poster,
+        escrow_account,
+        space,
+        program_id,
+        system_program,
+        &[
+            OFFER_SEED,
+            poster.key.as_ref(),
+            &offer_id.to_le_bytes(),
+            &[bump_seed],
+        ],
+    )
+}
+
+/// Creates the account at the PDA of `seeds`, owned by `owner` and rent exempt with `space` bytes of data.
+/// Lamports sent to the address beforehand are kept and only topped up, so they can't block its creation
+fn create_pda_account<'a>(
+    payer: &AccountInfo<'a>,
+    account: &AccountInfo<'a>,
+    space: usize,
+    owner: &Pubkey,
+    system_program: &AccountInfo<'a>,
+    seeds: &[&[u8]],
+) -> ProgramResult {
+    let rent = Rent::get()?.minimum_balance(space);
+    if account.lamports() == 0 {
+        return invoke_signed(
+            &solana_program::system_instruction::create_account(
+                payer.key,
+                account.key,
+                rent,
+                space as u64,
+                owner,
+            ),
+            &[payer.clone(), account.clone(), system_program.clone()],
+            &[seeds],
+        );
+    }
+
+    let top_up = rent.saturating_sub(account.lamports());
+    if top_up > 0 {
+        invoke(
+            &solana_program::system_instruction::transfer(payer.key, account.key, top_up),
+            &[payer.clone(), account.clone(), system_program.clone()],
+        )?;
+    }
+    invoke_signed(
+        &solana_program::system_instruction::allocate(account.key, space as u64),
+        &[account.clone(), system_program.clone()],
+        &[seeds],
+    )?;
+    invoke_signed(
+        &solana_program::system_instruction::assign(account.key, owner),
+        &[account.clone(), system_program.clone()],
+        &[seeds],
+    )
+}
+
+/// Creates a vault token account at the PDA of `vault_seeds`, owned by `authority`, with room for
+/// any extensions the mint requires of its token accounts
+fn create_vault<'a>(
+    poster: &AccountInfo<'a>,
+    vault_account: &AccountInfo<'a>,
+    mint: &AccountInfo<'a>,
+    token_program: &AccountInfo<'a>,
+    system_program: &AccountInfo<'a>,
+    authority: &Pubkey,
+    vault_seeds: &[&[u8]],
+) -> ProgramResult {
+    let mint_extensions =
+        StateWithExtensions::<Mint>::unpack(&mint.try_borrow_data()?)?.get_extension_types()?;
+    let space = ExtensionType::get_account_len::<TokenAccount>(
+        &ExtensionType::get_required_init_account_extensions(&mint_extensions),
+    );
+    create_pda_account(
+        poster,
+        vault_account,
+        space,
+        token_program.key,
+        system_program,
+        vault_seeds,
+    )?;
+    invoke(
+        &spl_token_2022::instruction::initialize_account3(
+            token_program.key,
+            vault_account.key,
+            mint.key,
+            authority,
+        )?,
+        &[vault_account.clone(), mint.clone(), token_program.clone()],
+    )
+}
+
+/// Lowers what an escrow asks by the `price` paid for `fill_amount` of the `remaining_amount` on offer.
+/// A Dutch auction's prices are scaled down to what is left of the offer instead
+fn record_partial_fill(
+    escrow_info: &mut Escrow,
+    fill_amount: u64,
+    remaining_amount: u64,
+    price: u64,
+) -> ProgramResult {
+    match &mut escrow_info.dutch_auction {
+        Some(auction) => {
+            let left_amount = remaining_amount - fill_amount;
+            auction.start_price = fill_price(left_amount, remaining_amount, auction.start_price)
+                .ok_or(Error::InvalidFillAmount)?;
+            auction.end_price = fill_price(left_amount, remaining_amount, auction.end_price)
+                .ok_or(Error::InvalidFillAmount)?;
+            escrow_info.buy_amount = auction.end_price;
+        }
+        None => escrow_info.buy_amount -= price,
+    }
+    Ok(())
+}
+
+/// Amount of the poster's tokens still on offer in the escrow's token account
+fn remaining_amount(escrow_info: &Escrow, token_info: &TokenAccount, authority: &Pubkey) -> u64 {
+    match escrow_info.custody {
+        Custody::Vault => token_info.amount,
+        Custody::Delegated if token_info.delegate == COption::Some(*authority) => {
+            token_info.delegated_amount
+        }
+        Custody::Delegated => 0,
+    }
+}
+
+fn process_take(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    buy_amount: u64,
+    sell_amount: u64,
+    version: u64,
+) -> ProgramResult {
+    msg!("Instruction: Take");
+    take_offer(program_id, accounts, buy_amount, sell_amount, version, None)
+}
+
+/// Takes an offer as `Take` does. A quote can only be taken this way by accepting it for the request `rfq`,
+/// and other offers only without one
+fn take_offer(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    buy_amount: u64,
+    sell_amount: u64,
+    version: u64,
+    rfq: Option<&Pubkey>,
+) -> ProgramResult {
+    //
+    // deserialize accounts info
+    //
+    msg!("Deserializing accounts");
+    let mut accounts_iter = accounts.iter();
+
+    let taker = next_account_info(&mut accounts_iter)?;
+    if !taker.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    let taker_sell_account = next_account_info(&mut accounts_iter)?;
+    let taker_buy_account = next_account_info(&mut accounts_iter)?;
+    let token_account = next_account_info(&mut accounts_iter)?;
+    let poster = next_account_info(&mut accounts_iter)?;
+    let poster_buy_account = next_account_info(&mut accounts_iter)?;
+    let escrow_account = next_account_info(&mut accounts_iter)?;
+    let token_program = next_account_info(&mut accounts_iter)?;
+    check_token_program(token_program)?;
+    if token_account.owner != token_program.key {
+        return Err(Error::AccountNotToken.into());
+    }
+    let pda_account = next_account_info(&mut accounts_iter)?;
+    let config_account = next_account_info(&mut accounts_iter)?;
+    let config = load_config(program_id, config_account)?;
+    let fee_token_account = next_account_info(&mut accounts_iter)?;
+    let system_program = next_account_info(&mut accounts_iter)?;
+    let unwrap_account = next_account_info(&mut accounts_iter)?;
+    let native_mint = next_account_info(&mut accounts_iter)?;
+    let sell_mint = next_account_info(&mut accounts_iter)?;
+    let buy_mint = next_account_info(&mut accounts_iter)?;
+    let buy_token_program = next_account_info(&mut accounts_iter)?;
+    check_token_program(buy_token_program)?;
+
+    //
+    // Deserialize token account info
+    //
+    msg!("Deserializing token accounts");
+    let token_info = unpack_token_account(token_account)?;
+
+    //
+    // Deserialize escrow account info
+    //
+    msg!("Deserializing escrow info");
+    let mut escrow_info = load_escrow(program_id, escrow_account)?;
+    if escrow_info.token_account != *token_account.key {
+        return Err(Error::DoesntMatchEscrow.into());
+    }
+    if escrow_info.poster != *poster.key {
+        return Err(Error::DoesntMatchEscrow.into());
+    }
+    if escrow_info.poster_buy_account != *poster_buy_account.key {
+        return Err(Error::DoesntMatchEscrow.into());
+    }
+    if escrow_info.sell_mint != *sell_mint.key || escrow_info.buy_mint != *buy_mint.key {
+        return Err(Error::MintMismatch.into());
+    }
+    if sell_mint.owner != token_program.key || buy_mint.owner != buy_token_program.key {
+        return Err(Error::AccountNotToken.into());
+    }
+
+    // Native SOL is paid from, or unwrapped into, the taker's own account
+    let pays_native = escrow_info.poster_buy_account == escrow_info.poster;
+    let receives_native =
+        escrow_info.sell_mint == spl_token::native_mint::id() && taker_buy_account.key == taker.key;
+    if pays_native {
+        if taker_sell_account.key != taker.key {
+            return Err(Error::MintMismatch.into());
+        }
+    } else if unpack_token_account(taker_sell_account)?.mint != escrow_info.buy_mint {
+        return Err(Error::MintMismatch.into());
+    }
+    if !receives_native && unpack_token_account(taker_buy_account)?.mint != escrow_info.sell_mint {
+        return Err(Error::MintMismatch.into());
+    }
+    if escrow_info.version != version {
+        return Err(Error::StaleOffer.into());
+    }
+    match rfq {
+        Some(rfq) if escrow_info.rfq.as_ref() != Some(rfq) => {
+            return Err(Error::DoesntMatchRfq.into())
+        }
+        Some(_) => {}
+        None => check_takeable(&escrow_info)?,
+    }
+    let clock = Clock::get()?;
+    if escrow_info.is_expired(clock.unix_timestamp) {
+        return Err(Error::OfferExpired.into());
+    }
+    if matches!(escrow_info.allowed_taker, Some(allowed_taker) if allowed_taker != *taker.key) {
+        return Err(Error::NotAllowedTaker.into());
+    }
+    let authority = EscrowAuthority::new(
+        program_id,
+        pda_account,
+        escrow_account.key,
+        &escrow_info.poster,
+        escrow_info.authority_bump,
+    )?;
+
+    //
+    // Work out the pro-rata price of the fill, at the current price of a Dutch auction.
+    // The taker pays any transfer fee on top of it, so the poster receives their full share
+    // less the basis-point fee
+    //
+    let remaining_amount = remaining_amount(&escrow_info, &token_info, pda_account.key);
+    let fully_filled = buy_amount == remaining_amount;
+    let asked_amount = escrow_info.current_buy_amount(clock.unix_timestamp);
+    let price =
+        fill_price(buy_amount, remaining_amount, asked_amount).ok_or(Error::InvalidFillAmount)?;
+    if !fully_filled && price >= asked_amount {
+        return Err(Error::InvalidFillAmount.into());
+    }
+    let fee = token_fee(price, escrow_info.fee_bps);
+    let (poster_amount, fee_amount) = if pays_native {
+        (price - fee, fee)
+    } else {
+        let mint_data = buy_mint.try_borrow_data()?;
+        (
+            amount_with_transfer_fee(&mint_data, clock.epoch, price - fee)?,
+            amount_with_transfer_fee(&mint_data, clock.epoch, fee)?,
+        )
+    };
+    // the price of a Dutch auction can only have fallen since the taker worked it out
+    let total_amount = poster_amount
+        .checked_add(fee_amount)
+        .ok_or(Error::AmountOverflow)?;
+    if total_amount != sell_amount
+        && (escrow_info.dutch_auction.is_none() || total_amount > sell_amount)
+    {
+        return Err(Error::ExpectedAmountMismatch.into());
+    }
+
+    //
+    // Send token Y amount from taker's to poster's account, less the fee
+    //
+    if pays_native {
+        msg!("Sending SOL from Taker to Poster");
+        invoke(
+            &solana_program::system_instruction::transfer(taker.key, poster.key, poster_amount),
+            &[taker.clone(), poster.clone(), system_program.clone()],
+        )?;
+    } else {
+        msg!("Sending token Y from Taker to Poster");
+        transfer_tokens(
+            buy_token_program,
+            taker_sell_account,
+            buy_mint,
+            poster_buy_account,
+            taker,
+            poster_amount,
+            &[],
+        )?;
+    }
+
+    if fee > 0 && pays_native {
+        msg!("Sending {} lamports from Taker as fee", fee);
+        if *fee_token_account.key != config.fee_recipient {
+            return Err(Error::IncorrectFeeAccount.into());
+        }
+        invoke(
+            &solana_program::system_instruction::transfer(taker.key, fee_token_account.key, fee),
+            &[
+                taker.clone(),
+                fee_token_account.clone(),
+                system_program.clone(),
+            ],
+        )?;
+    } else if fee > 0 {
+        msg!("Sending {} of token Y from Taker as fee", fee);
+        let fee_token_info = unpack_token_account(fee_token_account)?;
+        if fee_token_info.owner != find_fee_authority(program_id).0
+            || fee_token_info.mint != escrow_info.buy_mint
+        {
+            return Err(Error::IncorrectFeeAccount.into());
+        }
+        transfer_tokens(
+            buy_token_program,
+            taker_sell_account,
+            buy_mint,
+            fee_token_account,
+            taker,
+            fee_amount,
+            &[],
+        )?;
+    }
+
+    //
+    // Send token X amount from token account to taker's account,
+    // closing the vault once it is empty
+    //
+    let destination_account = if receives_native {
+        create_unwrap_account(
+            program_id,
+            escrow_account.key,
+            taker,
+            unwrap_account,
+            native_mint,
+            system_program,
+            token_program,
+        )?;
+        unwrap_account
+    } else {
+        taker_buy_account
+    };
+    msg!("Sending token X from Poster to Taker");
+    if fully_filled && escrow_info.custody == Custody::Vault {
+        transfer_and_close(
+            token_program,
+            token_account,
+            sell_mint,
+            destination_account,
+            poster,
+            &authority,
+            buy_amount,
+        )?;
+    } else {
+        transfer_from_pda(
+            token_program,
+            token_account,
+            sell_mint,
+            destination_account,
+            &authority,
+            buy_amount,
+        )?;
+    }
+    if receives_native {
+        msg!("Unwrapping SOL to Taker");
+        close_unwrap_account(token_program, unwrap_account, taker)?;
+    }
+
+    if !fully_filled {
+        //
+        // Record what is left of the offer
+        //
+        record_partial_fill(&mut escrow_info, buy_amount, remaining_amount, price)?;
+        escrow_info.serialize(&mut *escrow_account.try_borrow_mut_data()?)?;
+        return Ok(());
+    }
+
+    //
+    // Close escrow account
+    //
+    close_escrow(escrow_account, poster)?;
+
+    Ok(())
+}
+
+fn process_cancel(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    //
+    // deserialize accounts info
+    //
+    msg!("Deserializing accounts");
+    let mut accounts_iter = accounts.iter();
+
+    let poster = next_account_info(&mut accounts_iter)?;
+    if !poster.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    let token_account = next_account_info(&mut accounts_iter)?;
+    let escrow = next_account_info(&mut accounts_iter)?;
+    let refund_account = next_account_info(&mut accounts_iter)?;
+    let token_program = next_account_info(&mut accounts_iter)?;
+    check_token_program(token_program)?;
+    if token_account.owner != token_program.key {
+        return Err(Error::AccountNotToken.into());
+    }
+    let pda_account = next_account_info(&mut accounts_iter)?;
+    let sell_mint = next_account_info(&mut accounts_iter)?;
+
+    //
+    // Deserialize token account info
+    //
+    msg!("Deserializing token account");
+    let token_info = unpack_token_account(token_account)?;
+
+    //
+    // Deserialize escrow account info
+    //
+    msg!("Deserializing escrow info");
+    let escrow_info = load_escrow(program_id, escrow)?;
+    if escrow_info.token_account != *token_account.key {
+        return Err(Error::DoesntMatchEscrow.into());
+    }
+    if escrow_info.poster != *poster.key {
+        return Err(Error::DoesntMatchEscrow.into());
+    }
+    if escrow_info.sell_mint != *sell_mint.key {
+        return Err(Error::MintMismatch.into());
+    }
+    if matches!(escrow_info.english_auction, Some(auction) if auction.bid.is_some())
+        || matches!(escrow_info.sealed_auction, Some(auction) if auction.has_bids())
+    {
+        return Err(Error::AuctionHasBids.into());
+    }
+    if matches!(escrow_info.call_option, Some(option) if option.is_sold) {
+        return Err(Error::OptionSold.into());
+    }
+    let authority = EscrowAuthority::new(
+        program_id,
+        pda_account,
+        escrow.key,
+        &escrow_info.poster,
+        escrow_info.authority_bump,
+    )?;
+
+    match escrow_info.custody {
+        Custody::Vault if refund_account.key == poster.key => {
+            //
+            // Unwrap the SOL in the vault straight into the poster's account
+            //
+            if escrow_info.sell_mint != spl_token::native_mint::id() {
+                return Err(Error::MintMismatch.into());
+            }
+            msg!("Unwrapping SOL to poster and closing the vault");
+            close_vault(token_program, token_account, sell_mint, poster, &authority)?;
+        }
+        Custody::Vault => {
+            //
+            // Return the tokens in the vault to the poster
+            //
+            msg!("Returning tokens to poster and closing the vault");
+            transfer_and_close(
+                token_program,
+                token_account,
+                sell_mint,
+                refund_account,
+                poster,
+                &authority,
+                token_info.amount,
+            )?;
+        }
+        Custody::Delegated => {
+            //
+            // Revoke the escrow PDA's delegation over the poster's tokens
+            //
+            msg!("Revoking delegation");
+            invoke(
+                &spl_token_2022::instruction::revoke(
+                    token_program.key,
+                    token_account.key,
+                    poster.key,
+                    &[poster.key],
+                )?,
+                &[token_account.clone(), poster.clone(), token_program.clone()],
+            )?;
+        }
+    }
+
+    //
+    // Close escrow account
+    //
+    close_escrow(escrow, poster)?;
+
+    Ok(())
+}
+
+fn process_amend(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    buy_amount: u64,
+    sell_amount: Option<u64>,
+) -> ProgramResult {
+    msg!("Instruction: Amend");
+
+    //
+    // deserialize accounts info
+    //
+    msg!("Deserializing accounts");
+    let mut accounts_iter = accounts.iter();
+
+    let poster = next_account_info(&mut accounts_iter)?;
+    if !poster.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    let escrow = next_account_info(&mut accounts_iter)?;
+    let token_account = next_account_info(&mut accounts_iter)?;
+    let poster_sell_account = next_account_info(&mut accounts_iter)?;
+    let token_program = next_account_info(&mut accounts_iter)?;
+    check_token_program(token_program)?;
+    if token_account.owner != token_program.key {
+        return Err(Error::AccountNotToken.into());
+    }
+    let pda_account = next_account_info(&mut accounts_iter)?;
+    let system_program = next_account_info(&mut accounts_iter)?;
+    let unwrap_account = next_account_info(&mut accounts_iter)?;
+    let native_mint = next_account_info(&mut accounts_iter)?;
+    let sell_mint = next_account_info(&mut accounts_iter)?;
+
+    //
+    // Deserialize escrow account info
+    //
+    msg!("Deserializing escrow info");
+    let mut escrow_info = load_escrow(program_id, escrow)?;
+    if escrow_info.token_account != *token_account.key {
+        return Err(Error::DoesntMatchEscrow.into());
+    }
+    if escrow_info.poster != *poster.key {
+        return Err(Error::DoesntMatchEscrow.into());
+    }
+    if escrow_info.sell_mint != *sell_mint.key {
+        return Err(Error::MintMismatch.into());
+    }
+    if buy_amount == 0 || sell_amount == Some(0) {
+        return Err(Error::InvalidAmendment.into());
+    }
+    check_takeable(&escrow_info)?;
+    // Native SOL is wrapped from, or unwrapped into, the poster's own account
+    let native = poster_sell_account.key == poster.key;
+    if native && escrow_info.sell_mint != spl_token::native_mint::id() {
+        return Err(Error::MintMismatch.into());
+    }
+    let authority = EscrowAuthority::new(
+        program_id,
+        pda_account,
+        escrow.key,
+        &escrow_info.poster,
+        escrow_info.authority_bump,
+    )?;
+
+    //
+    // Adjust the tokens on offer
+    //
+    if let Some(sell_amount) = sell_amount {
+        let token_info = unpack_token_account(token_account)?;
+        let remaining_amount = remaining_amount(&escrow_info, &token_info, pda_account.key);
+        match escrow_info.custody {
+            Custody::Vault if native && sell_amount > remaining_amount => {
+                msg!("Wrapping SOL into the vault");
+                wrap_native(
+                    token_program,
+                    system_program,
+                    poster,
+                    token_account,
+                    sell_amount - remaining_amount,
+                )?;
+            }
+            Custody::Vault if native && sell_amount < remaining_amount => {
+                msg!("Unwrapping SOL from the vault");
+                create_unwrap_account(
+                    program_id,
+                    escrow.key,
+                    poster,
+                    unwrap_account,
+                    native_mint,
+                    system_program,
+                    token_program,
+                )?;
+                transfer_from_pda(
+                    token_program,
+                    token_account,
+                    sell_mint,
+                    unwrap_account,
+                    &authority,
+                    remaining_amount - sell_amount,
+                )?;
+                close_unwrap_account(token_program, unwrap_account, poster)?;
+            }
+            Custody::Vault if sell_amount > remaining_amount => {
+                msg!("Topping up the vault");
+                transfer_tokens(
+                    token_program,
+                    poster_sell_account,
+                    sell_mint,
+                    token_account,
+                    poster,
+                    sell_amount - remaining_amount,
+                    &[],
+                )?;
+            }
+            Custody::Vault if sell_amount < remaining_amount => {
+                msg!("Withdrawing from the vault");
+                if unpack_token_account(poster_sell_account)?.owner != escrow_info.poster {
+                    return Err(Error::DoesntMatchEscrow.into());
+                }
+                transfer_from_pda(
+                    token_program,
+                    token_account,
+                    sell_mint,
+                    poster_sell_account,
+                    &authority,
+                    remaining_amount - sell_amount,
+                )?;
+            }
+            Custody::Vault => {}
+            Custody::Delegated => {
+                msg!("Changing the delegated amount");
+                invoke(
+                    &spl_token_2022::instruction::approve(
+                        token_program.key,
+                        token_account.key,
+                        authority.account.key,
+                        poster.key,
+                        &[poster.key],
+                        sell_amount,
+                    )?,
+                    &[token_account.clone(), poster.clone(), token_program.clone()],
+                )?;
+            }
+        }
+        escrow_info.sell_amount = sell_amount;
+    }
+
+    //
+    // Record the new terms
+    //
+    escrow_info.buy_amount = buy_amount;
+    escrow_info.dutch_auction = None;
+    escrow_info.version += 1;
+    escrow_info.serialize(&mut *escrow.try_borrow_mut_data()?)?;
+
+    Ok(())
+}
+
+fn process_reclaim(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    msg!("Instruction: Reclaim");
+
+    //
+    // deserialize accounts info
+    //
+    msg!("Deserializing accounts");
+    let mut accounts_iter = accounts.iter();
+
+    let caller = next_account_info(&mut accounts_iter)?;
+    if !caller.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    let refund = Refund::load(program_id, &mut accounts_iter)?;
+    if !refund.escrow_info.is_expired(Clock::get()?.unix_timestamp) {
+        return Err(Error::OfferNotExpired.into());
+    }
+
+    //
+    // Return tokens to the poster
+    //
+    refund.return_tokens()?;
+
+    //
+    // Pay the caller their reward, then close escrow account
+    //
+    let escrow = refund.escrow;
+    let reward = RECLAIM_REWARD.min(escrow.lamports());
+    **caller.lamports.borrow_mut() = caller
+        .lamports()
+        .checked_add(reward)
+        .ok_or(Error::AmountOverflow)?;
+    **escrow.lamports.borrow_mut() = escrow.lamports() - reward;
+    close_escrow(escrow, refund.poster)?;
+
+    Ok(())
+}
+
+/// An offer being returned to its poster without them signing, by `Reclaim` once it has expired
+/// or by `AcceptQuote` for the quotes that weren't accepted
+struct Refund<'a, 'b> {
+    escrow: &'b AccountInfo<'a>,
+    escrow_info: Escrow,
+    token_account: &'b AccountInfo<'a>,
+    poster: &'b AccountInfo<'a>,
+    refund_account: &'b AccountInfo<'a>,
+    token_program: &'b AccountInfo<'a>,
+    authority: EscrowAuthority<'a, 'b>,
+    sell_mint: &'b AccountInfo<'a>,
+    amount: u64,
+}
+
+impl<'a, 'b> Refund<'a, 'b> {
+    /// Loads the accounts of `Reclaim` after the caller
+    fn load<I: Iterator<Item = &'b AccountInfo<'a>>>(
+        program_id: &Pubkey,
+        accounts_iter: &mut I,
+    ) -> Result<Self, ProgramError> {
+        let token_account = next_account_info(accounts_iter)?;
+        let escrow = next_account_info(accounts_iter)?;
+        let poster = next_account_info(accounts_iter)?;
+        // Native SOL is unwrapped straight into the poster's account
+        let refund_account = next_account_info(accounts_iter)?;
+        let refunds_native = refund_account.key == poster.key;
+
+        let token_program = next_account_info(accounts_iter)?;
+        check_token_program(token_program)?;
+        if token_account.owner != token_program.key
+            || (!refunds_native && refund_account.owner != token_program.key)
+        {
+            return Err(Error::AccountNotToken.into());
+        }
+        let pda_account = next_account_info(accounts_iter)?;
+        let sell_mint = next_account_info(accounts_iter)?;
+
+        //
+        // Deserialize token account info
+        //
+        msg!("Deserializing token accounts");
+        let token_info = unpack_token_account(token_account)?;
+
+        //
+        // Deserialize escrow account info
+        //
+        msg!("Deserializing escrow info");
+        let escrow_info = load_escrow(program_id, escrow)?;
+        if escrow_info.token_account != *token_account.key {
+            return Err(Error::DoesntMatchEscrow.into());
+        }
+        if escrow_info.poster != *poster.key {
+            return Err(Error::DoesntMatchEscrow.into());
+        }
+        if escrow_info.sell_mint != *sell_mint.key {
+            return Err(Error::MintMismatch.into());
+        }
+        if refunds_native {
+            if token_info.mint != spl_token::native_mint::id() {
+                return Err(Error::MintMismatch.into());
+            }
+        } else {
+            let refund_info = unpack_token_account(refund_account)?;
+            if refund_info.owner != escrow_info.poster || refund_info.mint != token_info.mint {
+                return Err(Error::DoesntMatchEscrow.into());
+            }
+        }
+        let authority = EscrowAuthority::new(
+            program_id,
+            pda_account,
+            escrow.key,
+            &escrow_info.poster,
+            escrow_info.authority_bump,
+        )?;
+
+        Ok(Refund {
+            escrow,
+            escrow_info,
+            token_account,
+            poster,
+            refund_account,
+            token_program,
+            authority,
+            sell_mint,
+            amount: token_info.amount,
+        })
+    }
+
+    /// Returns the tokens in the vault to the poster and closes it. Delegated tokens never left the poster's
+    /// account, and only the poster can revoke the delegation, which is useless once the escrow is closed
+    fn return_tokens(&self) -> ProgramResult {
+        if self.escrow_info.custody != Custody::Vault {
+            return Ok(());
+        }
+        if self.refund_account.key == self.poster.key {
+            msg!("Unwrapping SOL to poster");
+            close_vault(
+                self.token_program,
+                self.token_account,
+                self.sell_mint,
+                self.poster,
+                &self.authority,
+            )
+        } else {
+            msg!("Returning tokens to poster");
+            transfer_and_close(
+                self.token_program,
+                self.token_account,
+                self.sell_mint,
+                self.refund_account,
+                self.poster,
+                &self.authority,
+                self.amount,
+            )
+        }
+    }
+}
+
+fn process_initialize_config(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    fee: u64,
+    fee_recipient: Pubkey,
+    fee_bps: u16,
+) -> ProgramResult {
+    msg!("Instruction: InitializeConfig");
+
+    if fee_bps > MAX_FEE_BPS {
+        return Err(Error::InvalidFeeRate.into());
+    }
+
+    //
+    // deserialize accounts info
+    //
+    let mut accounts_iter = accounts.iter();
+
+    let admin = next_account_info(&mut accounts_iter)?;
+    if !admin.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    let config_account = next_account_info(&mut accounts_iter)?;
+    let (config_address, bump_seed) = find_config_address(program_id);
+    if *config_account.key != config_address {
+        return Err(Error::IncorrectPDA.into());
+    }
+
+    let system_program = next_account_info(&mut accounts_iter)?;
+
+    // only the upgrade authority may initialize, so nobody can front-run the deployer
+    let program_data = next_account_info(&mut accounts_iter)?;
+    let (program_data_address, _) =
+        Pubkey::find_program_address(&[program_id.as_ref()], &bpf_loader_upgradeable::id());
+    if *program_data.key != program_data_address {
+        return Err(ProgramError::InvalidAccountData);
+    }
+    let metadata_len = UpgradeableLoaderState::programdata_data_offset()
+        .map_err(|_| ProgramError::InvalidAccountData)?;
+    let upgrade_authority =
+        match limited_deserialize(&program_data.try_borrow_data()?, metadata_len as u64) {
+            Ok(UpgradeableLoaderState::ProgramData {
+                upgrade_authority_address,
+                ..
+            }) => upgrade_authority_address,
+            _ => return Err(ProgramError::InvalidAccountData),
+        };
+    if upgrade_authority != Some(*admin.key) {
+        return Err(Error::NotUpgradeAuthority.into());
+    }
+
+    //
+    // create config account
+    //
+    msg!("Creating config account");
+    let space = get_packed_len::<Config>();
+    create_pda_account(
+        admin,
+        config_account,
+        space,
+        program_id,
+        system_program,
+        &[CONFIG_SEED, &[bump_seed]],
+    )?;
+
+    let config = Config {
+        is_initialized: true,
+        admin: *admin.key,
+        fee,
+        fee_recipient,
+        fee_bps,
+    };
+    config.serialize(&mut *config_account.try_borrow_mut_data()?)?;
+
+    Ok(())
+}
+
+fn process_update_config(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    new_admin: Pubkey,
+    fee: u64,
+    fee_recipient: Pubkey,
+    fee_bps: u16,
+) -> ProgramResult {
+    msg!("Instruction: UpdateConfig");
+
+    if fee_bps > MAX_FEE_BPS {
+        return Err(Error::InvalidFeeRate.into());
+    }
+
+    //
+    // deserialize accounts info
+    //
+    let mut accounts_iter = accounts.iter();
+
+    let admin = next_account_info(&mut accounts_iter)?;
+    if !admin.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    let config_account = next_account_info(&mut accounts_iter)?;
+    let mut config = load_config(program_id, config_account)?;
+    if config.admin != *admin.key {
+        return Err(Error::NotAdmin.into());
+    }
+
+    //
+    // update config info
+    //
+    config.admin = new_admin;
+    config.fee = fee;
+    config.fee_recipient = fee_recipient;
+    config.fee_bps = fee_bps;
+    config.serialize(&mut *config_account.try_borrow_mut_data()?)?;
+
+    Ok(())
+}
+
+fn process_withdraw_fees(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    amount: Option<u64>,
+) -> ProgramResult {
+    msg!("Instruction: WithdrawFees");
+
+    //
+    // deserialize accounts info
+    //
+    let mut accounts_iter = accounts.iter();
+
+    let admin = next_account_info(&mut accounts_iter)?;
+    if !admin.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    let config_account = next_account_info(&mut accounts_iter)?;
+    let config = load_config(program_id, config_account)?;
+    if config.admin != *admin.key {
+        return Err(Error::NotAdmin.into());
+    }
+
+    let fee_account = next_account_info(&mut accounts_iter)?;
+    if *fee_account.key != config.fee_recipient || fee_account.owner != program_id {
+        return Err(Error::IncorrectFeeAccount.into());
+    }
+
+    let destination = next_account_info(&mut accounts_iter)?;
+
+    //
+    // move lamports above rent exemption
+    //
+    let available = fee_account
+        .lamports()
+        .saturating_sub(Rent::get()?.minimum_balance(fee_account.data_len()));
+    let amount = amount.unwrap_or(available);
+    if amount > available {
+        return Err(Error::InsufficientFees.into());
+    }
+    msg!("Withdrawing {} lamports of fees", amount);
+    **fee_account.lamports.borrow_mut() = fee_account.lamports() - amount;
+    **destination.lamports.borrow_mut() = destination
+        .lamports()
+        .checked_add(amount)
+        .ok_or(Error::AmountOverflow)?;
+
+    Ok(())
+}
+
+fn process_withdraw_token_fees(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    amount: Option<u64>,
+) -> ProgramResult {
+    msg!("Instruction: WithdrawTokenFees");
+
+    //
+    // deserialize accounts info
+    //
+    let mut accounts_iter = accounts.iter();
+
+    let admin = next_account_info(&mut accounts_iter)?;
+    if !admin.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    let config_account = next_account_info(&mut accounts_iter)?;
+    let config = load_config(program_id, config_account)?;
+    if config.admin != *admin.key {
+        return Err(Error::NotAdmin.into());
+    }
+
+    let fee_token_account = next_account_info(&mut accounts_iter)?;
+    let destination = next_account_info(&mut accounts_iter)?;
+    let token_program = next_account_info(&mut accounts_iter)?;
+    check_token_program(token_program)?;
+    let fee_authority = next_account_info(&mut accounts_iter)?;
+    let (fee_pda, bump_seed) = find_fee_authority(program_id);
+    if *fee_authority.key != fee_pda {
+        return Err(Error::IncorrectPDA.into());
+    }
+    let mint = next_account_info(&mut accounts_iter)?;
+
+    //
+    // move the collected tokens
+    //
+    let fee_token_info = unpack_token_account(fee_token_account)?;
+    let amount = amount.unwrap_or(fee_token_info.amount);
+    if amount > fee_token_info.amount {
+        return Err(Error::InsufficientFees.into());
+    }
+    msg!("Withdrawing {} tokens of fees", amount);
+    transfer_tokens(
+        token_program,
+        fee_token_account,
+        mint,
+        destination,
+        fee_authority,
+        amount,
+        &[&[FEE_SEED, &[bump_seed]]],
+    )?;
+
+    Ok(())
+}
+
+fn process_post_bundle(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    offer_id: u64,
+    sell_amounts: &[u64],
+    buy_amounts: &[u64],
+    expires_at: Option<i64>,
+    allowed_taker: Option<Pubkey>,
+) -> ProgramResult {
+    msg!("Instruction: PostBundle");
+
+    let leg_counts = 1..=MAX_BUNDLE_LEGS;
+    if !leg_counts.contains(&sell_amounts.len()) || !leg_counts.contains(&buy_amounts.len()) {
+        return Err(Error::InvalidBundle.into());
+    }
+
+    //
+    // deserialize accounts info
+    //
+    let mut accounts_iter = accounts.iter();
+
+    let poster = next_account_info(&mut accounts_iter)?;
+    if !poster.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    let bundle_account = next_account_info(&mut accounts_iter)?;
+    let system_program = next_account_info(&mut accounts_iter)?;
+    let config_account = next_account_info(&mut accounts_iter)?;
+    let config = load_config(program_id, config_account)?;
+
+    let fee_account = next_account_info(&mut accounts_iter)?;
+    if *fee_account.key != config.fee_recipient {
+        return Err(Error::IncorrectFeeAccount.into());
+    }
+
+    //
+    // Take fee
+    //
+    take_posting_fee(&config, poster, fee_account, system_program)?;
+
+    //
+    // create bundle account
+    //
+    create_escrow_account(
+        program_id,
+        poster,
+        bundle_account,
+        system_program,
+        offer_id,
+        Bundle::LEN,
+    )?;
+    let (pda, bump_seed) = find_escrow_authority(bundle_account.key, poster.key, program_id);
+
+    //
+    // create a vault for each token being sold and move the tokens into it
+    //
+    let mut sell_legs = Vec::with_capacity(sell_amounts.len());
+    for (index, &amount) in (0u8..).zip(sell_amounts) {
+        let sell_account = next_account_info(&mut accounts_iter)?;
+        let vault_account = next_account_info(&mut accounts_iter)?;
+        let sell_mint = next_account_info(&mut accounts_iter)?;
+        let token_program = next_account_info(&mut accounts_iter)?;
+        check_token_program(token_program)?;
+        if sell_mint.owner != token_program.key {
+            return Err(Error::AccountNotToken.into());
+        }
+        if unpack_token_account(sell_account)?.mint != *sell_mint.key {
+            return Err(Error::MintMismatch.into());
+        }
+        let (vault, vault_bump_seed) = find_bundle_vault(bundle_account.key, index, program_id);
+        if *vault_account.key != vault {
+            return Err(Error::IncorrectPDA.into());
+        }
+
+        msg!("Creating vault token account {}", index);
+        create_vault(
+            poster,
+            vault_account,
+            sell_mint,
+            token_program,
+            system_program,
+            &pda,
+            &[
+                VAULT_SEED,
+                bundle_account.key.as_ref(),
+                &[index],
+                &[vault_bump_seed],
+            ],
+        )?;
+        msg!("Transferring tokens into vault {}", index);
+        transfer_tokens(
+            token_program,
+            sell_account,
+            sell_mint,
+            vault_account,
+            poster,
+            amount,
+            &[],
+        )?;
+
+        sell_legs.push(Leg {
+            mint: *sell_mint.key,
+            amount,
+            token_account: vault,
+        });
+    }
+
+    let mut buy_legs = Vec::with_capacity(buy_amounts.len());
+    for &amount in buy_amounts {
+        let buy_account = next_account_info(&mut accounts_iter)?;
+        if !is_token_program(buy_account.owner) {
+            return Err(Error::AccountNotToken.into());
+        }
+        buy_legs.push(Leg {
+            mint: unpack_token_account(buy_account)?.mint,
+            amount,
+            token_account: *buy_account.key,
+        });
+    }
+
+    //
+    // set bundle info
+    //
+    let bundle_info = Bundle {
+        is_initialized: true,
+        poster: *poster.key,
+        offer_id,
+        authority_bump: bump_seed,
+        sell_legs,
+        buy_legs,
+        fee_bps: config.fee_bps,
+        expires_at,
+        allowed_taker,
+    };
+    bundle_info.serialize(&mut *bundle_account.try_borrow_mut_data()?)?;
+
+    Ok(())
+}
+
+fn process_take_bundle(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    sell_amounts: &[u64],
+    buy_amounts: &[u64],
+) -> ProgramResult {
+    msg!("Instruction: TakeBundle");
+
+    //
+    // deserialize accounts info
+    //
+    msg!("Deserializing accounts");
+    let mut accounts_iter = accounts.iter();
+
+    let taker = next_account_info(&mut accounts_iter)?;
+    if !taker.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    let poster = next_account_info(&mut accounts_iter)?;
+    let bundle_account = next_account_info(&mut accounts_iter)?;
+    let pda_account = next_account_info(&mut accounts_iter)?;
+    let config_account = next_account_info(&mut accounts_iter)?;
+    load_config(program_id, config_account)?;
+
+    //
+    // Deserialize bundle account info
+    //
+    msg!("Deserializing bundle info");
+    let bundle_info = load_bundle(program_id, bundle_account)?;
+    if bundle_info.poster != *poster.key {
+        return Err(Error::DoesntMatchEscrow.into());
+    }
+    if sell_amounts.len() != bundle_info.sell_legs.len()
+        || buy_amounts.len() != bundle_info.buy_legs.len()
+    {
+        return Err(Error::InvalidBundle.into());
+    }
+    let clock = Clock::get()?;
+    if bundle_info.is_expired(clock.unix_timestamp) {
+        return Err(Error::OfferExpired.into());
+    }
+    if matches!(bundle_info.allowed_taker, Some(allowed_taker) if allowed_taker != *taker.key) {
+        return Err(Error::NotAllowedTaker.into());
+    }
+    let authority = EscrowAuthority::new(
+        program_id,
+        pda_account,
+        bundle_account.key,
+        &bundle_info.poster,
+        bundle_info.authority_bump,
+    )?;
+
+    //
+    // Pay the poster for every token they asked for, less the fee. The taker pays any transfer fees on top
+    //
+    let fee_authority = find_fee_authority(program_id).0;
+    for (leg, &expected_amount) in bundle_info.buy_legs.iter().zip(buy_amounts) {
+        let taker_sell_account = next_account_info(&mut accounts_iter)?;
+        let poster_buy_account = next_account_info(&mut accounts_iter)?;
+        let fee_token_account = next_account_info(&mut accounts_iter)?;
+        let buy_mint = next_account_info(&mut accounts_iter)?;
+        let buy_token_program = next_account_info(&mut accounts_iter)?;
+        check_token_program(buy_token_program)?;
+        if leg.token_account != *poster_buy_account.key {
+            return Err(Error::DoesntMatchEscrow.into());
+        }
+        if leg.mint != *buy_mint.key || unpack_token_account(taker_sell_account)?.mint != leg.mint {
+            return Err(Error::MintMismatch.into());
+        }
+        if buy_mint.owner != buy_token_program.key {
+            return Err(Error::AccountNotToken.into());
+        }
+
+        let fee = token_fee(leg.amount, bundle_info.fee_bps);
+        let (poster_amount, fee_amount) = {
+            let mint_data = buy_mint.try_borrow_data()?;
+            (
+                amount_with_transfer_fee(&mint_data, clock.epoch, leg.amount - fee)?,
+                amount_with_transfer_fee(&mint_data, clock.epoch, fee)?,
+            )
+        };
+        if poster_amount.checked_add(fee_amount) != Some(expected_amount) {
+            return Err(Error::ExpectedAmountMismatch.into());
+        }
+
+        msg!("Sending {} from Taker to Poster", leg.mint);
+        transfer_tokens(
+            buy_token_program,
+            taker_sell_account,
+            buy_mint,
+            poster_buy_account,
+            taker,
+            poster_amount,
+            &[],
+        )?;
+        if fee > 0 {
+            msg!("Sending {} of {} from Taker as fee", fee, leg.mint);
+            let fee_token_info = unpack_token_account(fee_token_account)?;
+            if fee_token_info.owner != fee_authority || fee_token_info.mint != leg.mint {
+                return Err(Error::IncorrectFeeAccount.into());
+            }
+            transfer_tokens(
+                buy_token_program,
+                taker_sell_account,
+                buy_mint,
+                fee_token_account,
+                taker,
+                fee_amount,
+                &[],
+            )?;
+        }
+    }
+
+    //
+    // Empty every vault into the taker's accounts, closing them
+    //
+    for (leg, &expected_amount) in bundle_info.sell_legs.iter().zip(sell_amounts) {
+        let vault_account = next_account_info(&mut accounts_iter)?;
+        let taker_buy_account = next_account_info(&mut accounts_iter)?;
+        let sell_mint = next_account_info(&mut accounts_iter)?;
+        let token_program = next_account_info(&mut accounts_iter)?;
+        check_token_program(token_program)?;
+        if leg.token_account != *vault_account.key {
+            return Err(Error::DoesntMatchEscrow.into());
+        }
+        if vault_account.owner != token_program.key {
+            return Err(Error::AccountNotToken.into());
+        }
+        if leg.mint != *sell_mint.key || unpack_token_account(taker_buy_account)?.mint != leg.mint {
+            return Err(Error::MintMismatch.into());
+        }
+        let vault_amount = unpack_token_account(vault_account)?.amount;
+        if vault_amount != expected_amount {
+            return Err(Error::ExpectedAmountMismatch.into());
+        }
+
+        msg!("Sending {} from Poster to Taker", leg.mint);
+        transfer_and_close(
+            token_program,
+            vault_account,
+            sell_mint,
+            taker_buy_account,
+            poster,
+            &authority,
+            vault_amount,
+        )?;
+    }
+
+    //
+    // Close bundle account
+    //
+    close_escrow(bundle_account, poster)?;
+
+    Ok(())
+}
+
+fn process_cancel_bundle(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    msg!("Instruction: CancelBundle");
+
+    //
+    // deserialize accounts info
+    //
+    msg!("Deserializing accounts");
+    let mut accounts_iter = accounts.iter();
+
+    let poster = next_account_info(&mut accounts_iter)?;
+    if !poster.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    let bundle_account = next_account_info(&mut accounts_iter)?;
+    let pda_account = next_account_info(&mut accounts_iter)?;
+
+    //
+    // Deserialize bundle account info
+    //
+    msg!("Deserializing bundle info");
+    let bundle_info = load_bundle(program_id, bundle_account)?;
+    if bundle_info.poster != *poster.key {
+        return Err(Error::DoesntMatchEscrow.into());
+    }
+    let authority = EscrowAuthority::new(
+        program_id,
+        pda_account,
+        bundle_account.key,
+        &bundle_info.poster,
+        bundle_info.authority_bump,
+    )?;
+
+    //
+    // Return the tokens in every vault to the poster
+    //
+    return_bundle_tokens(&mut accounts_iter, &bundle_info, poster, &authority)?;
+
+    //
+    // Close bundle account
+    //
+    close_escrow(bundle_account, poster)?;
+
+    Ok(())
+}
+
+fn process_reclaim_bundle(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    msg!("Instruction: ReclaimBundle");
+
+    //
+    // deserialize accounts info
+    //
+    msg!("Deserializing accounts");
+    let mut accounts_iter = accounts.iter();
+
+    let caller = next_account_info(&mut accounts_iter)?;
+    if !caller.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    let bundle_account = next_account_info(&mut accounts_iter)?;
+    let poster = next_account_info(&mut accounts_iter)?;
+    let pda_account = next_account_info(&mut accounts_iter)?;
+
+    //
+    // Deserialize bundle account info
+    //
+    msg!("Deserializing bundle info");
+    let bundle_info = load_bundle(program_id, bundle_account)?;
+    if bundle_info.poster != *poster.key {
+        return Err(Error::DoesntMatchEscrow.into());
+    }
+    if !bundle_info.is_expired(Clock::get()?.unix_timestamp) {
+        return Err(Error::OfferNotExpired.into());
+    }
+    let authority = EscrowAuthority::new(
+        program_id,
+        pda_account,
+        bundle_account.key,
+        &bundle_info.poster,
+        bundle_info.authority_bump,
+    )?;
+
+    //
+    // Return the tokens in every vault to the poster
+    //
+    return_bundle_tokens(&mut accounts_iter, &bundle_info, poster, &authority)?;
+
+    //
+    // Pay the caller their reward, then close bundle account
+    //
+    let reward = RECLAIM_REWARD.min(bundle_account.lamports());
+    **caller.lamports.borrow_mut() = caller
+        .lamports()
+        .checked_add(reward)
+        .ok_or(Error::AmountOverflow)?;
+    **bundle_account.lamports.borrow_mut() = bundle_account.lamports() - reward;
+    close_escrow(bundle_account, poster)?;
+
+    Ok(())
+}
+
+/// Empties every vault of a bundle into the poster's token accounts and closes them, sending their rent to the
+/// poster. Takes the vault, the poster's token account, the mint and the token program of each token on offer
+fn return_bundle_tokens<'a: 'b, 'b, I: Iterator<Item = &'b AccountInfo<'a>>>(
+    accounts_iter: &mut I,
+    bundle_info: &Bundle,
+    poster: &AccountInfo<'a>,
+    authority: &EscrowAuthority<'a, '_>,
+) -> ProgramResult {
+    for leg in &bundle_info.sell_legs {
+        let vault_account = next_account_info(accounts_iter)?;
+        let refund_account = next_account_info(accounts_iter)?;
+        let sell_mint = next_account_info(accounts_iter)?;
+        let token_program = next_account_info(accounts_iter)?;
+        check_token_program(token_program)?;
+        if leg.token_account != *vault_account.key {
+            return Err(Error::DoesntMatchEscrow.into());
+        }
+        if vault_account.owner != token_program.key || refund_account.owner != token_program.key {
+            return Err(Error::AccountNotToken.into());
+        }
+        if leg.mint != *sell_mint.key {
+            return Err(Error::MintMismatch.into());
+        }
+        let refund_info = unpack_token_account(refund_account)?;
+        if refund_info.owner != bundle_info.poster || refund_info.mint != leg.mint {
+            return Err(Error::DoesntMatchEscrow.into());
+        }
+
+        msg!("Returning {} to poster and closing its vault", leg.mint);
+        let vault_amount = unpack_token_account(vault_account)?.amount;
+        transfer_and_close(
+            token_program,
+            vault_account,
+            sell_mint,
+            refund_account,
+            poster,
+            authority,
+            vault_amount,
+        )?;
+    }
+    Ok(())
+}
+
+fn process_match(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    msg!("Instruction: Match");
+
+    //
+    // deserialize accounts info
+    //
+    msg!("Deserializing accounts");
+    let mut accounts_iter = accounts.iter();
+
+    let matcher = next_account_info(&mut accounts_iter)?;
+    if !matcher.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    let config_account = next_account_info(&mut accounts_iter)?;
+    load_config(program_id, config_account)?;
+
+    let now = Clock::get()?.unix_timestamp;
+    let first = MatchSide::load(program_id, &mut accounts_iter, now)?;
+    let second = MatchSide::load(program_id, &mut accounts_iter, now)?;
+
+    //
+    // Check the two offers can trade with each other
+    //
+    if first.escrow_account.key == second.escrow_account.key {
+        return Err(Error::DoesntMatchEscrow.into());
+    }
+    if first.escrow_info.sell_mint != second.escrow_info.buy_mint
+        || first.escrow_info.buy_mint != second.escrow_info.sell_mint
+    {
+        return Err(Error::MintMismatch.into());
+    }
+    if first.asks_native() || second.asks_native() {
+        return Err(Error::CannotMatchNative.into());
+    }
+    if !first.allows(second.poster) || !second.allows(first.poster) {
+        return Err(Error::NotAllowedTaker.into());
+    }
+
+    let fill = match_fill(
+        first.remaining_amount,
+        first.escrow_info.current_buy_amount(now),
+        second.remaining_amount,
+        second.escrow_info.current_buy_amount(now),
+    )
+    .ok_or(Error::OffersDontCross)?;
+    let (filled, mut other) = if fill.second_filled {
+        (second, first)
+    } else {
+        (first, second)
+    };
+
+    //
+    // Pay the fully filled offer's poster what they asked for, out of the other escrow, less the fee
+    //
+    msg!("Sending {} to the filled offer's poster", fill.amount);
+    let fee = token_fee(fill.amount, filled.escrow_info.fee_bps);
+    other.transfer(filled.poster_buy_account, fill.amount - fee)?;
+    other.pay_fee(program_id, fee)?;
+
+    //
+    // Pay the other offer's poster their price out of the filled escrow, less the fee,
+    // and the matcher whatever is left
+    //
+    msg!("Sending {} to the other offer's poster", fill.price);
+    let fee = token_fee(fill.price, other.escrow_info.fee_bps);
+    filled.transfer(other.poster_buy_account, fill.price - fee)?;
+    filled.pay_fee(program_id, fee)?;
+    if fill.surplus > 0 {
+        msg!("Sending surplus of {} to matcher", fill.surplus);
+        if unpack_token_account(filled.matcher_account)?.mint != filled.escrow_info.sell_mint {
+            return Err(Error::MintMismatch.into());
+        }
+        filled.transfer(filled.matcher_account, fill.surplus)?;
+    }
+
+    //
+    // Close the fully filled offer, and the other one too if it is used up
+    //
+    filled.close()?;
+    if fill.amount == other.remaining_amount {
+        other.close()?;
+    } else {
+        record_partial_fill(
+            &mut other.escrow_info,
+            fill.amount,
+            other.remaining_amount,
+            fill.price,
+        )?;
+        other
+            .escrow_info
+            .serialize(&mut *other.escrow_account.try_borrow_mut_data()?)?;
+    }
+
+    Ok(())
+}
+
+/// One of the two escrows `Match` settles against each other, with the accounts it needs
+struct MatchSide<'a, 'b> {
+    escrow_account: &'b AccountInfo<'a>,
+    escrow_info: Escrow,
+    token_account: &'b AccountInfo<'a>,
+    poster: &'b AccountInfo<'a>,
+    poster_buy_account: &'b AccountInfo<'a>,
+    authority: EscrowAuthority<'a, 'b>,
+    sell_mint: &'b AccountInfo<'a>,
+    token_program: &'b AccountInfo<'a>,
+    fee_token_account: &'b AccountInfo<'a>,
+    matcher_account: &'b AccountInfo<'a>,
+    remaining_amount: u64,
+}
+
+impl<'a, 'b> MatchSide<'a, 'b> {
+    fn load<I: Iterator<Item = &'b AccountInfo<'a>>>(
+        program_id: &Pubkey,
+        accounts_iter: &mut I,
+        now: i64,
+    ) -> Result<Self, ProgramError> {
+        let escrow_account = next_account_info(accounts_iter)?;
+        let token_account = next_account_info(accounts_iter)?;
+        let poster = next_account_info(accounts_iter)?;
+        let poster_buy_account = next_account_info(accounts_iter)?;
+        let pda_account = next_account_info(accounts_iter)?;
+        let sell_mint = next_account_info(accounts_iter)?;
+        let token_program = next_account_info(accounts_iter)?;
+        check_token_program(token_program)?;
+        if token_account.owner != token_program.key || sell_mint.owner != token_program.key {
+            return Err(Error::AccountNotToken.into());
+        }
+        let fee_token_account = next_account_info(accounts_iter)?;
+        let matcher_account = next_account_info(accounts_iter)?;
+
+        let escrow_info = load_escrow(program_id, escrow_account)?;
+        if escrow_info.token_account != *token_account.key
+            || escrow_info.poster != *poster.key
+            || escrow_info.poster_buy_account != *poster_buy_account.key
+        {
+            return Err(Error::DoesntMatchEscrow.into());
+        }
+        if escrow_info.sell_mint != *sell_mint.key {
+            return Err(Error::MintMismatch.into());
+        }
+        if escrow_info.is_expired(now) {
+            return Err(Error::OfferExpired.into());
+        }
+        check_takeable(&escrow_info)?;
+        let authority = EscrowAuthority::new(
+            program_id,
+            pda_account,
+            escrow_account.key,
+            &escrow_info.poster,
+            escrow_info.authority_bump,
+        )?;
+        let remaining_amount = remaining_amount(
+            &escrow_info,
+            &unpack_token_account(token_account)?,
+            pda_account.key,
+        );
+
+        Ok(MatchSide {
+            escrow_account,
+            escrow_info,
+            token_account,
+            poster,
+            poster_buy_account,
+            authority,
+            sell_mint,
+            token_program,
+            fee_token_account,
+            matcher_account,
+            remaining_amount,
+        })
+    }
+
+    fn asks_native(&self) -> bool {
+        self.escrow_info.poster_buy_account == self.escrow_info.poster
+    }
+
+    fn allows(&self, taker: &AccountInfo) -> bool {
+        !matches!(self.escrow_info.allowed_taker, Some(allowed_taker) if allowed_taker != *taker.key)
+    }
+
+    fn transfer(&self, destination: &AccountInfo<'a>, amount: u64) -> ProgramResult {
+        transfer_from_pda(
+            self.token_program,
+            self.token_account,
+            self.sell_mint,
+            destination,
+            &self.authority,
+            amount,
+        )
+    }
+
+    fn pay_fee(&self, program_id: &Pubkey, fee: u64) -> ProgramResult {
+        if fee == 0 {
+            return Ok(());
+        }
+        let fee_token_info = unpack_token_account(self.fee_token_account)?;
+        if fee_token_info.owner != find_fee_authority(program_id).0
+            || fee_token_info.mint != self.escrow_info.sell_mint
+        {
+            return Err(Error::IncorrectFeeAccount.into());
+        }
+        self.transfer(self.fee_token_account, fee)
+    }
+
+    fn close(&self) -> ProgramResult {
+        if self.escrow_info.custody == Custody::Vault {
+            msg!("Closing the vault");
+            close_vault(
+                self.token_program,
+                self.token_account,
+                self.sell_mint,
+                self.poster,
+                &self.authority,
+            )?;
+        }
+        close_escrow(self.escrow_account, self.poster)
+    }
+}
+
+fn process_create_market(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    msg!("Instruction: CreateMarket");
+
+    //
+    // deserialize accounts info
+    //
+    let mut accounts_iter = accounts.iter();
+
+    let payer = next_account_info(&mut accounts_iter)?;
+    if !payer.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    let market_account = next_account_info(&mut accounts_iter)?;
+    let base_mint = next_account_info(&mut accounts_iter)?;
+    let quote_mint = next_account_info(&mut accounts_iter)?;
+    let base_vault = next_account_info(&mut accounts_iter)?;
+    let quote_vault = next_account_info(&mut accounts_iter)?;
+    let base_token_program = next_account_info(&mut accounts_iter)?;
+    let quote_token_program = next_account_info(&mut accounts_iter)?;
+    check_token_program(base_token_program)?;
+    check_token_program(quote_token_program)?;
+    if base_mint.owner != base_token_program.key || quote_mint.owner != quote_token_program.key {
+        return Err(Error::AccountNotToken.into());
+    }
+    if base_mint.key == quote_mint.key {
+        return Err(Error::MintMismatch.into());
+    }
+    let system_program = next_account_info(&mut accounts_iter)?;
+
+    let (market, bump_seed) = find_market_address(base_mint.key, quote_mint.key, program_id);
+    if *market_account.key != market {
+        return Err(Error::IncorrectPDA.into());
+    }
+    let (base_vault_address, base_vault_bump) =
+        find_market_vault(&market, base_mint.key, program_id);
+    let (quote_vault_address, quote_vault_bump) =
+        find_market_vault(&market, quote_mint.key, program_id);
+    if *base_vault.key != base_vault_address || *quote_vault.key != quote_vault_address {
+        return Err(Error::IncorrectPDA.into());
+    }
+
+    //
+    // create market account
+    //
+    msg!("Creating market account");
+    create_pda_account(
+        payer,
+        market_account,
+        Market::LEN,
+        program_id,
+        system_program,
+        &[
+            MARKET_SEED,
+            base_mint.key.as_ref(),
+            quote_mint.key.as_ref(),
+            &[bump_seed],
+        ],
+    )?;
+
+    //
+    // create the vaults, owned by the market account
+    //
+    msg!("Creating vault token accounts");
+    create_vault(
+        payer,
+        base_vault,
+        base_mint,
+        base_token_program,
+        system_program,
+        &market,
+        &[
+            VAULT_SEED,
+            market.as_ref(),
+            base_mint.key.as_ref(),
+            &[base_vault_bump],
+        ],
+    )?;
+    create_vault(
+        payer,
+        quote_vault,
+        quote_mint,
+        quote_token_program,
+        system_program,
+        &market,
+        &[
+            VAULT_SEED,
+            market.as_ref(),
+            quote_mint.key.as_ref(),
+            &[quote_vault_bump],
+        ],
+    )?;
+
+    //
+    // set market info
+    //
+    let market_info = Market {
+        is_initialized: true,
+        base_mint: *base_mint.key,
+        quote_mint: *quote_mint.key,
+        bump: bump_seed,
+        base_vault: base_vault_address,
+        quote_vault: quote_vault_address,
+        next_order_id: 0,
+        bids: Vec::new(),
+        asks: Vec::new(),
+    };
+    market_info.serialize(&mut *market_account.try_borrow_mut_data()?)?;
+
+    Ok(())
+}
+
+fn process_place_order(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    side: Side,
+    base_amount: u64,
+    quote_amount: u64,
+) -> ProgramResult {
+    msg!("Instruction: PlaceOrder");
+
+    if base_amount == 0 || quote_amount == 0 {
+        return Err(Error::InvalidOrder.into());
+    }
+
+    //
+    // deserialize accounts info
+    //
+    let mut accounts_iter = accounts.iter();
+
+    let owner = next_account_info(&mut accounts_iter)?;
+    if !owner.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    let market_account = next_account_info(&mut accounts_iter)?;
+    let source_account = next_account_info(&mut accounts_iter)?;
+    let vault_account = next_account_info(&mut accounts_iter)?;
+    let mint = next_account_info(&mut accounts_iter)?;
+    let token_program = next_account_info(&mut accounts_iter)?;
+    check_token_program(token_program)?;
+    if mint.owner != token_program.key {
+        return Err(Error::AccountNotToken.into());
+    }
+    let evicted_claim_vault = next_account_info(&mut accounts_iter)?;
+    let system_program = next_account_info(&mut accounts_iter)?;
+    let config_account = next_account_info(&mut accounts_iter)?;
+    let config = load_config(program_id, config_account)?;
+
+    let mut market = load_market(program_id, market_account)?;
+    let (vault, amount) = match side {
+        Side::Ask => (market.base_vault, base_amount),
+        Side::Bid => (market.quote_vault, quote_amount),
+    };
+    if *vault_account.key != vault {
+        return Err(Error::DoesntMatchEscrow.into());
+    }
+    if unpack_token_account(vault_account)?.mint != *mint.key
+        || unpack_token_account(source_account)?.mint != *mint.key
+    {
+        return Err(Error::MintMismatch.into());
+    }
 
     //
-    // Deserialize escrow account info
+    // add the order to the book, taking out the worst priced order if the book is full
     //
-    msg!("Deserializing escrow info");
-    let escrow_info = Escrow::deserialize(&mut escrow.try_borrow_data()?.as_ref())?;
-    if escrow_info.token_account != *token_account.key {
+    let order = Order {
+        id: market.next_order_id,
+        owner: *owner.key,
+        base_amount,
+        quote_amount,
+        fee_bps: config.fee_bps,
+    };
+    let evicted = market.make_room(side, &order);
+    if !market.insert(side, order) {
+        return Err(Error::OrderBookFull.into());
+    }
+    market.next_order_id += 1;
+    market.serialize(&mut *market_account.try_borrow_mut_data()?)?;
+    msg!("Placed order {}", order.id);
+
+    //
+    // refund the order taken out into its owner's claim vault
+    //
+    if let Some(evicted) = evicted {
+        let evicted_claim = ClaimVault::new(
+            program_id,
+            evicted_claim_vault,
+            market_account.key,
+            &evicted.owner,
+            mint.key,
+        )?;
+        evicted_claim.open(owner, mint, token_program, system_program)?;
+        let refund = match side {
+            Side::Ask => evicted.base_amount,
+            Side::Bid => evicted.quote_amount,
+        };
+        msg!("Refunding order {} into its claim vault", evicted.id);
+        transfer_tokens(
+            token_program,
+            vault_account,
+            mint,
+            evicted_claim_vault,
+            market_account,
+            refund,
+            &[&market_seeds(&market, &[market.bump])],
+        )?;
+    }
+
+    //
+    // move what the order pays with into the vault, the owner paying any transfer fee on top
+    //
+    let gross_amount = {
+        let mint_data = mint.try_borrow_data()?;
+        amount_with_transfer_fee(&mint_data, Clock::get()?.epoch, amount)?
+    };
+    msg!("Transferring tokens into the vault");
+    transfer_tokens(
+        token_program,
+        source_account,
+        mint,
+        vault_account,
+        owner,
+        gross_amount,
+        &[],
+    )
+}
+
+fn process_cancel_order(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    side: Side,
+    order_id: u64,
+) -> ProgramResult {
+    msg!("Instruction: CancelOrder");
+
+    //
+    // deserialize accounts info
+    //
+    let mut accounts_iter = accounts.iter();
+
+    let owner = next_account_info(&mut accounts_iter)?;
+    if !owner.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    let market_account = next_account_info(&mut accounts_iter)?;
+    let vault_account = next_account_info(&mut accounts_iter)?;
+    let refund_account = next_account_info(&mut accounts_iter)?;
+    let mint = next_account_info(&mut accounts_iter)?;
+    let token_program = next_account_info(&mut accounts_iter)?;
+    check_token_program(token_program)?;
+
+    let mut market = load_market(program_id, market_account)?;
+    let orders = market.orders_mut(side);
+    let position = orders
+        .iter()
+        .position(|order| order.id == order_id)
+        .ok_or(Error::OrderNotFound)?;
+    let order = orders[position];
+    if order.owner != *owner.key {
         return Err(Error::DoesntMatchEscrow.into());
     }
-    if escrow_info.poster != *poster.key {
+    let (vault, refund_mint, amount) = match side {
+        Side::Ask => (market.base_vault, market.base_mint, order.base_amount),
+        Side::Bid => (market.quote_vault, market.quote_mint, order.quote_amount),
+    };
+    if *vault_account.key != vault {
         return Err(Error::DoesntMatchEscrow.into());
     }
+    if *mint.key != refund_mint {
+        return Err(Error::MintMismatch.into());
+    }
 
     //
-    // Transfer authority of tokens account back to poster
+    // take the order out of the book and refund what is left of it
     //
-    msg!("Calling the token program to transfer token account ownership...");
-    transfer_and_close(
-        program_id,
+    market.orders_mut(side).remove(position);
+    market.serialize(&mut *market_account.try_borrow_mut_data()?)?;
+
+    msg!("Refunding order {}", order_id);
+    transfer_tokens(
         token_program,
-        token_account,
+        vault_account,
+        mint,
         refund_account,
-        poster,
-        pda_account,
-        token_info.amount,
-    )?;
+        market_account,
+        amount,
+        &[&market_seeds(&market, &[market.bump])],
+    )
+}
+
+fn process_match_orders(program_id: &Pubkey, accounts: &[AccountInfo], limit: u8) -> ProgramResult {
+    msg!("Instruction: MatchOrders");
 
     //
-    // Close escrow account
+    // deserialize accounts info
     //
-    close_escrow(escrow, poster)?;
+    let mut accounts_iter = accounts.iter();
+
+    let matcher = next_account_info(&mut accounts_iter)?;
+    if !matcher.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    let market_account = next_account_info(&mut accounts_iter)?;
+    let base_vault = next_account_info(&mut accounts_iter)?;
+    let quote_vault = next_account_info(&mut accounts_iter)?;
+    let base_mint = next_account_info(&mut accounts_iter)?;
+    let quote_mint = next_account_info(&mut accounts_iter)?;
+    let base_token_program = next_account_info(&mut accounts_iter)?;
+    let quote_token_program = next_account_info(&mut accounts_iter)?;
+    check_token_program(base_token_program)?;
+    check_token_program(quote_token_program)?;
+    let config_account = next_account_info(&mut accounts_iter)?;
+    load_config(program_id, config_account)?;
+    let fee_token_account = next_account_info(&mut accounts_iter)?;
+    let system_program = next_account_info(&mut accounts_iter)?;
+
+    let mut market = load_market(program_id, market_account)?;
+    if *base_vault.key != market.base_vault || *quote_vault.key != market.quote_vault {
+        return Err(Error::DoesntMatchEscrow.into());
+    }
+    if *base_mint.key != market.base_mint || *quote_mint.key != market.quote_mint {
+        return Err(Error::MintMismatch.into());
+    }
+
+    //
+    // fill crossing orders and record what is left of the book
+    //
+    let fills = match_orders(&mut market, limit as usize);
+    if fills.is_empty() {
+        return Err(Error::OffersDontCross.into());
+    }
+    if fills.iter().any(|fill| fill.ask_fee_bps > 0) {
+        let fee_token_info = unpack_token_account(fee_token_account)?;
+        if fee_token_info.owner != find_fee_authority(program_id).0
+            || fee_token_info.mint != market.quote_mint
+        {
+            return Err(Error::IncorrectFeeAccount.into());
+        }
+    }
+    market.serialize(&mut *market_account.try_borrow_mut_data()?)?;
+
+    //
+    // pay out every fill from the vaults into the claim vaults of the orders' owners
+    //
+    let bump = [market.bump];
+    let seeds = market_seeds(&market, &bump);
+    for fill in fills {
+        let ask_claim_vault = next_account_info(&mut accounts_iter)?;
+        let bid_base_claim_vault = next_account_info(&mut accounts_iter)?;
+        let bid_quote_claim_vault = next_account_info(&mut accounts_iter)?;
+        ClaimVault::new(
+            program_id,
+            ask_claim_vault,
+            market_account.key,
+            &fill.ask_owner,
+            quote_mint.key,
+        )?
+        .open(matcher, quote_mint, quote_token_program, system_program)?;
+        ClaimVault::new(
+            program_id,
+            bid_base_claim_vault,
+            market_account.key,
+            &fill.bid_owner,
+            base_mint.key,
+        )?
+        .open(matcher, base_mint, base_token_program, system_program)?;
+
+        msg!(
+            "Filling {} base tokens for {} quote tokens",
+            fill.base_amount,
+            fill.quote_amount
+        );
+        transfer_tokens(
+            base_token_program,
+            base_vault,
+            base_mint,
+            bid_base_claim_vault,
+            market_account,
+            fill.base_amount,
+            &[&seeds],
+        )?;
+        let fee = token_fee(fill.quote_amount, fill.ask_fee_bps);
+        transfer_tokens(
+            quote_token_program,
+            quote_vault,
+            quote_mint,
+            ask_claim_vault,
+            market_account,
+            fill.quote_amount - fee,
+            &[&seeds],
+        )?;
+        if fee > 0 {
+            transfer_tokens(
+                quote_token_program,
+                quote_vault,
+                quote_mint,
+                fee_token_account,
+                market_account,
+                fee,
+                &[&seeds],
+            )?;
+        }
+        if fill.bid_refund > 0 {
+            ClaimVault::new(
+                program_id,
+                bid_quote_claim_vault,
+                market_account.key,
+                &fill.bid_owner,
+                quote_mint.key,
+            )?
+            .open(matcher, quote_mint, quote_token_program, system_program)?;
+            msg!("Refunding {} quote tokens to the bid", fill.bid_refund);
+            transfer_tokens(
+                quote_token_program,
+                quote_vault,
+                quote_mint,
+                bid_quote_claim_vault,
+                market_account,
+                fill.bid_refund,
+                &[&seeds],
+            )?;
+        }
+    }
 
     Ok(())
 }
 
-fn transfer_and_close<'a>(
+fn load_market(program_id: &Pubkey, market_account: &AccountInfo) -> Result<Market, ProgramError> {
+    if market_account.owner != program_id || market_account.data_len() != Market::LEN {
+        return Err(Error::DoesntMatchEscrow.into());
+    }
+    let market = Market::deserialize(&mut market_account.try_borrow_data()?.as_ref())?;
+    if !market.is_initialized {
+        return Err(ProgramError::UninitializedAccount);
+    }
+    Ok(market)
+}
+
+/// Seeds the market account signs for its vaults with
+fn market_seeds<'a>(market: &'a Market, bump: &'a [u8]) -> [&'a [u8]; 4] {
+    [
+        MARKET_SEED,
+        market.base_mint.as_ref(),
+        market.quote_mint.as_ref(),
+        bump,
+    ]
+}
+
+fn load_config(program_id: &Pubkey, config_account: &AccountInfo) -> Result<Config, ProgramError> {
+    if *config_account.key != find_config_address(program_id).0
+        || config_account.owner != program_id
+    {
+        return Err(Error::IncorrectPDA.into());
+    }
+    let config = Config::deserialize(&mut config_account.try_borrow_data()?.as_ref())?;
+    if !config.is_initialized {
+        return Err(ProgramError::UninitializedAccount);
+    }
+    Ok(config)
+}
+
+/// Bundles are told apart from escrows at the same kind of address by their account size
+/// Reads an escrow account owned by the program. Bundles live at the same kind of address, but are a
+/// different size, so are rejected too
+fn load_escrow(program_id: &Pubkey, escrow_account: &AccountInfo) -> Result<Escrow, ProgramError> {
+    if escrow_account.owner != program_id || escrow_account.data_len() != get_packed_len::<Escrow>()
+    {
+        return Err(Error::DoesntMatchEscrow.into());
+    }
+    let escrow = Escrow::deserialize(&mut escrow_account.try_borrow_data()?.as_ref())?;
+    if !escrow.is_initialized {
+        return Err(ProgramError::UninitializedAccount);
+    }
+    Ok(escrow)
+}
+
+fn load_bundle(program_id: &Pubkey, bundle_account: &AccountInfo) -> Result<Bundle, ProgramError> {
+    if bundle_account.owner != program_id || bundle_account.data_len() != Bundle::LEN {
+        return Err(Error::DoesntMatchEscrow.into());
+    }
+    let bundle = Bundle::deserialize(&mut bundle_account.try_borrow_data()?.as_ref())?;
+    if !bundle.is_initialized {
+        return Err(ProgramError::UninitializedAccount);
+    }
+    Ok(bundle)
+}
+
+/// Fails for offers that are sold by auction, as an option or as a quote, instead of being taken
+fn check_takeable(escrow_info: &Escrow) -> ProgramResult {
+    if escrow_info.english_auction.is_some() {
+        return Err(Error::IsEnglishAuction.into());
+    }
+    if escrow_info.sealed_auction.is_some() {
+        return Err(Error::IsSealedAuction.into());
+    }
+    if escrow_info.call_option.is_some() {
+        return Err(Error::IsCallOption.into());
+    }
+    if escrow_info.rfq.is_some() {
+        return Err(Error::IsQuote.into());
+    }
+    Ok(())
+}
+
+/// Checks `fee_token_account` is the fee PDA's token account for `mint`
+fn check_fee_token_account(
     program_id: &Pubkey,
+    fee_token_account: &AccountInfo,
+    mint: &Pubkey,
+) -> ProgramResult {
+    let fee_token_info = unpack_token_account(fee_token_account)?;
+    if fee_token_info.owner != find_fee_authority(program_id).0 || fee_token_info.mint != *mint {
+        return Err(Error::IncorrectFeeAccount.into());
+    }
+    Ok(())
+}
+
+/// Only the original token program and Token-2022 are trusted with the escrowed tokens
+fn is_token_program(program_id: &Pubkey) -> bool {
+    *program_id == spl_token::id() || *program_id == spl_token_2022::id()
+}
+
+fn check_token_program(token_program: &AccountInfo) -> ProgramResult {
+    if !is_token_program(token_program.key) {
+        return Err(Error::AccountNotToken.into());
+    }
+    Ok(())
+}
+
+/// Reads a token account of either token program, ignoring any Token-2022 extensions
+fn unpack_token_account(account: &AccountInfo) -> Result<TokenAccount, ProgramError> {
+    Ok(StateWithExtensions::<TokenAccount>::unpack(&account.try_borrow_data()?)?.base)
+}
+
+/// The PDA with authority over a single escrow's token account
+struct EscrowAuthority<'a, 'b> {
+    account: &'b AccountInfo<'a>,
+    escrow: Pubkey,
+    poster: Pubkey,
+    bump: [u8; 1],
+}
+
+impl<'a, 'b> EscrowAuthority<'a, 'b> {
+    fn new(
+        program_id: &Pubkey,
+        account: &'b AccountInfo<'a>,
+        escrow: &Pubkey,
+        poster: &Pubkey,
+        bump: u8,
+    ) -> Result<Self, ProgramError> {
+        let authority = EscrowAuthority {
+            account,
+            escrow: *escrow,
+            poster: *poster,
+            bump: [bump],
+        };
+        let pda = Pubkey::create_program_address(&authority.seeds(), program_id)
+            .map_err(|_| Error::IncorrectPDA)?;
+        if *account.key != pda {
+            return Err(Error::IncorrectPDA.into());
+        }
+        Ok(authority)
+    }
+
+    fn seeds(&self) -> [&[u8]; 4] {
+        [
+            ESCROW_SEED,
+            self.escrow.as_ref(),
+            self.poster.as_ref(),
+            &self.bump,
+        ]
+    }
+}
+
+/// A token account holding what an auction or a market owes `owner` in one mint, until they withdraw it with
+/// `WithdrawClaim`. It is owned by a PDA, so the auction can always pay into it whatever `owner` does
+struct ClaimVault<'a, 'b> {
+    account: &'b AccountInfo<'a>,
+    authority: Pubkey,
+    escrow: Pubkey,
+    owner: Pubkey,
+    mint: Pubkey,
+    bump: [u8; 1],
+}
+
+impl<'a, 'b> ClaimVault<'a, 'b> {
+    fn new(
+        program_id: &Pubkey,
+        account: &'b AccountInfo<'a>,
+        escrow: &Pubkey,
+        owner: &Pubkey,
+        mint: &Pubkey,
+    ) -> Result<Self, ProgramError> {
+        let (address, bump_seed) = find_claim_vault(escrow, owner, mint, program_id);
+        if *account.key != address {
+            return Err(Error::IncorrectPDA.into());
+        }
+        Ok(ClaimVault {
+            account,
+            authority: find_claim_authority(owner, program_id).0,
+            escrow: *escrow,
+            owner: *owner,
+            mint: *mint,
+            bump: [bump_seed],
+        })
+    }
+
+    fn seeds(&self) -> [&[u8]; 5] {
+        [
+            CLAIM_SEED,
+            self.escrow.as_ref(),
+            self.owner.as_ref(),
+            self.mint.as_ref(),
+            &self.bump,
+        ]
+    }
+
+    /// Creates the claim vault at `payer`'s expense, unless it already exists
+    fn open(
+        &self,
+        payer: &AccountInfo<'a>,
+        mint: &AccountInfo<'a>,
+        token_program: &AccountInfo<'a>,
+        system_program: &AccountInfo<'a>,
+    ) -> ProgramResult {
+        if !self.account.data_is_empty() {
+            return Ok(());
+        }
+        msg!("Creating claim vault token account");
+        create_vault(
+            payer,
+            self.account,
+            mint,
+            token_program,
+            system_program,
+            &self.authority,
+            &self.seeds(),
+        )
+    }
+}
+
+fn transfer_and_close<'a>(
     token_program: &AccountInfo<'a>,
     source_account: &AccountInfo<'a>,
+    mint: &AccountInfo<'a>,
     destination_account: &AccountInfo<'a>,
     poster: &AccountInfo<'a>,
-    pda_account: &AccountInfo<'a>,
+    authority: &EscrowAuthority<'a, '_>,
     amount: u64,
 ) -> ProgramResult {
-    let (pda, bump_seed) = Pubkey::find_program_address(&[ESCROW_SEED], program_id);
-    if *pda_account.owner != pda {
-        return Err(Error::IncorrectPDA.into());
+    transfer_from_pda(
+        token_program,
+        source_account,
+        mint,
+        destination_account,
+        authority,
+        amount,
+    )?;
+    close_vault(token_program, source_account, mint, poster, authority)
+}
+
+/// Closes the escrow's vault, sending its rent, and any wrapped SOL left in it, to `destination_account`
+fn close_vault<'a>(
+    token_program: &AccountInfo<'a>,
+    vault_account: &AccountInfo<'a>,
+    mint: &AccountInfo<'a>,
+    destination_account: &AccountInfo<'a>,
+    authority: &EscrowAuthority<'a, '_>,
+) -> ProgramResult {
+    close_token_account(
+        token_program,
+        vault_account,
+        mint,
+        destination_account,
+        authority.account,
+        &[&authority.seeds()],
+    )
+}
+
+/// Closes a token account owned by a PDA, signed by `authority` with `signer_seeds`.
+/// Transfer fees withheld in a Token-2022 account are harvested to the mint first, as they would stop it closing
+fn close_token_account<'a>(
+    token_program: &AccountInfo<'a>,
+    vault_account: &AccountInfo<'a>,
+    mint: &AccountInfo<'a>,
+    destination_account: &AccountInfo<'a>,
+    authority: &AccountInfo<'a>,
+    signer_seeds: &[&[&[u8]]],
+) -> ProgramResult {
+    let withheld_amount =
+        StateWithExtensions::<TokenAccount>::unpack(&vault_account.try_borrow_data()?)?
+            .get_extension::<TransferFeeAmount>()
+            .map_or(0, |extension| u64::from(extension.withheld_amount));
+    if withheld_amount > 0 {
+        msg!("Harvesting withheld transfer fees to the mint");
+        invoke(
+            &spl_token_2022::extension::transfer_fee::instruction::harvest_withheld_tokens_to_mint(
+                token_program.key,
+                mint.key,
+                &[vault_account.key],
+            )?,
+            &[mint.clone(), vault_account.clone(), token_program.clone()],
+        )?;
     }
     invoke_signed(
-        &spl_token::instruction::transfer(
+        &spl_token_2022::instruction::close_account(
             token_program.key,
-            source_account.key,
+            vault_account.key,
             destination_account.key,
-            &pda,
-            &[&pda],
-            amount,
+            authority.key,
+            &[authority.key],
         )?,
         &[
-            source_account.clone(),
+            vault_account.clone(),
             destination_account.clone(),
-            pda_account.clone(),
+            authority.clone(),
             token_program.clone(),
         ],
-        &[&[ESCROW_SEED, &[bump_seed]]],
+        signer_seeds,
     )?;
-    invoke_signed(
+    Ok(())
+}
+
+/// Moves `amount` lamports from `payer` into the wrapped SOL `token_account`
+fn wrap_native<'a>(
+    token_program: &AccountInfo<'a>,
+    system_program: &AccountInfo<'a>,
+    payer: &AccountInfo<'a>,
+    token_account: &AccountInfo<'a>,
+    amount: u64,
+) -> ProgramResult {
+    invoke(
+        &solana_program::system_instruction::transfer(payer.key, token_account.key, amount),
+        &[payer.clone(), token_account.clone(), system_program.clone()],
+    )?;
+    invoke(
+        &spl_token::instruction::sync_native(token_program.key, token_account.key)?,
+        &[token_account.clone(), token_program.clone()],
+    )
+}
+
+/// Creates the temporary wrapped SOL account, owned by `recipient`, that native SOL is paid out of the escrow into.
+/// The recipient pays its rent, and gets it back along with the SOL when it is closed by `close_unwrap_account`
+fn create_unwrap_account<'a>(
+    program_id: &Pubkey,
+    escrow: &Pubkey,
+    recipient: &AccountInfo<'a>,
+    unwrap_account: &AccountInfo<'a>,
+    native_mint: &AccountInfo<'a>,
+    system_program: &AccountInfo<'a>,
+    token_program: &AccountInfo<'a>,
+) -> ProgramResult {
+    if *native_mint.key != spl_token::native_mint::id() {
+        return Err(Error::MintMismatch.into());
+    }
+    let (address, bump_seed) = find_unwrap_account(escrow, recipient.key, program_id);
+    if *unwrap_account.key != address {
+        return Err(Error::IncorrectPDA.into());
+    }
+
+    let space = spl_token::state::Account::LEN;
+    create_pda_account(
+        recipient,
+        unwrap_account,
+        space,
+        token_program.key,
+        system_program,
+        &[
+            UNWRAP_SEED,
+            escrow.as_ref(),
+            recipient.key.as_ref(),
+            &[bump_seed],
+        ],
+    )?;
+    invoke(
+        &spl_token::instruction::initialize_account3(
+            token_program.key,
+            unwrap_account.key,
+            native_mint.key,
+            recipient.key,
+        )?,
+        &[
+            unwrap_account.clone(),
+            native_mint.clone(),
+            token_program.clone(),
+        ],
+    )
+}
+
+fn close_unwrap_account<'a>(
+    token_program: &AccountInfo<'a>,
+    unwrap_account: &AccountInfo<'a>,
+    recipient: &AccountInfo<'a>,
+) -> ProgramResult {
+    invoke(
         &spl_token::instruction::close_account(
+            token_program.key,
+            unwrap_account.key,
+            recipient.key,
+            recipient.key,
+            &[recipient.key],
+        )?,
+        &[
+            unwrap_account.clone(),
+            recipient.clone(),
+            token_program.clone(),
+        ],
+    )
+}
+
+fn transfer_from_pda<'a>(
+    token_program: &AccountInfo<'a>,
+    source_account: &AccountInfo<'a>,
+    mint: &AccountInfo<'a>,
+    destination_account: &AccountInfo<'a>,
+    authority: &EscrowAuthority<'a, '_>,
+    amount: u64,
+) -> ProgramResult {
+    transfer_tokens(
+        token_program,
+        source_account,
+        mint,
+        destination_account,
+        authority.account,
+        amount,
+        &[&authority.seeds()],
+    )
+}
+
+/// Transfers `amount` from an account of the signing `owner`, who pays any transfer fee on top so that
+/// `destination_account` receives all of it
+fn transfer_fee_on_top<'a>(
+    token_program: &AccountInfo<'a>,
+    source_account: &AccountInfo<'a>,
+    mint: &AccountInfo<'a>,
+    destination_account: &AccountInfo<'a>,
+    owner: &AccountInfo<'a>,
+    amount: u64,
+) -> ProgramResult {
+    let gross_amount = {
+        let mint_data = mint.try_borrow_data()?;
+        amount_with_transfer_fee(&mint_data, Clock::get()?.epoch, amount)?
+    };
+    transfer_tokens(
+        token_program,
+        source_account,
+        mint,
+        destination_account,
+        owner,
+        gross_amount,
+        &[],
+    )
+}
+
+/// Checked transfer of either token program, signed by `authority` or by the PDA of `signer_seeds`
+fn transfer_tokens<'a>(
+    token_program: &AccountInfo<'a>,
+    source_account: &AccountInfo<'a>,
+    mint: &AccountInfo<'a>,
+    destination_account: &AccountInfo<'a>,
+    authority: &AccountInfo<'a>,
+    amount: u64,
+    signer_seeds: &[&[&[u8]]],
+) -> ProgramResult {
+    let decimals = StateWithExtensions::<Mint>::unpack(&mint.try_borrow_data()?)?
+        .base
+        .decimals;
+    invoke_signed(
+        &spl_token_2022::instruction::transfer_checked(
             token_program.key,
             source_account.key,
-            poster.key,
-            &pda,
-            &[&pda],
+            mint.key,
+            destination_account.key,
+            authority.key,
+            &[authority.key],
+            amount,
+            decimals,
         )?,
         &[
             source_account.clone(),
-            poster.clone(),
-            pda_account.clone(),
+            mint.clone(),
+            destination_account.clone(),
+            authority.clone(),
             token_program.clone(),
         ],
-        &[&[ESCROW_SEED, &[bump_seed]]],
-    )?;
-    Ok(())
+        signer_seeds,
+    )
 }
 
 fn close_escrow(escrow: &AccountInfo, poster: &AccountInfo) -> ProgramResult {
     msg!("Closing escrow account");
-    **poster.lamports.borrow_mut() = poster
+    close_account(escrow, poster)
+}
+
+/// Closes an account owned by the program, sending its rent to `destination`
+fn close_account(account: &AccountInfo, destination: &AccountInfo) -> ProgramResult {
+    **destination.lamports.borrow_mut() = destination
         .lamports()
-        .checked_add(escrow.lamports())
+        .checked_add(account.lamports())
         .ok_or(Error::AmountOverflow)?;
-    **escrow.lamports.borrow_mut() = 0;
-    *escrow.try_borrow_mut_data()? = &mut [];
+    **account.lamports.borrow_mut() = 0;
+    *account.try_borrow_mut_data()? = &mut [];
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn order(id: u64, base_amount: u64, quote_amount: u64) -> Order {
+        Order {
+            id,
+            owner: Pubkey::new_unique(),
+            base_amount,
+            quote_amount,
+            fee_bps: 0,
+        }
+    }
+
+    fn market(bids: Vec<Order>, asks: Vec<Order>) -> Market {
+        Market {
+            is_initialized: true,
+            base_mint: Pubkey::new_unique(),
+            quote_mint: Pubkey::new_unique(),
+            bump: 0,
+            base_vault: Pubkey::new_unique(),
+            quote_vault: Pubkey::new_unique(),
+            next_order_id: 10,
+            bids,
+            asks,
+        }
+    }
+
+    fn transfer_fee(basis_points: u16, maximum_fee: u64) -> TransferFee {
+        TransferFee {
+            epoch: 0.into(),
+            maximum_fee: maximum_fee.into(),
+            transfer_fee_basis_points: basis_points.into(),
+        }
+    }
+
+    #[test]
+    fn fill_price_rounds_up_for_the_poster() {
+        assert_eq!(fill_price(1, 3, 10), Some(4));
+        assert_eq!(fill_price(2, 3, 10), Some(7));
+        assert_eq!(fill_price(3, 3, 10), Some(10));
+        assert_eq!(fill_price(5, 10, 4), Some(2));
+    }
+
+    #[test]
+    fn fill_price_rejects_empty_and_oversized_fills() {
+        assert_eq!(fill_price(0, 3, 10), None);
+        assert_eq!(fill_price(4, 3, 10), None);
+        assert_eq!(fill_price(u64::MAX, u64::MAX, u64::MAX), Some(u64::MAX));
+    }
+
+    #[test]
+    fn match_fill_fills_the_second_offer_at_the_first_offers_price() {
+        // 100 X for 50 Y against 60 Y for 100 X
+        assert_eq!(
+            match_fill(100, 50, 60, 100),
+            Some(MatchFill {
+                second_filled: true,
+                amount: 100,
+                price: 50,
+                surplus: 10,
+            })
+        );
+    }
+
+    #[test]
+    fn match_fill_fills_the_first_offer_when_the_second_asks_for_more() {
+        // 10 X for 20 Y against 100 Y for 40 X, the first gets its 20 Y for 8 X, rounded up
+        assert_eq!(
+            match_fill(10, 20, 100, 40),
+            Some(MatchFill {
+                second_filled: false,
+                amount: 20,
+                price: 8,
+                surplus: 2,
+            })
+        );
+    }
+
+    #[test]
+    fn match_fill_rejects_offers_that_dont_cross() {
+        assert_eq!(match_fill(100, 50, 40, 100), None);
+    }
+
+    #[test]
+    fn match_fill_rejects_partial_fills_that_leave_an_offer_unpriced() {
+        // filling 5 of 10 X asking 1 Y would take the whole 1 Y, leaving 5 X asking nothing
+        assert_eq!(match_fill(10, 1, 5, 5), None);
+    }
+
+    #[test]
+    fn match_orders_fills_at_the_earlier_orders_price() {
+        let ask = order(0, 10, 20);
+        let bid = order(1, 10, 30);
+        let mut book = market(vec![bid], vec![ask]);
+        assert_eq!(
+            match_orders(&mut book, 10),
+            vec![OrderFill {
+                ask_owner: ask.owner,
+                bid_owner: bid.owner,
+                base_amount: 10,
+                quote_amount: 20,
+                bid_refund: 10,
+                ask_fee_bps: 0,
+            }]
+        );
+        assert!(book.bids.is_empty() && book.asks.is_empty());
+
+        let bid = order(0, 10, 30);
+        let ask = order(1, 10, 20);
+        let mut book = market(vec![bid], vec![ask]);
+        assert_eq!(
+            match_orders(&mut book, 10),
+            vec![OrderFill {
+                ask_owner: ask.owner,
+                bid_owner: bid.owner,
+                base_amount: 10,
+                quote_amount: 30,
+                bid_refund: 0,
+                ask_fee_bps: 0,
+            }]
+        );
+        assert!(book.bids.is_empty() && book.asks.is_empty());
+    }
+
+    #[test]
+    fn match_orders_walks_the_book_up_to_the_limit() {
+        let bid = order(2, 20, 60);
+        let best_ask = order(0, 10, 20);
+        let next_ask = order(1, 10, 25);
+        let mut book = market(vec![bid], vec![best_ask, next_ask]);
+        let fills = match_orders(&mut book, 1);
+        assert_eq!(fills.len(), 1);
+        assert_eq!(fills[0].ask_owner, best_ask.owner);
+        assert_eq!(fills[0].quote_amount, 20);
+        assert_eq!(book.asks, vec![next_ask]);
+        assert_eq!(book.bids[0].base_amount, 10);
+        assert_eq!(book.bids[0].quote_amount, 40);
+
+        let fills = match_orders(&mut book, 10);
+        assert_eq!(fills.len(), 1);
+        assert_eq!(fills[0].ask_owner, next_ask.owner);
+        assert_eq!(fills[0].quote_amount, 25);
+        assert_eq!(fills[0].bid_refund, 15);
+        assert!(book.bids.is_empty() && book.asks.is_empty());
+    }
+
+    #[test]
+    fn match_orders_stops_when_prices_dont_cross() {
+        let mut book = market(vec![order(1, 10, 10)], vec![order(0, 10, 20)]);
+        assert!(match_orders(&mut book, 10).is_empty());
+        assert_eq!(book.bids.len(), 1);
+        assert_eq!(book.asks.len(), 1);
+    }
+
+    #[test]
+    fn match_orders_stops_before_leaving_an_order_unpriced() {
+        // selling 1 of the ask's 3 base tokens would take its whole 1 quote token
+        let mut book = market(vec![order(1, 1, 1)], vec![order(0, 3, 1)]);
+        assert!(match_orders(&mut book, 10).is_empty());
+        assert_eq!(book.asks[0].base_amount, 3);
+    }
+
+    #[test]
+    fn gross_amount_covers_the_transfer_fee() {
+        assert_eq!(gross_amount(&transfer_fee(0, 0), 1_000), Some(1_000));
+        // 1% of 100 is 1, and 99 arrives
+        assert_eq!(gross_amount(&transfer_fee(100, 1_000), 99), Some(100));
+        assert_eq!(gross_amount(&transfer_fee(100, 1_000), 0), Some(0));
+    }
+
+    #[test]
+    fn gross_amount_stops_at_the_maximum_fee() {
+        let fee = transfer_fee(100, 5);
+        assert_eq!(gross_amount(&fee, 10_000), Some(10_005));
+        assert_eq!(gross_amount(&fee, u64::MAX), None);
+    }
+}
@@ -5,7 +5,470 @@ use solana_program::pubkey::Pubkey;
 pub struct Escrow {
     pub is_initialized: bool,
     pub poster: Pubkey,
+    /// Id the poster chose for this offer, the escrow account is derived from it and the poster
+    pub offer_id: u64,
+    /// Incremented every time the poster amends the offer, so takers can tell its terms changed
+    pub version: u64,
     pub token_account: Pubkey,
+    /// Bump seed of the PDA with authority over `token_account`
+    pub authority_bump: u8,
+    /// How the tokens being sold are held in `token_account`
+    pub custody: Custody,
+    /// Mint of the token the poster is selling
+    pub sell_mint: Pubkey,
+    /// Amount of `sell_mint` on offer when the trade was posted or last amended
+    pub sell_amount: u64,
     pub poster_buy_account: Pubkey,
+    /// Mint of the token the poster wants in return
+    pub buy_mint: Pubkey,
     pub buy_amount: u64,
+    /// Basis-point fee taken from what the poster receives, the config's rate when the offer was posted
+    pub fee_bps: u16,
+    /// Unix timestamp after which the offer can no longer be taken
+    pub expires_at: Option<i64>,
+    /// The only account allowed to take the offer, if it is private
+    pub allowed_taker: Option<Pubkey>,
+    /// Falling price asked for the offer, in place of `buy_amount`, if it is a Dutch auction
+    pub dutch_auction: Option<DutchAuction>,
+    /// Bidding on the offer, if it is an English auction. It can't be taken, only bid on and settled
+    pub english_auction: Option<EnglishAuction>,
+    /// Sealed bidding on the offer, if it is a sealed-bid auction. It can't be taken, only bid on and settled
+    pub sealed_auction: Option<SealedAuction>,
+    /// Call option written on the offer, if it is a covered call. It can't be taken, only bought and exercised
+    pub call_option: Option<CallOption>,
+    /// Request for quotes the offer answers, if it is a quote. It can't be taken, only accepted by the requester
+    pub rfq: Option<Pubkey>,
+}
+
+#[derive(BorshSerialize, BorshDeserialize, BorshSchema, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Custody {
+    /// `token_account` is a vault created by the program and owned by the escrow's PDA
+    Vault,
+    /// `token_account` is the poster's own account, with the escrow's PDA approved as its delegate
+    Delegated,
+}
+
+/// An asking price that falls linearly from `start_price` at `start_time` to `end_price` at `end_time`,
+/// and stays there after
+#[derive(BorshSerialize, BorshDeserialize, BorshSchema, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DutchAuction {
+    pub start_price: u64,
+    pub end_price: u64,
+    pub start_time: i64,
+    pub end_time: i64,
+}
+
+impl DutchAuction {
+    /// Price asked at unix time `now`, rounded up in favour of the poster
+    pub fn price(&self, now: i64) -> u64 {
+        if now <= self.start_time {
+            return self.start_price;
+        }
+        if now >= self.end_time {
+            return self.end_price;
+        }
+        let drop = (self.start_price - self.end_price) as u128 * (now - self.start_time) as u128
+            / (self.end_time - self.start_time) as u128;
+        self.start_price - drop as u64
+    }
+}
+
+/// An auction that sells the escrowed tokens to the highest bid at `end_time`
+#[derive(BorshSerialize, BorshDeserialize, BorshSchema, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EnglishAuction {
+    /// Lowest first bid, in token Y
+    pub reserve_price: u64,
+    /// Least each bid has to beat the one before by
+    pub min_increment: u64,
+    /// Unix timestamp bidding closes at, the auction can be settled from then on
+    pub end_time: i64,
+    /// The highest bid so far, held in the escrow's bid vault
+    pub bid: Option<Bid>,
+}
+
+#[derive(BorshSerialize, BorshDeserialize, BorshSchema, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Bid {
+    pub bidder: Pubkey,
+    /// Amount of token Y bid, held in the bid vault
+    pub amount: u64,
+}
+
+impl EnglishAuction {
+    /// Least the next bid can be
+    pub fn min_bid(&self) -> Option<u64> {
+        match self.bid {
+            Some(bid) => bid.amount.checked_add(self.min_increment),
+            None => Some(self.reserve_price),
+        }
+    }
+}
+
+/// An auction bid on with commitments to hidden amounts, each backed by a deposit, which are revealed once
+/// bidding closes. The highest valid reveal wins
+#[derive(BorshSerialize, BorshDeserialize, BorshSchema, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SealedAuction {
+    /// Lowest bid that can win, in token Y
+    pub reserve_price: u64,
+    /// Unix timestamp bidding closes and revealing opens at
+    pub commit_end: i64,
+    /// Unix timestamp revealing closes at, the auction can be settled from then on
+    pub reveal_end: i64,
+    /// What happens to the deposits of bids not revealed in time
+    pub unrevealed: UnrevealedPolicy,
+    /// Sealed bids not yet revealed or released, whose deposits are still in the bid vault
+    pub commitments: u32,
+    /// The highest valid bid revealed so far
+    pub best: Option<Bid>,
+    /// Deposit of the highest bid, the part above its amount is refunded when the auction is settled
+    pub best_deposit: u64,
+}
+
+#[derive(BorshSerialize, BorshDeserialize, BorshSchema, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UnrevealedPolicy {
+    /// Deposits of bids not revealed are returned to their bidders
+    Refund,
+    /// Deposits of bids not revealed are paid into the poster's claim vault
+    Forfeit,
+}
+
+/// A bidder's commitment to a sealed-bid auction, in an account derived from the escrow and the bidder
+#[derive(BorshSerialize, BorshDeserialize, BorshSchema, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SealedBid {
+    pub is_initialized: bool,
+    pub escrow: Pubkey,
+    pub bidder: Pubkey,
+    /// Hash of the bidder, the amount bid and a secret salt, see `sealed_bid_commitment`
+    pub commitment: [u8; 32],
+    /// Amount of token Y deposited in the bid vault, the most the bid can be
+    pub deposit: u64,
+}
+
+/// A covered call on the escrowed tokens: whoever holds the option token can buy them for `buy_amount`, the
+/// strike, until the escrow expires, after which the writer can reclaim them
+#[derive(BorshSerialize, BorshDeserialize, BorshSchema, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CallOption {
+    /// Amount of token Y the buyer of the option pays the writer for it
+    pub premium: u64,
+    /// Mint of the option token, created with the option. The one token is minted to the buyer of the option
+    pub option_mint: Pubkey,
+    /// Whether the option has been bought
+    pub is_sold: bool,
+}
+
+impl SealedAuction {
+    /// Whether any bid has been committed that is still held, sealed or as the highest revealed bid
+    pub fn has_bids(&self) -> bool {
+        self.commitments > 0 || self.best.is_some()
+    }
+}
+
+impl Escrow {
+    pub fn is_expired(&self, now: i64) -> bool {
+        matches!(self.expires_at, Some(expires_at) if now >= expires_at)
+    }
+
+    /// Amount of token Y asked for what is left of the offer at unix time `now`
+    pub fn current_buy_amount(&self, now: i64) -> u64 {
+        match self.dutch_auction {
+            Some(auction) => auction.price(now),
+            None => self.buy_amount,
+        }
+    }
+}
+
+/// Most tokens a bundle can offer, and most it can ask for in return
+pub const MAX_BUNDLE_LEGS: usize = 4;
+
+/// An offer of several tokens at once for several others, taken all together.
+/// Lives at the same address an `Escrow` for the offer id would, and shares its PDA authority seeds
+#[derive(BorshSerialize, BorshDeserialize, BorshSchema)]
+pub struct Bundle {
+    pub is_initialized: bool,
+    pub poster: Pubkey,
+    pub offer_id: u64,
+    /// Bump seed of the PDA with authority over the vaults
+    pub authority_bump: u8,
+    /// Tokens on offer, each held in its own vault
+    pub sell_legs: Vec<Leg>,
+    /// Tokens the poster wants in return, each paid into its own account of the poster's
+    pub buy_legs: Vec<Leg>,
+    /// Basis-point fee taken from what the poster receives, the config's rate when the offer was posted
+    pub fee_bps: u16,
+    /// Unix timestamp after which the offer can no longer be taken
+    pub expires_at: Option<i64>,
+    /// The only account allowed to take the offer, if it is private
+    pub allowed_taker: Option<Pubkey>,
+}
+
+/// One token of a bundle
+#[derive(BorshSerialize, BorshDeserialize, BorshSchema, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Leg {
+    pub mint: Pubkey,
+    pub amount: u64,
+    /// The vault for tokens on offer, the poster's receiving account for tokens asked for
+    pub token_account: Pubkey,
+}
+
+impl Leg {
+    pub const LEN: usize = 32 + 8 + 32;
+}
+
+impl Bundle {
+    /// Space a bundle account is created with, enough for the most legs on both sides
+    pub const LEN: usize =
+        1 + 32 + 8 + 1 + 2 * (4 + MAX_BUNDLE_LEGS * Leg::LEN) + 2 + (1 + 8) + (1 + 32);
+
+    pub fn is_expired(&self, now: i64) -> bool {
+        matches!(self.expires_at, Some(expires_at) if now >= expires_at)
+    }
+}
+
+/// A maker's order, signed off chain and filled in full by a taker with `FillSignedOrder`. The tokens being sold
+/// stay in the maker's account until then, with the PDA derived from `ORDER_SEED` approved as its delegate.
+/// What the maker signs is the order's borsh serialization
+#[derive(BorshSerialize, BorshDeserialize, BorshSchema, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SignedOrder {
+    pub maker: Pubkey,
+    /// Mint of the token the maker is selling
+    pub sell_mint: Pubkey,
+    pub sell_amount: u64,
+    /// Mint of the token the maker wants in return
+    pub buy_mint: Pubkey,
+    pub buy_amount: u64,
+    /// Maker's token account that receives token Y
+    pub maker_buy_account: Pubkey,
+    /// Basis-point fee the maker agrees to be charged on token Y, at least the config's rate when the order is filled
+    pub fee_bps: u16,
+    /// The only account allowed to fill the order, if it is private
+    pub allowed_taker: Option<Pubkey>,
+    /// Unix timestamp after which the order can no longer be filled
+    pub expires_at: i64,
+    /// Number the maker chose for this order. Once an order with it is filled or cancelled, no other order of
+    /// the maker's with the same nonce can be filled
+    pub nonce: u64,
+}
+
+/// Nonces one nonce page records
+pub const NONCES_PER_PAGE: u64 = 2048;
+
+/// Which of a maker's nonces, from `page * NONCES_PER_PAGE` on, have been used by filling or cancelling a signed
+/// order. Lives in a PDA derived from `NONCE_SEED`, the maker and the page
+#[derive(BorshSerialize, BorshDeserialize, BorshSchema)]
+pub struct NoncePage {
+    pub is_initialized: bool,
+    /// One bit per nonce, set once it is used
+    pub used: [u64; NONCES_PER_PAGE as usize / 64],
+}
+
+impl NoncePage {
+    /// Page a nonce is recorded in
+    pub fn page(nonce: u64) -> u64 {
+        nonce / NONCES_PER_PAGE
+    }
+
+    fn bit(nonce: u64) -> (usize, u64) {
+        let index = nonce % NONCES_PER_PAGE;
+        ((index / 64) as usize, 1 << (index % 64))
+    }
+
+    pub fn is_used(&self, nonce: u64) -> bool {
+        let (word, mask) = Self::bit(nonce);
+        self.used[word] & mask != 0
+    }
+
+    pub fn mark_used(&mut self, nonce: u64) {
+        let (word, mask) = Self::bit(nonce);
+        self.used[word] |= mask;
+    }
+}
+
+/// A request for quotes to buy `amount` of `mint`, paid for in `pay_mint`. Makers answer it by posting quotes,
+/// offers reserved for the requester that expire at the deadline, and the requester accepts one with `AcceptQuote`.
+/// Lives in a PDA derived from `RFQ_SEED`, the requester and the request's id
+#[derive(BorshSerialize, BorshDeserialize, BorshSchema)]
+pub struct Rfq {
+    pub is_initialized: bool,
+    pub requester: Pubkey,
+    /// Id the requester chose for this request, the account is derived from it and the requester
+    pub rfq_id: u64,
+    /// Mint of the token the requester wants to buy
+    pub mint: Pubkey,
+    pub amount: u64,
+    /// Mint of the token the requester pays with, the native mint to pay in SOL
+    pub pay_mint: Pubkey,
+    /// Unix timestamp quotes can be posted and accepted until
+    pub deadline: i64,
+}
+
+/// Program wide settings, stored in a PDA derived from `CONFIG_SEED`
+#[derive(BorshSerialize, BorshDeserialize, BorshSchema)]
+pub struct Config {
+    pub is_initialized: bool,
+    /// The only account allowed to update the config
+    pub admin: Pubkey,
+    /// Lamports charged to post a trade
+    pub fee: u64,
+    /// Account the fee is paid into
+    pub fee_recipient: Pubkey,
+    /// Fee in basis points taken from the tokens the poster receives when a trade is taken,
+    /// paid into token accounts owned by the PDA derived from `FEE_SEED`
+    pub fee_bps: u16,
+}
+
+/// Most orders a market holds on each side of its book
+pub const MAX_ORDERS: usize = 32;
+
+/// An order book for trading one mint, the base, against another, the quote.
+/// Lives in a PDA derived from `MARKET_SEED` and the two mints, which also has authority over the market's vaults
+#[derive(BorshSerialize, BorshDeserialize, BorshSchema)]
+pub struct Market {
+    pub is_initialized: bool,
+    pub base_mint: Pubkey,
+    pub quote_mint: Pubkey,
+    /// Bump seed of the market's PDA
+    pub bump: u8,
+    /// Vault holding the base tokens of resting asks
+    pub base_vault: Pubkey,
+    /// Vault holding the quote tokens of resting bids
+    pub quote_vault: Pubkey,
+    /// Id given to the next order placed, orders placed earlier have lower ids
+    pub next_order_id: u64,
+    /// Orders to buy base tokens, best price first and earliest first at the same price
+    pub bids: Vec<Order>,
+    /// Orders to sell base tokens, best price first and earliest first at the same price
+    pub asks: Vec<Order>,
+}
+
+#[derive(BorshSerialize, BorshDeserialize, BorshSchema, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Side {
+    Bid,
+    Ask,
+}
+
+/// An order resting in a market's book. Its price is `quote_amount` for `base_amount`.
+/// What it is paid, or refunded when it is taken out of a full book, goes into its owner's claim vaults
+#[derive(BorshSerialize, BorshDeserialize, BorshSchema, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Order {
+    pub id: u64,
+    pub owner: Pubkey,
+    /// Amount of base tokens left to buy or sell
+    pub base_amount: u64,
+    /// Amount of quote tokens left to pay or ask for the rest of the base tokens
+    pub quote_amount: u64,
+    /// Basis-point fee taken from the quote tokens an ask is paid, the config's rate when the order was placed
+    pub fee_bps: u16,
+}
+
+impl Order {
+    pub const LEN: usize = 8 + 32 + 8 + 8 + 2;
+
+    /// Whether the order has a higher price than `other`
+    fn pays_more_than(&self, other: &Order) -> bool {
+        self.quote_amount as u128 * other.base_amount as u128
+            > other.quote_amount as u128 * self.base_amount as u128
+    }
+}
+
+impl Market {
+    /// Space a market account is created with, enough for a full book
+    pub const LEN: usize = 1 + 32 + 32 + 1 + 32 + 32 + 8 + 2 * (4 + MAX_ORDERS * Order::LEN);
+
+    pub fn orders(&self, side: Side) -> &Vec<Order> {
+        match side {
+            Side::Bid => &self.bids,
+            Side::Ask => &self.asks,
+        }
+    }
+
+    pub fn orders_mut(&mut self, side: Side) -> &mut Vec<Order> {
+        match side {
+            Side::Bid => &mut self.bids,
+            Side::Ask => &mut self.asks,
+        }
+    }
+
+    /// Makes room for `order` on a full side of the book by taking out the worst priced order there, as long as
+    /// `order` has a better price. Returns the order taken out
+    pub fn make_room(&mut self, side: Side, order: &Order) -> Option<Order> {
+        let orders = self.orders_mut(side);
+        let worst = orders.last()?;
+        let beats_worst = match side {
+            Side::Bid => order.pays_more_than(worst),
+            Side::Ask => worst.pays_more_than(order),
+        };
+        if orders.len() < MAX_ORDERS || !beats_worst {
+            return None;
+        }
+        orders.pop()
+    }
+
+    /// Adds an order to its side of the book, behind every order at a better or the same price.
+    /// Returns false if that side of the book is full
+    pub fn insert(&mut self, side: Side, order: Order) -> bool {
+        let orders = self.orders_mut(side);
+        if orders.len() >= MAX_ORDERS {
+            return false;
+        }
+        let position = orders
+            .iter()
+            .position(|resting| match side {
+                Side::Bid => order.pays_more_than(resting),
+                Side::Ask => resting.pays_more_than(&order),
+            })
+            .unwrap_or(orders.len());
+        orders.insert(position, order);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dutch_auction_price_holds_outside_the_auction() {
+        let auction = DutchAuction {
+            start_price: 100,
+            end_price: 40,
+            start_time: 10,
+            end_time: 20,
+        };
+        assert_eq!(auction.price(i64::MIN), 100);
+        assert_eq!(auction.price(10), 100);
+        assert_eq!(auction.price(20), 40);
+        assert_eq!(auction.price(i64::MAX), 40);
+    }
+
+    #[test]
+    fn dutch_auction_price_falls_rounded_up() {
+        let auction = DutchAuction {
+            start_price: 10,
+            end_price: 1,
+            start_time: 0,
+            end_time: 4,
+        };
+        assert_eq!(auction.price(1), 8);
+        assert_eq!(auction.price(2), 6);
+        assert_eq!(auction.price(3), 4);
+    }
+
+    #[test]
+    fn nonce_page_records_nonces_independently() {
+        assert_eq!(NoncePage::page(0), 0);
+        assert_eq!(NoncePage::page(NONCES_PER_PAGE - 1), 0);
+        assert_eq!(NoncePage::page(NONCES_PER_PAGE), 1);
+
+        let mut page = NoncePage {
+            is_initialized: true,
+            used: [0; NONCES_PER_PAGE as usize / 64],
+        };
+        for nonce in [63, 64, NONCES_PER_PAGE - 1] {
+            assert!(!page.is_used(nonce));
+            page.mark_used(nonce);
+            assert!(page.is_used(nonce));
+        }
+        assert!(!page.is_used(62) && !page.is_used(65));
+        // pages are looked up by `NoncePage::page`, so the bits only depend on the nonce within its page
+        assert!(page.is_used(NONCES_PER_PAGE + 63));
+    }
 }
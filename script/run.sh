@@ -1,6 +1,6 @@
 set -eu
 
-fee_account="Btun84XLwZEtu4XjHwskq2Xu5qQK5FYw17UDDCfVHTbY"
+fee_account=$(cargo run -- config show | grep "Fee recipient" | awk '{print $3}' | tr -d '\n')
 
 # Minting new tokens
 echo "Minting new tokens"
@@ -39,27 +39,27 @@ echo_balances
 
 # Post trade
 function post_trade() {
-    echo "Posting trade"
-    trade_output=$(cargo run -- post ./alice-keypair.json  $token_x 10 $token_y 11)
+    echo "Posting trade $1"
+    trade_output=$(cargo run -- post ./alice-keypair.json  $token_x 10 $token_y 11 --id $1)
     escrow=$(echo $trade_output | awk '{print $4}' | tr -d '\n')
-    tokens=$(echo $trade_output | awk '{print $8}' | tr -d '\n')
+    tokens=$(echo $trade_output | awk '{print $11}' | tr -d '\n')
     echo "  Tokens account: $tokens"
     echo "  Escrow account: $escrow"
 }
 
-post_trade
+post_trade 1
 echo_balances
 
 # Cancel trade
 echo "Cancelling trade"
-cargo run -- cancel ./alice-keypair.json $escrow
+cargo run -- cancel ./alice-keypair.json $alice/1
 echo_balances
 
 # Post trade again
-post_trade
+post_trade 2
 echo_balances
 
 # Take trade
 echo "Taking trade"
-cargo run -- take ./bob-keypair.json $escrow
+cargo run -- take ./bob-keypair.json $alice/2
 echo_balances
\ No newline at end of file